@@ -0,0 +1,385 @@
+//! OAuth2のリダイレクトを受け取るローカルサーバー
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use google_drive3::yup_oauth2::authenticator_delegate::InstalledFlowDelegate;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+
+/// 認証の開始と終了の通知を受ける
+pub trait AuthListener {
+    /// 認証を開始する
+    fn on_auth_start(&self);
+
+    /// 認証ページの`url`をブラウザで開いてもらう
+    ///
+    /// `on_auth_start`の直後に呼ばれます。既定の実装は何もしません。
+    fn on_auth_url(&self, url: &str) {
+        let _ = url;
+    }
+
+    /// ブラウザからリダイレクトされた、またはタイムアウトした
+    ///
+    /// 成功した場合`error_message`は`None`です。
+    fn on_auth_complete(&self, error_message: Option<String>);
+}
+
+/// ローカルサーバーでOAuth2のリダイレクトを受け取る`InstalledFlowDelegate`
+///
+/// `GDrive::oauth`に渡すと、ブラウザでの認証後に`http://127.0.0.1:<port>/`へリダイレクトされた
+/// 認証コードを受け取ります。ブラウザを開くのは`AuthListener::on_auth_url`の役目です。
+///
+/// # 例
+///
+/// ```skip
+/// use std::time::Duration;
+/// use google_drive::{AuthListener, CustomServerFlowDelegate, GDrive, scope};
+///
+/// struct OpenBrowser;
+///
+/// impl AuthListener for OpenBrowser {
+///     fn on_auth_start(&self) {
+///         println!("ブラウザで認証してください");
+///     }
+///     fn on_auth_url(&self, url: &str) {
+///         let _ = open::that(url);
+///     }
+///     fn on_auth_complete(&self, error_message: Option<String>) {
+///         if let Some(e) = error_message {
+///             eprintln!("認証に失敗しました: {}", e);
+///         }
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let delegate = CustomServerFlowDelegate::new(None, Duration::from_secs(300), OpenBrowser).await?;
+///     let drive = GDrive::oauth(
+///         "client_secret.json",
+///         "./token.json",
+///         &[scope::READONLY],
+///         Some(Box::new(delegate)),
+///     ).await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct CustomServerFlowDelegate<L: AuthListener> {
+    port: u16,
+    redirect_uri: String,
+    server: Mutex<MyServer>,
+    listener: Arc<L>,
+    timeout: Duration,
+}
+
+impl<L: AuthListener> CustomServerFlowDelegate<L> {
+    /// サーバーを起動する
+    ///
+    /// # 引数
+    ///
+    /// * `port` - 待ち受けるポート。`None`なら空いているポートを使う
+    /// * `timeout` - ブラウザからのリダイレクトを待つ時間
+    /// * `listener` - 認証の開始と終了の通知を受けるリスナー
+    pub async fn new(port: Option<u16>, timeout: Duration, listener: L) -> std::io::Result<Self> {
+        let server = MyServer::new(port).await?;
+        let port = server.listener.local_addr()?.port();
+        Ok(Self {
+            port,
+            redirect_uri: format!("http://127.0.0.1:{}", port),
+            server: Mutex::new(server),
+            listener: Arc::new(listener),
+            timeout,
+        })
+    }
+
+    /// 待ち受けているポート
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl<L: AuthListener + Send + Sync> InstalledFlowDelegate for CustomServerFlowDelegate<L> {
+    fn redirect_uri(&self) -> Option<&str> {
+        Some(&self.redirect_uri)
+    }
+
+    fn present_user_url<'a>(
+        &'a self,
+        url: &'a str,
+        _need_code: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>> {
+        Box::pin(async move {
+            let server = self.server.lock().await;
+            self.listener.on_auth_start();
+            self.listener.on_auth_url(url);
+            let result = tokio::time::timeout(self.timeout, server.wait_for_code())
+                .await
+                .unwrap_or_else(|_| Err("timed out waiting for the authorization redirect".into()));
+            self.listener
+                .on_auth_complete(result.as_ref().err().cloned());
+            result
+        })
+    }
+}
+
+/// リダイレクトを受け取るサーバー
+struct MyServer {
+    listener: TcpListener,
+}
+
+impl MyServer {
+    async fn new(port: Option<u16>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port.unwrap_or(0))).await?;
+        Ok(Self { listener })
+    }
+
+    /// `code`か`error`を含むリダイレクトが来るまで待ち、認証コードを返す
+    ///
+    /// `/favicon.ico`などそれ以外のリクエストには 404 を返して待ち続ける。
+    async fn wait_for_code(&self) -> Result<String, String> {
+        loop {
+            let (stream, _) = self.listener.accept().await.map_err(|e| e.to_string())?;
+            if let Ok(Some(result)) = Self::handle_request(stream).await {
+                return result;
+            }
+        }
+    }
+
+    /// 1 つのリクエストを処理する。`code`も`error`も無ければ`None`
+    async fn handle_request(
+        mut stream: TcpStream,
+    ) -> std::io::Result<Option<Result<String, String>>> {
+        // リクエストヘッダー読み込み
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let request = String::from_utf8_lossy(&buf);
+
+        // URLからクエリパラメータ抽出
+        let path_and_query = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("");
+        let query = path_and_query
+            .split_once('?')
+            .map_or("", |(_, query)| query);
+        let result = parse_redirect(query);
+
+        // 確認ページを返信
+        let (status, body) = match &result {
+            Some(Ok(_)) => (
+                "200 OK",
+                page(
+                    "認証完了",
+                    "認証が完了しました",
+                    "このページを閉じて、アプリケーションに戻ってください。",
+                ),
+            ),
+            Some(Err(e)) => (
+                "200 OK",
+                page("認証失敗", "認証に失敗しました", &html_escape(e)),
+            ),
+            None => ("404 Not Found", String::new()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        Ok(result)
+    }
+}
+
+/// リダイレクトのクエリから認証コードまたはエラーを取り出す
+fn parse_redirect(query: &str) -> Option<Result<String, String>> {
+    let mut code = None;
+    let mut error = None;
+    for param in query.split('&') {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = urlencoding::decode(&value.replace('+', " "))
+            .map(|v| v.into_owned())
+            .unwrap_or_else(|_| value.to_string());
+        match key {
+            "code" => code = Some(value),
+            "error" => error = Some(value),
+            _ => {}
+        }
+    }
+    match (code, error) {
+        (_, Some(error)) => Some(Err(error)),
+        (Some(code), None) => Some(Ok(code)),
+        (None, None) => None,
+    }
+}
+
+fn page(title: &str, heading: &str, message: &str) -> String {
+    format!(
+        "<html>\
+         <head><title>{}</title></head>\
+         <body>\
+         <h1>{}</h1>\
+         <p>{}</p>\
+         </body>\
+         </html>",
+        title, heading, message
+    )
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use super::*;
+
+    /// 通知を記録するリスナー
+    #[derive(Default)]
+    struct Recorder {
+        started: StdMutex<Vec<String>>,
+        completed: StdMutex<Vec<Option<String>>>,
+    }
+
+    impl AuthListener for Arc<Recorder> {
+        fn on_auth_start(&self) {
+            self.started.lock().unwrap().push("start".into());
+        }
+
+        fn on_auth_url(&self, url: &str) {
+            self.started.lock().unwrap().push(url.into());
+        }
+
+        fn on_auth_complete(&self, error_message: Option<String>) {
+            self.completed.lock().unwrap().push(error_message);
+        }
+    }
+
+    /// ブラウザの代わりにリダイレクト先へリクエストを送り、レスポンスを返す
+    async fn redirect(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\n\r\n", path, port);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn start(
+        timeout: Duration,
+    ) -> (Arc<CustomServerFlowDelegate<Arc<Recorder>>>, Arc<Recorder>) {
+        let recorder = Arc::new(Recorder::default());
+        let delegate = CustomServerFlowDelegate::new(None, timeout, recorder.clone())
+            .await
+            .unwrap();
+        (Arc::new(delegate), recorder)
+    }
+
+    #[tokio::test]
+    async fn receives_code_from_redirect() {
+        let (delegate, recorder) = start(Duration::from_secs(10)).await;
+        let port = delegate.port();
+        assert_eq!(
+            delegate.redirect_uri(),
+            Some(format!("http://127.0.0.1:{}", port).as_str())
+        );
+
+        let d = delegate.clone();
+        let task =
+            tokio::spawn(async move { d.present_user_url("https://auth.example/", true).await });
+
+        // ブラウザはページと一緒に favicon も要求する
+        let response = redirect(port, "/favicon.ico").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = redirect(port, "/?state=x&code=4%2F0Ab+c&scope=drive").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("認証が完了しました"));
+
+        assert_eq!(task.await.unwrap(), Ok("4/0Ab c".to_string()));
+        assert_eq!(
+            *recorder.started.lock().unwrap(),
+            ["start", "https://auth.example/"]
+        );
+        assert_eq!(*recorder.completed.lock().unwrap(), [None]);
+    }
+
+    #[tokio::test]
+    async fn receives_error_from_redirect() {
+        let (delegate, recorder) = start(Duration::from_secs(10)).await;
+        let port = delegate.port();
+        let d = delegate.clone();
+        let task =
+            tokio::spawn(async move { d.present_user_url("https://auth.example/", true).await });
+
+        let response = redirect(port, "/?error=access_denied").await;
+        assert!(response.contains("認証に失敗しました"));
+        assert!(response.contains("access_denied"));
+
+        assert_eq!(task.await.unwrap(), Err("access_denied".to_string()));
+        assert_eq!(
+            *recorder.completed.lock().unwrap(),
+            [Some("access_denied".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_without_redirect() {
+        let (delegate, recorder) = start(Duration::from_millis(50)).await;
+        let result = delegate
+            .present_user_url("https://auth.example/", true)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(recorder.completed.lock().unwrap().len(), 1);
+        assert!(recorder.completed.lock().unwrap()[0].is_some());
+    }
+
+    #[tokio::test]
+    async fn uses_fixed_port() {
+        // 空いているポートを探してから、そのポートを指定する
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let recorder = Arc::new(Recorder::default());
+        let delegate = CustomServerFlowDelegate::new(Some(port), Duration::from_secs(1), recorder)
+            .await
+            .unwrap();
+        assert_eq!(delegate.port(), port);
+    }
+
+    #[test]
+    fn parse_redirect_query() {
+        assert_eq!(parse_redirect("code=abc"), Some(Ok("abc".into())));
+        assert_eq!(
+            parse_redirect("error=access_denied&code=abc"),
+            Some(Err("access_denied".into()))
+        );
+        assert_eq!(parse_redirect(""), None);
+        assert_eq!(parse_redirect("state=1"), None);
+    }
+}
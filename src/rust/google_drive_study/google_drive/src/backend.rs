@@ -0,0 +1,232 @@
+//! Google Driveの操作を差し替えるためのトレイト
+
+use std::{future::Future, path::Path};
+
+use crate::{
+    DownloadHandler, DownloadOptions, Error, ExportFormat, GDrive, GDriveId, GMeta, UploadTarget,
+    download::partial_path,
+};
+
+/// 一覧の取得、メタデータの取得、ダウンロード、エクスポート、アップロードを行うバックエンド
+///
+/// [`GDrive`]と、テスト用にメモリ上でファイルを管理する[`MemoryDrive`](crate::MemoryDrive)が実装しています。
+/// このトレイトを使って書いた処理（[`mirror`](crate::mirror)など）は、
+/// Googleの認証情報が無くても`MemoryDrive`でテストできます。
+///
+/// # 例
+///
+/// ```
+/// use google_drive::{Backend, Error, GDriveId, MemoryDrive};
+///
+/// /// フォルダ直下のファイルの合計サイズ
+/// async fn total_size<B: Backend>(drive: &B, folder: &GDriveId) -> Result<u64, Error> {
+///     let items = drive.list(folder).await?;
+///     Ok(items.iter().filter_map(|m| m.size).sum())
+/// }
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let drive = MemoryDrive::new();
+/// drive.add_file(&drive.root(), "a.txt", "text/plain", "hello");
+/// drive.add_file(&drive.root(), "b.txt", "text/plain", "world!");
+/// assert_eq!(total_size(&drive, &drive.root()).await.unwrap(), 11);
+/// # });
+/// ```
+pub trait Backend: Send + Sync {
+    /// フォルダの直下にあり、ゴミ箱に無いアイテムの一覧を全て取得する
+    ///
+    /// ダウンロードに必要なフィールド（サイズ、更新日時、チェックサムなど）を含みます。
+    fn list(&self, folder: &GDriveId) -> impl Future<Output = Result<Vec<GMeta>, Error>> + Send;
+
+    /// ダウンロードに必要なフィールドを含むメタデータを取得する
+    fn get_meta(&self, id: &GDriveId) -> impl Future<Output = Result<GMeta, Error>> + Send;
+
+    /// ファイルの内容をダウンロードし、ハンドラで受け取る
+    fn download<H>(
+        &self,
+        id: &GDriveId,
+        handler: H,
+    ) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        H: DownloadHandler + Sync + Send + 'static;
+
+    /// Google Apps形式のファイルを`mime_type`でエクスポートし、ハンドラで受け取る
+    fn export<H>(
+        &self,
+        id: &GDriveId,
+        mime_type: &str,
+        handler: H,
+    ) -> impl Future<Output = Result<(), Error>> + Send
+    where
+        H: DownloadHandler + Sync + Send + 'static;
+
+    /// データをアップロードし、作成または更新したファイルのメタデータを返す
+    fn upload(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> impl Future<Output = Result<GMeta, Error>> + Send;
+
+    /// ファイルをダウンロードして`path`に保存する
+    ///
+    /// 既定の実装は`<path>.part`に書き込み、完了してから`path`に名前を変更します。
+    fn save(&self, meta: &GMeta, path: &Path) -> impl Future<Output = Result<(), Error>> + Send {
+        async move {
+            let part = partial_path(path);
+            let handler = GDrive::file_handler(&part).await?;
+            if let Err(e) = self.download(&meta.id, handler).await {
+                let _ = tokio::fs::remove_file(&part).await;
+                return Err(e);
+            }
+            tokio::fs::rename(&part, path).await?;
+            Ok(())
+        }
+    }
+}
+
+impl Backend for GDrive {
+    async fn list(&self, folder: &GDriveId) -> Result<Vec<GMeta>, Error> {
+        self.list_with_fields(folder, self.meta_fields.for_transfer())
+            .await
+    }
+
+    async fn get_meta(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.transfer_meta(id).await
+    }
+
+    async fn download<H>(&self, id: &GDriveId, handler: H) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        GDrive::download(self, id, handler).await
+    }
+
+    async fn export<H>(&self, id: &GDriveId, mime_type: &str, handler: H) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        GDrive::export(self, id, mime_type, handler).await
+    }
+
+    async fn upload(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> Result<GMeta, Error> {
+        GDrive::upload(self, target, mime_type, data).await
+    }
+
+    /// 中断からの再開とチェックサムの検証を行う[`GDrive::download_to_file`]と同じ方法で保存する
+    async fn save(&self, meta: &GMeta, path: &Path) -> Result<(), Error> {
+        self.save_media(meta, path, &DownloadOptions::default())
+            .await
+    }
+}
+
+/// [`GDrive::download_and_save`]と同じく、`backend`のファイルをダウンロードして`file`に保存する
+///
+/// Google Apps形式のファイルは[`GMeta::default_export`]の形式でエクスポートします。
+pub async fn download_and_save<B: Backend, P: AsRef<Path>>(
+    backend: &B,
+    id: &GDriveId,
+    file: P,
+) -> Result<(), Error> {
+    let meta = backend.get_meta(id).await?;
+    if meta.is_directory() {
+        return Err(Error::DirectoryDownloadError(meta.id));
+    }
+    if meta.is_google_app_file() {
+        let Some(format) = meta.default_export() else {
+            return Err(Error::NotExportable(meta.mime_type));
+        };
+        return export_and_save(backend, id, format, file).await;
+    }
+    backend.save(&meta, file.as_ref()).await
+}
+
+/// [`GDrive::export_and_save`]と同じく、`backend`のファイルをエクスポートして`file`に保存する
+pub async fn export_and_save<B: Backend, P: AsRef<Path>>(
+    backend: &B,
+    id: &GDriveId,
+    format: ExportFormat,
+    file: P,
+) -> Result<(), Error> {
+    let file = file.as_ref();
+    let part = partial_path(file);
+    let handler = GDrive::file_handler(&part).await?;
+    if let Err(e) = backend.export(id, format.mime_type(), handler).await {
+        let _ = tokio::fs::remove_file(&part).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&part, file).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryDrive;
+
+    #[tokio::test]
+    async fn saves_files_and_exports_google_apps_files() {
+        let drive = MemoryDrive::new();
+        let file = drive.add_file(&drive.root(), "a.txt", "text/plain", "hello");
+        let doc = drive.add_file(
+            &drive.root(),
+            "doc",
+            "application/vnd.google-apps.document",
+            "# doc",
+        );
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("sub/a.txt");
+        download_and_save(&drive, &file, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+        // Google ドキュメントは既定の形式でエクスポートする
+        let path = dir.path().join("doc.docx");
+        download_and_save(&drive, &doc, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"# doc");
+
+        let path = dir.path().join("doc.md");
+        export_and_save(&drive, &doc, ExportFormat::Markdown, &path)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"# doc");
+    }
+
+    #[tokio::test]
+    async fn leaves_nothing_behind_on_failure() {
+        let drive = MemoryDrive::new();
+        let folder = drive.add_folder(&drive.root(), "folder");
+        let file = drive.add_file(&drive.root(), "a.txt", "text/plain", "hello");
+        let doc = drive.add_file(
+            &drive.root(),
+            "doc",
+            "application/vnd.google-apps.document",
+            "# doc",
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+
+        let e = download_and_save(&drive, &folder, &path).await.unwrap_err();
+        assert!(matches!(e, Error::DirectoryDownloadError(_)), "{:?}", e);
+
+        let e = export_and_save(&drive, &doc, ExportFormat::Xlsx, &path)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotExportable(_)), "{:?}", e);
+
+        drive.set_can_download(&file, false);
+        let e = download_and_save(&drive, &file, &path).await.unwrap_err();
+        assert!(matches!(e, Error::PermissionDenied(_)), "{:?}", e);
+
+        let e = download_and_save(&drive, &"missing".into(), &path)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
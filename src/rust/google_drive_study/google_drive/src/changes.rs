@@ -0,0 +1,422 @@
+//! Changes APIによる変更の追跡
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+
+use crate::{Error, GDrive, GDriveId, GMeta};
+
+/// ファイルの変更
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// 前回の確認以降に作成された
+    Added(GMeta),
+    /// 内容やメタデータが変更された
+    Modified(GMeta),
+    /// ゴミ箱に移動された
+    Trashed(GMeta),
+    /// 完全に削除された、またはアクセスできなくなった
+    Removed(GDriveId),
+}
+
+impl Change {
+    /// 変更されたファイルのID
+    pub fn id(&self) -> &GDriveId {
+        match self {
+            Change::Added(meta) | Change::Modified(meta) | Change::Trashed(meta) => &meta.id,
+            Change::Removed(id) => id,
+        }
+    }
+
+    /// 変更後のメタデータ。`Removed`には無い
+    pub fn meta(&self) -> Option<&GMeta> {
+        match self {
+            Change::Added(meta) | Change::Modified(meta) | Change::Trashed(meta) => Some(meta),
+            Change::Removed(_) => None,
+        }
+    }
+
+    /// APIの変更を解釈する。共有ドライブ自体の変更は`None`
+    ///
+    /// `checked_at`より後に作成されたファイルを`Added`とする。
+    fn new(
+        change: google_drive3::api::Change,
+        checked_at: DateTime<Utc>,
+    ) -> Option<Result<Self, Error>> {
+        if change.change_type.as_deref().is_some_and(|t| t != "file") {
+            return None;
+        }
+        let id = change.file_id?;
+        let file = match change.file {
+            Some(file) if change.removed != Some(true) => file,
+            _ => return Some(Ok(Change::Removed(id.into()))),
+        };
+        Some(GMeta::new(file).map(|meta| {
+            if meta.trashed == Some(true) {
+                Change::Trashed(meta)
+            } else if meta.created_time.is_some_and(|t| t > checked_at) {
+                Change::Added(meta)
+            } else {
+                Change::Modified(meta)
+            }
+        }))
+    }
+}
+
+/// 次に変更を取得する位置
+///
+/// [`GDrive::persistent_change_cursor`]で作成すると、[`GDrive::changes`]で変更を取得するたびに
+/// ファイルに保存されます。次に起動した時は、前回の続きから変更だけを取得できます。
+#[derive(Debug, Clone)]
+pub struct ChangeCursor {
+    page_token: String,
+    checked_at: DateTime<Utc>,
+    path: Option<PathBuf>,
+}
+
+/// 保存するカーソルの内容
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    page_token: String,
+    checked_at: String,
+}
+
+impl ChangeCursor {
+    /// 保存したページトークンから作成する。`checked_at`はそのトークンを取得した日時
+    pub fn new<S: Into<String>>(page_token: S, checked_at: DateTime<Utc>) -> Self {
+        Self {
+            page_token: page_token.into(),
+            checked_at,
+            path: None,
+        }
+    }
+
+    /// 次に取得するページトークン
+    pub fn page_token(&self) -> &str {
+        &self.page_token
+    }
+
+    /// 最後に変更を確認した日時
+    pub fn checked_at(&self) -> DateTime<Utc> {
+        self.checked_at
+    }
+
+    async fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let saved: Saved = serde_json::from_slice(&data)?;
+        let checked_at = DateTime::parse_from_rfc3339(&saved.checked_at)
+            .map_err(<serde_json::Error as serde::de::Error>::custom)?
+            .into();
+        Ok(Some(Self {
+            page_token: saved.page_token,
+            checked_at,
+            path: Some(path.into()),
+        }))
+    }
+
+    /// ファイルに保存する。途中で中断しても壊れないように、一時ファイルに書いてから名前を変更する
+    async fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved = Saved {
+            page_token: self.page_token.clone(),
+            checked_at: self.checked_at.to_rfc3339(),
+        };
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&saved)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+impl GDrive {
+    /// 現在の位置を表すページトークンを取得する（`changes.getStartPageToken`）
+    pub async fn start_page_token(&self) -> Result<String, Error> {
+        let (_, token) = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .changes()
+                    .get_start_page_token()
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        token
+            .start_page_token
+            .ok_or(Error::MetaIsNull("startPageToken"))
+    }
+
+    /// 現在の位置から変更を追跡するカーソルを作成する
+    pub async fn change_cursor(&self) -> Result<ChangeCursor, Error> {
+        let checked_at = Utc::now();
+        let token = self.start_page_token().await?;
+        Ok(ChangeCursor::new(token, checked_at))
+    }
+
+    /// `path`に保存するカーソルを作成する
+    ///
+    /// `path`があればその位置から、無ければ現在の位置から追跡します。
+    pub async fn persistent_change_cursor<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<ChangeCursor, Error> {
+        let path = path.as_ref();
+        if let Some(cursor) = ChangeCursor::load(path).await? {
+            return Ok(cursor);
+        }
+        let mut cursor = self.change_cursor().await?;
+        cursor.path = Some(path.into());
+        cursor.save().await?;
+        Ok(cursor)
+    }
+
+    /// `cursor`以降の変更を取得し、`cursor`を進める（`changes.list`）
+    ///
+    /// 全てのページを取得し終えてから`cursor`を進めるため、失敗した場合は同じ位置から取得し直せます。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{Change, GDrive, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///
+    ///     // cronで実行するたびに、前回からの変更だけを表示する
+    ///     let mut cursor = drive.persistent_change_cursor("./changes.json").await?;
+    ///     for change in drive.changes(&mut cursor).await? {
+    ///         match change {
+    ///             Change::Added(meta) => println!("追加: {}", meta.name),
+    ///             Change::Modified(meta) => println!("変更: {}", meta.name),
+    ///             Change::Trashed(meta) => println!("ゴミ箱: {}", meta.name),
+    ///             Change::Removed(id) => println!("削除: {}", id),
+    ///         }
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn changes(&self, cursor: &mut ChangeCursor) -> Result<Vec<Change>, Error> {
+        // `Added`の判定に作成日時を使う
+        let mut file_fields = self.meta_fields.for_transfer().fields().to_string();
+        if !file_fields.contains("createdTime") {
+            file_fields.push_str(",createdTime");
+        }
+        let fields = format!(
+            "nextPageToken,newStartPageToken,\
+             changes(changeType,removed,fileId,file({}))",
+            file_fields
+        );
+        let checked_at = Utc::now();
+        let mut result = Vec::new();
+        let mut page_token = cursor.page_token.clone();
+        loop {
+            let (_, list) = self
+                .retry(|| async {
+                    Ok(self
+                        .hub
+                        .changes()
+                        .list(&page_token)
+                        .param("fields", &fields)
+                        .page_size(1000)
+                        .include_items_from_all_drives(true)
+                        .supports_all_drives(true)
+                        .add_scopes(&self.scopes)
+                        .doit()
+                        .await?)
+                })
+                .await?;
+            for change in list.changes.unwrap_or_default() {
+                if let Some(change) = Change::new(change, cursor.checked_at) {
+                    result.push(change?);
+                }
+            }
+            match (list.next_page_token, list.new_start_page_token) {
+                (Some(next), _) => page_token = next,
+                (None, Some(new_start)) => {
+                    cursor.page_token = new_start;
+                    cursor.checked_at = checked_at;
+                    cursor.save().await?;
+                    return Ok(result);
+                }
+                (None, None) => return Err(Error::MetaIsNull("newStartPageToken")),
+            }
+        }
+    }
+
+    /// `interval`ごとに変更を確認し、見つかった変更をチャンネルに送る
+    ///
+    /// 受信側を閉じると確認をやめます。取得に失敗した場合はエラーを送り、次の確認で同じ位置から取得し直します。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use std::{sync::Arc, time::Duration};
+    /// use google_drive::{GDrive, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = Arc::new(GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?);
+    ///     let cursor = drive.persistent_change_cursor("./changes.json").await?;
+    ///
+    ///     let mut rx = drive.watch(cursor, Duration::from_secs(60));
+    ///     while let Some(change) = rx.recv().await {
+    ///         println!("{:?}", change?);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn watch(
+        self: Arc<Self>,
+        mut cursor: ChangeCursor,
+        interval: Duration,
+    ) -> mpsc::Receiver<Result<Change, Error>> {
+        let (tx, rx) = mpsc::channel(256);
+        tokio::spawn(async move {
+            loop {
+                match self.changes(&mut cursor).await {
+                    Ok(changes) => {
+                        for change in changes {
+                            if tx.send(Ok(change)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        if tx.send(Err(e)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    /// トークン 10 から 11、11 から新しい開始トークン 12 まで変更を返すサーバー
+    async fn changes_server() -> TestServer {
+        let polls = Mutex::new(0);
+        TestServer::start(move |request| {
+            if request.path == "/drive/v3/changes/startPageToken" {
+                return test_server::Response::json(200, json!({"startPageToken": "10"}));
+            }
+            let file = |id: &str, created: &str, trashed: bool| {
+                json!({
+                    "id": id, "name": format!("{}.txt", id), "mimeType": "text/plain",
+                    "createdTime": created, "trashed": trashed,
+                })
+            };
+            let body = match request.query("pageToken") {
+                Some("10") => json!({
+                    "nextPageToken": "11",
+                    "changes": [
+                        {"changeType": "file", "fileId": "old", "file": file("old", "2000-01-01T00:00:00Z", false)},
+                        {"changeType": "file", "fileId": "new", "file": file("new", "2999-01-01T00:00:00Z", false)},
+                        {"changeType": "drive", "driveId": "team"},
+                    ],
+                }),
+                Some("11") => json!({
+                    "newStartPageToken": "12",
+                    "changes": [
+                        {"changeType": "file", "fileId": "bin", "file": file("bin", "2000-01-01T00:00:00Z", true)},
+                        {"changeType": "file", "fileId": "gone", "removed": true},
+                    ],
+                }),
+                Some(_) => {
+                    let mut polls = polls.lock().unwrap();
+                    *polls += 1;
+                    let changes = if *polls == 2 {
+                        json!([{"changeType": "file", "fileId": "later", "file": file("later", "2000-01-01T00:00:00Z", false)}])
+                    } else {
+                        json!([])
+                    };
+                    json!({"newStartPageToken": "12", "changes": changes})
+                }
+                None => json!({}),
+            };
+            test_server::Response::json(200, body)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn lists_typed_changes_across_pages() {
+        let server = changes_server().await;
+        let drive = server.drive();
+
+        let mut cursor = drive.change_cursor().await.unwrap();
+        assert_eq!(cursor.page_token(), "10");
+        let changes = drive.changes(&mut cursor).await.unwrap();
+        assert!(matches!(&changes[0], Change::Modified(m) if m.id == "old".into()));
+        assert!(matches!(&changes[1], Change::Added(m) if m.id == "new".into()));
+        assert!(matches!(&changes[2], Change::Trashed(m) if m.id == "bin".into()));
+        assert!(matches!(&changes[3], Change::Removed(id) if *id == "gone".into()));
+        assert_eq!(changes.len(), 4);
+        assert_eq!(cursor.page_token(), "12");
+    }
+
+    #[tokio::test]
+    async fn persists_page_token() {
+        let server = changes_server().await;
+        let drive = server.drive();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("changes.json");
+
+        let mut cursor = drive.persistent_change_cursor(&path).await.unwrap();
+        assert_eq!(cursor.page_token(), "10");
+        drive.changes(&mut cursor).await.unwrap();
+
+        // 次に起動した時は保存した位置から再開する
+        let cursor = drive.persistent_change_cursor(&path).await.unwrap();
+        assert_eq!(cursor.page_token(), "12");
+        let starts = server
+            .requests()
+            .iter()
+            .filter(|r| r.path == "/drive/v3/changes/startPageToken")
+            .count();
+        assert_eq!(starts, 1);
+    }
+
+    #[tokio::test]
+    async fn watch_delivers_changes_through_channel() {
+        let server = changes_server().await;
+        let drive = Arc::new(server.drive());
+
+        let cursor = ChangeCursor::new("12", Utc::now());
+        let mut rx = drive.watch(cursor, Duration::from_millis(10));
+        let change = rx.recv().await.unwrap().unwrap();
+        assert_eq!(change.id(), &"later".into());
+        drop(rx);
+        assert!(server.requests().len() >= 2);
+    }
+}
@@ -0,0 +1,189 @@
+//! ダウンロードしたデータのチェックサムの検証
+
+use std::{path::Path, sync::Mutex};
+
+use google_drive3::hyper::body::Bytes;
+use md5::{Md5, digest::DynDigest};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::{DownloadHandler, Error, GDriveId, GMeta};
+
+/// 1 つのアルゴリズムで計算中のハッシュと期待する値
+pub(crate) struct Checksum {
+    id: GDriveId,
+    algorithm: &'static str,
+    expected: String,
+    digest: Box<dyn DynDigest + Send>,
+}
+
+impl Checksum {
+    /// メタデータにあるチェックサムのうち、最も強いものを計算する。無ければ`None`
+    pub(crate) fn new(meta: &GMeta) -> Option<Self> {
+        let (algorithm, expected, digest): (_, _, Box<dyn DynDigest + Send>) =
+            if let Some(sha256) = &meta.sha256_checksum {
+                ("sha256", sha256, Box::new(Sha256::default()))
+            } else if let Some(sha1) = &meta.sha1_checksum {
+                ("sha1", sha1, Box::new(Sha1::default()))
+            } else if let Some(md5) = &meta.md5_checksum {
+                ("md5", md5, Box::new(Md5::default()))
+            } else {
+                return None;
+            };
+        Some(Self::from_parts(&meta.id, algorithm, expected, digest))
+    }
+
+    /// MD5 のチェックサムを計算する。`md5`が`None`なら`None`
+    pub(crate) fn from_md5(id: &GDriveId, md5: Option<&str>) -> Option<Self> {
+        md5.map(|md5| Self::from_parts(id, "md5", md5, Box::new(Md5::default())))
+    }
+
+    fn from_parts(
+        id: &GDriveId,
+        algorithm: &'static str,
+        expected: &str,
+        digest: Box<dyn DynDigest + Send>,
+    ) -> Self {
+        Self {
+            id: id.clone(),
+            algorithm,
+            expected: expected.to_ascii_lowercase(),
+            digest,
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    /// ファイルの内容を加える
+    pub(crate) async fn update_file(&mut self, path: &Path) -> std::io::Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.update(&buf[..n]);
+        }
+    }
+
+    /// 計算したハッシュが期待する値と一致するか確かめる
+    pub(crate) fn verify(self) -> Result<(), Error> {
+        let actual: String = self
+            .digest
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if actual == self.expected {
+            Ok(())
+        } else {
+            Err(Error::ChecksumMismatch {
+                id: self.id,
+                algorithm: self.algorithm,
+                expected: self.expected,
+                actual,
+            })
+        }
+    }
+}
+
+/// 受け取ったデータのハッシュを計算してから`inner`に渡すハンドラ
+pub(crate) struct Hashing<H> {
+    inner: H,
+    checksum: Mutex<Option<Checksum>>,
+}
+
+impl<H: DownloadHandler> Hashing<H> {
+    pub(crate) fn new(inner: H, checksum: Option<Checksum>) -> Self {
+        Self {
+            inner,
+            checksum: Mutex::new(checksum),
+        }
+    }
+
+    /// 受け取ったデータのハッシュを検証する。チェックサムが無ければ何もしない
+    pub(crate) fn verify(&self) -> Result<(), Error> {
+        match self.checksum.lock().unwrap().take() {
+            Some(checksum) => checksum.verify(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<H: DownloadHandler + Sync> DownloadHandler for Hashing<H> {
+    async fn set_size(&self, size: usize) -> Result<(), Error> {
+        self.inner.set_size(size).await
+    }
+
+    async fn write(&self, b: Bytes) -> Result<(), Error> {
+        if let Some(checksum) = self.checksum.lock().unwrap().as_mut() {
+            checksum.update(&b);
+        }
+        self.inner.write(b).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(md5: Option<&str>, sha1: Option<&str>, sha256: Option<&str>) -> GMeta {
+        GMeta {
+            id: "file-id".into(),
+            name: "a.txt".into(),
+            mime_type: "text/plain".into(),
+            md5_checksum: md5.map(Into::into),
+            sha1_checksum: sha1.map(Into::into),
+            sha256_checksum: sha256.map(Into::into),
+            size: Some(11),
+            ..Default::default()
+        }
+    }
+
+    // "hello world"
+    const MD5: &str = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+    const SHA1: &str = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+    const SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    fn check(meta: &GMeta, data: &[u8]) -> Result<(), Error> {
+        let mut checksum = Checksum::new(meta).unwrap();
+        checksum.update(data);
+        checksum.verify()
+    }
+
+    #[test]
+    fn uses_strongest_checksum() {
+        let m = meta(Some(MD5), Some(SHA1), Some(SHA256));
+        assert_eq!(Checksum::new(&m).unwrap().algorithm, "sha256");
+        check(&m, b"hello world").unwrap();
+
+        let m = meta(Some(MD5), Some(SHA1), None);
+        assert_eq!(Checksum::new(&m).unwrap().algorithm, "sha1");
+        check(&m, b"hello world").unwrap();
+
+        let m = meta(Some(&MD5.to_ascii_uppercase()), None, None);
+        check(&m, b"hello world").unwrap();
+
+        assert!(Checksum::new(&meta(None, None, None)).is_none());
+    }
+
+    #[test]
+    fn detects_mismatch() {
+        let m = meta(Some(MD5), None, None);
+        let Err(Error::ChecksumMismatch {
+            algorithm,
+            expected,
+            ..
+        }) = check(&m, b"hello World")
+        else {
+            panic!("checksum should not match");
+        };
+        assert_eq!(algorithm, "md5");
+        assert_eq!(expected, MD5);
+    }
+}
@@ -0,0 +1,569 @@
+//! `Range`を使った再開可能なダウンロードと並列ダウンロード
+//!
+//! `DriveHub`のダウンロードは`Range`ヘッダーを付けられないため、
+//! `alt=media`のリクエストを直接組み立てて送ります。
+
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use futures::{StreamExt, stream};
+use google_drive3::{
+    common,
+    hyper::{Request, StatusCode, body::Bytes, header::RANGE},
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::{
+    DownloadHandler, Error, GDrive, GDriveId, GMeta,
+    checksum::{Checksum, Hashing},
+};
+
+/// 並列ダウンロードで 1 回に取得する既定のバイト数
+pub const DEFAULT_CHUNK_SIZE: u64 = 32 << 20;
+
+/// `GDrive::download_to_file`のオプション
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 途中まで保存されたファイル（`<保存先>.part`）があれば続きからダウンロードする
+    ///
+    /// 途中のファイルがリモートの更新日時より古い場合は最初からダウンロードします。
+    pub resume: bool,
+    /// 同時に取得する範囲の数。2 以上で、ファイルが`chunk_size`より大きければ並列にダウンロードする
+    ///
+    /// 並列にダウンロードした場合、途中のファイルからは再開しません。
+    pub concurrency: usize,
+    /// 並列ダウンロードで 1 回に取得するバイト数
+    pub chunk_size: u64,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            resume: true,
+            concurrency: 1,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// 受け取ったデータをファイルの現在の位置から書き込むハンドラ
+pub(crate) struct FileWriter(tokio::sync::Mutex<tokio::fs::File>);
+
+impl FileWriter {
+    pub(crate) fn new(file: tokio::fs::File) -> Self {
+        Self(tokio::sync::Mutex::new(file))
+    }
+}
+
+impl DownloadHandler for FileWriter {
+    async fn set_size(&self, _size: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn write(&self, b: Bytes) -> Result<(), Error> {
+        let mut f = self.0.lock().await;
+        f.write_all(&b).await?;
+        // 書き込みの完了を待つ。待たないと戻った後に書き込まれることがある
+        f.flush().await?;
+        Ok(())
+    }
+}
+
+impl GDrive {
+    /// ファイルをダウンロードして指定したパスに保存する
+    ///
+    /// `<file>.part`にダウンロードし、完了したら`file`に名前を変更します。
+    /// 接続が切れた場合は受信済みの位置から`Range`で再開し、
+    /// `opts.resume`なら前回中断した`<file>.part`の続きからダウンロードします。
+    /// Google Apps形式のファイルは[`GMeta::default_export`]の形式でエクスポートします（再開はしません）。
+    ///
+    /// # 引数
+    ///
+    /// * `id` - ダウンロードするファイルのID
+    /// * `file` - 保存先のファイルパス
+    /// * `opts` - オプション
+    ///
+    /// # 戻り値
+    ///
+    /// ダウンロードしたファイルのメタデータ
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{DownloadOptions, GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let file_id: GDriveId = "your_file_id".into();
+    ///
+    ///     // 64MiBずつ 4 並列でダウンロードする
+    ///     let opts = DownloadOptions {
+    ///         concurrency: 4,
+    ///         chunk_size: 64 << 20,
+    ///         ..Default::default()
+    ///     };
+    ///     drive.download_to_file(&file_id, "./downloads/large.iso", &opts).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn download_to_file<P: AsRef<Path>>(
+        &self,
+        id: &GDriveId,
+        file: P,
+        opts: &DownloadOptions,
+    ) -> Result<GMeta, Error> {
+        let meta = self.transfer_meta(id).await?;
+        if meta.is_directory() {
+            return Err(Error::DirectoryDownloadError(meta.id));
+        }
+        if meta.is_google_app_file() {
+            let Some(format) = meta.default_export() else {
+                return Err(Error::NotExportable(meta.mime_type));
+            };
+            self.export_and_save(id, format, file).await?;
+        } else {
+            self.save_media(&meta, file.as_ref(), opts).await?;
+        }
+        Ok(meta)
+    }
+
+    /// Google Apps形式以外のファイルを`<file>.part`にダウンロードしてから`file`に名前を変更する
+    ///
+    /// チェックサムが一致しない場合は`<file>.part`を削除し、`file`は変更しない。
+    pub(crate) async fn save_media(
+        &self,
+        meta: &GMeta,
+        file: &Path,
+        opts: &DownloadOptions,
+    ) -> Result<(), Error> {
+        if let Some(dir) = file.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let part = partial_path(file);
+        let verified = match meta.size {
+            Some(size) if opts.concurrency > 1 && size > opts.chunk_size => {
+                self.download_parallel(&meta.id, &part, size, opts).await?;
+                // 並列に書き込んだ範囲は順番に届かないため、書き終わったファイルから計算する
+                match Checksum::new(meta) {
+                    Some(mut checksum) => {
+                        checksum.update_file(&part).await?;
+                        checksum.verify()
+                    }
+                    None => Ok(()),
+                }
+            }
+            size => {
+                let start = if opts.resume {
+                    resumable_len(&part, meta).await
+                } else {
+                    0
+                };
+                let mut checksum = Checksum::new(meta);
+                let f = if start > 0 {
+                    if let Some(checksum) = &mut checksum {
+                        checksum.update_file(&part).await?;
+                    }
+                    tokio::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&part)
+                        .await?
+                } else {
+                    tokio::fs::File::create(&part).await?
+                };
+                let handler = Arc::new(Hashing::new(FileWriter::new(f), checksum));
+                self.download_range(&meta.id, start, size, handler.clone())
+                    .await?;
+                handler.verify()
+            }
+        };
+        if let Err(e) = verified {
+            // 壊れた内容から再開しないように削除する
+            let _ = tokio::fs::remove_file(&part).await;
+            return Err(e);
+        }
+        tokio::fs::rename(&part, file).await?;
+        Ok(())
+    }
+
+    /// `size`バイトのファイルを`opts.chunk_size`ずつ並列にダウンロードし、`part`のそれぞれの位置に書き込む
+    async fn download_parallel(
+        &self,
+        id: &GDriveId,
+        part: &Path,
+        size: u64,
+        opts: &DownloadOptions,
+    ) -> Result<(), Error> {
+        tokio::fs::File::create(part).await?.set_len(size).await?;
+        let chunk = opts.chunk_size.max(1);
+        let ranges = (0..size)
+            .step_by(chunk as usize)
+            .map(|start| (start, (start + chunk).min(size)));
+        let results: Vec<_> = stream::iter(ranges)
+            .map(|(start, end)| async move {
+                let mut f = tokio::fs::OpenOptions::new().write(true).open(part).await?;
+                f.seek(SeekFrom::Start(start)).await?;
+                let handler = Arc::new(FileWriter::new(f));
+                self.download_range(id, start, Some(end), handler).await
+            })
+            .buffer_unordered(opts.concurrency)
+            .collect()
+            .await;
+        results.into_iter().collect()
+    }
+
+    /// `start`バイト目から`end`バイト目の手前までをハンドラに渡す。`end`が`None`なら最後まで
+    ///
+    /// 受信中に接続が切れた場合は、受信済みの位置から`Range`で要求し直す。
+    pub(crate) async fn download_range<H>(
+        &self,
+        id: &GDriveId,
+        mut start: u64,
+        end: Option<u64>,
+        handler: Arc<H>,
+    ) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let mut attempt = 1;
+        loop {
+            if end.is_some_and(|end| start >= end) {
+                return Ok(());
+            }
+            let rsp = self.request_media(id, start, end).await?;
+            // 最初から要求した場合は、Rangeが無視されて全体が返ってきてもよい
+            let status = rsp.status();
+            if status != StatusCode::PARTIAL_CONTENT && !(start == 0 && status == StatusCode::OK) {
+                return Err(Error::InvalidResponse(status.as_u16()));
+            }
+            let (received, result) = Self::receive(rsp, handler.clone()).await;
+            start += received;
+            match result {
+                Err(Error::DownloadError(_)) if end.is_some_and(|end| start >= end) => {
+                    return Ok(());
+                }
+                Err(Error::DownloadError(_)) if attempt < self.retry.max_attempts => {
+                    // 少しでも受信できていれば、続けて失敗した回数を数え直す
+                    attempt = if received > 0 { 1 } else { attempt + 1 };
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// ファイルの内容を要求する。`start`以降を要求する場合や`end`がある場合は`Range`を付ける
+    async fn request_media(
+        &self,
+        id: &GDriveId,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<common::Response, Error> {
+        let url = format!(
+            "{}drive/v3/files/{}?alt=media&supportsAllDrives=true",
+            self.root_url,
+            urlencoding::encode(id.as_ref())
+        );
+        self.retry(|| {
+            let request = Request::get(&url);
+            let request = match (start, end) {
+                (0, None) => request,
+                (start, None) => request.header(RANGE, format!("bytes={}-", start)),
+                (start, Some(end)) => request.header(RANGE, format!("bytes={}-{}", start, end - 1)),
+            };
+            self.send_streaming(request)
+        })
+        .await
+    }
+}
+
+/// ダウンロード中のファイルのパス
+pub(crate) fn partial_path(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    file.with_file_name(name)
+}
+
+/// 途中までダウンロードしたファイルの続きから再開できるなら、その長さを返す
+///
+/// リモートのファイルが途中のファイルより後に更新されている場合や、
+/// リモートより大きい場合は再開できないため 0 を返す。
+async fn resumable_len(part: &Path, meta: &GMeta) -> u64 {
+    let Ok(local) = tokio::fs::metadata(part).await else {
+        return 0;
+    };
+    let modified = local.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    match meta.modified_time {
+        Some(remote) if modified >= SystemTime::from(remote) => {}
+        _ => return 0,
+    }
+    match meta.size {
+        Some(size) if local.len() <= size => local.len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    fn content() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// メタデータと、`Range`に対応した内容を返すサーバー
+    ///
+    /// `drop_after`バイト送ったところで 1 回だけ接続を切る。
+    async fn media_server(modified: &str, drop_after: Option<usize>) -> TestServer {
+        use md5::{Digest, Md5};
+
+        let md5: String = Md5::digest(content())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        serve(modified, &md5, drop_after).await
+    }
+
+    /// メタデータのチェックサムを`md5`にして内容を返すサーバー
+    async fn serve(modified: &str, md5: &str, drop_after: Option<usize>) -> TestServer {
+        let content = content();
+        let meta = serde_json::json!({
+            "id": "file-id",
+            "name": "data.bin",
+            "mimeType": "application/octet-stream",
+            "modifiedTime": modified,
+            "md5Checksum": md5,
+            "size": content.len().to_string(),
+            "capabilities": {"canDownload": true},
+        });
+        let drop_after = Mutex::new(drop_after);
+        TestServer::start(move |request| {
+            if request.query("alt") != Some("media") {
+                return test_server::Response::json(200, &meta);
+            }
+            let (status, start, end) = match request.header("Range") {
+                Some(range) => {
+                    let range = range.strip_prefix("bytes=").unwrap();
+                    let (start, end) = range.split_once('-').unwrap();
+                    let start: usize = start.parse().unwrap();
+                    let end = end.parse().map_or(content.len(), |e: usize| e + 1);
+                    (206, start, end)
+                }
+                None => (200, 0, content.len()),
+            };
+            let rsp = test_server::Response::new(status)
+                .header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, end - 1, content.len()),
+                )
+                .body(content[start..end].to_vec());
+            match drop_after.lock().unwrap().take() {
+                Some(n) => rsp.close_after(n),
+                None => rsp,
+            }
+        })
+        .await
+    }
+
+    fn ranges(server: &TestServer) -> Vec<Option<String>> {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.query("alt") == Some("media"))
+            .map(|r| r.header("Range").map(String::from))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn download_reports_size_from_metadata() {
+        let server = media_server("2025-01-01T00:00:00Z", None).await;
+        let size = Arc::new(Mutex::new(0));
+        struct H(Arc<Mutex<usize>>);
+        impl DownloadHandler for H {
+            async fn set_size(&self, size: usize) -> Result<(), Error> {
+                *self.0.lock().unwrap() = size;
+                Ok(())
+            }
+            async fn write(&self, _b: Bytes) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+        server
+            .drive()
+            .download(&"file-id".into(), H(size.clone()))
+            .await
+            .unwrap();
+        assert_eq!(*size.lock().unwrap(), 1000);
+    }
+
+    #[tokio::test]
+    async fn classifies_missing_and_forbidden_files() {
+        let server = TestServer::start(|_| {
+            test_server::Response::json(404, r#"{"error": {"code": 404, "message": "no"}}"#)
+        })
+        .await;
+        let e = server
+            .drive()
+            .download_as_binary(&"file-id".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+
+        let server = TestServer::start(|request| {
+            if request.query("alt") != Some("media") {
+                return test_server::Response::json(
+                    200,
+                    r#"{
+                        "id": "file-id",
+                        "name": "a.txt",
+                        "mimeType": "text/plain",
+                        "modifiedTime": "2025-01-01T00:00:00Z",
+                        "capabilities": {"canDownload": true}
+                    }"#,
+                );
+            }
+            test_server::Response::json(
+                403,
+                r#"{"error": {"code": 403, "message": "no",
+                    "errors": [{"reason": "cannotDownloadFile"}]}}"#,
+            )
+        })
+        .await;
+        let e = server
+            .drive()
+            .download_as_binary(&"file-id".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::PermissionDenied(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn resumes_after_connection_drop() {
+        let server = media_server("2025-01-01T00:00:00Z", Some(300)).await;
+        let data = server
+            .drive()
+            .download_as_binary(&"file-id".into())
+            .await
+            .unwrap();
+        assert_eq!(data, content());
+        assert_eq!(
+            ranges(&server),
+            [Some("bytes=0-999".into()), Some("bytes=300-999".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_from_partial_file() {
+        let server = media_server("2025-01-01T00:00:00Z", None).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(dir.path().join("data.bin.part"), &content()[..400]).unwrap();
+
+        server
+            .drive()
+            .download_to_file(&"file-id".into(), &path, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+        assert!(!dir.path().join("data.bin.part").exists());
+        assert_eq!(ranges(&server), [Some("bytes=400-999".into())]);
+    }
+
+    #[tokio::test]
+    async fn restarts_when_remote_is_newer_than_partial_file() {
+        let server = media_server("2100-01-01T00:00:00Z", None).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(dir.path().join("data.bin.part"), b"stale").unwrap();
+
+        server
+            .drive()
+            .download_to_file(&"file-id".into(), &path, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+        assert_eq!(ranges(&server), [Some("bytes=0-999".into())]);
+    }
+
+    #[tokio::test]
+    async fn downloads_chunks_in_parallel() {
+        let server = media_server("2025-01-01T00:00:00Z", None).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let opts = DownloadOptions {
+            concurrency: 4,
+            chunk_size: 128,
+            ..Default::default()
+        };
+
+        server
+            .drive()
+            .download_to_file(&"file-id".into(), &path, &opts)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+        let mut ranges = ranges(&server);
+        ranges.sort();
+        assert_eq!(ranges.len(), 8);
+        assert_eq!(ranges[0].as_deref(), Some("bytes=0-127"));
+        assert!(ranges.contains(&Some("bytes=896-999".into())));
+    }
+
+    #[tokio::test]
+    async fn rejects_checksum_mismatch() {
+        let server = serve(
+            "2025-01-01T00:00:00Z",
+            "00112233445566778899aabbccddeeff",
+            None,
+        )
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, b"old").unwrap();
+
+        let result = server
+            .drive()
+            .download_and_save(&"file-id".into(), &path)
+            .await;
+        let Err(Error::ChecksumMismatch { algorithm, .. }) = result else {
+            panic!("unexpected result: {:?}", result);
+        };
+        assert_eq!(algorithm, "md5");
+        // 既存のファイルは置き換えず、壊れた途中のファイルも残さない
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert!(!dir.path().join("data.bin.part").exists());
+
+        let result = server.drive().download_as_binary(&"file-id".into()).await;
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupted_partial_file() {
+        let server = media_server("2025-01-01T00:00:00Z", None).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(dir.path().join("data.bin.part"), vec![0; 400]).unwrap();
+
+        let result = server
+            .drive()
+            .download_to_file(&"file-id".into(), &path, &DownloadOptions::default())
+            .await;
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+        assert!(!path.exists());
+
+        // 壊れた途中のファイルは削除されているので、次は最初からダウンロードする
+        server
+            .drive()
+            .download_to_file(&"file-id".into(), &path, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), content());
+    }
+}
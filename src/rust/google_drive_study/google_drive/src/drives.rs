@@ -0,0 +1,223 @@
+//! 共有ドライブの一覧と取得
+
+use chrono::{DateTime, Utc};
+
+use crate::{Error, GDrive, GDriveId};
+
+/// 共有ドライブ
+///
+/// 共有ドライブのIDは、そのドライブのルートフォルダのIDでもあります。
+/// [`GDrive::list`]や[`GDrive::mirror`]にそのまま渡すことができます。
+#[derive(Debug, Clone, Default)]
+pub struct SharedDrive {
+    /// 共有ドライブのID
+    pub id: GDriveId,
+
+    /// 共有ドライブの名前
+    pub name: String,
+
+    /// 作成日時
+    pub created_time: Option<DateTime<Utc>>,
+
+    /// 既定の一覧から隠されているか
+    pub hidden: Option<bool>,
+
+    /// ファイルをダウンロードできるか
+    pub can_download: Option<bool>,
+}
+
+impl SharedDrive {
+    const FIELDS: &str = "id,name,createdTime,hidden,capabilities(canDownload)";
+
+    /// Google Drive APIのDriveオブジェクトからSharedDriveを作成
+    ///
+    /// `id`、`name`が無い場合は`Error::MetaIsNull`を返す。
+    fn new(drive: google_drive3::api::Drive) -> Result<Self, Error> {
+        let Some(id) = drive.id else {
+            return Err(Error::MetaIsNull("id"));
+        };
+        let Some(name) = drive.name else {
+            return Err(Error::MetaIsNull("name"));
+        };
+        Ok(Self {
+            id: id.into(),
+            name,
+            created_time: drive.created_time,
+            hidden: drive.hidden,
+            can_download: drive.capabilities.and_then(|c| c.can_download),
+        })
+    }
+}
+
+impl GDrive {
+    /// アクセスできる共有ドライブの一覧を取得する（`drives.list`）
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, MirrorOptions, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///
+    ///     // 全ての共有ドライブを名前のディレクトリにミラーリング
+    ///     for shared in drive.shared_drives().await? {
+    ///         let report = drive
+    ///             .mirror(&shared.id, format!("./mirror/{}", shared.name), &MirrorOptions::default())
+    ///             .await?;
+    ///         println!("{}: {}", shared.name, report);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn shared_drives(&self) -> Result<Vec<SharedDrive>, Error> {
+        self.list_shared_drives(None).await
+    }
+
+    /// 共有ドライブの情報を取得する（`drives.get`）
+    pub async fn shared_drive(&self, id: &GDriveId) -> Result<SharedDrive, Error> {
+        let (rsp, drive) = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .drives()
+                    .get(id.as_ref())
+                    .param("fields", SharedDrive::FIELDS)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        if !rsp.status().is_success() {
+            return Err(Error::InvalidResponse(rsp.status().as_u16()));
+        }
+        SharedDrive::new(drive)
+    }
+
+    /// 共有ドライブを全てのページから取得する。`q`は`drives.list`の検索クエリ
+    pub(crate) async fn list_shared_drives(
+        &self,
+        q: Option<&str>,
+    ) -> Result<Vec<SharedDrive>, Error> {
+        let fields = format!("nextPageToken,drives({})", SharedDrive::FIELDS);
+        let mut result = Vec::new();
+        let mut next_page_token: Option<String> = None;
+        loop {
+            let (rsp, list) = self
+                .retry(|| {
+                    let x = self
+                        .hub
+                        .drives()
+                        .list()
+                        .param("fields", &fields)
+                        .page_size(100)
+                        .add_scopes(&self.scopes);
+                    let x = match q {
+                        Some(q) => x.q(q),
+                        None => x,
+                    };
+                    let x = match next_page_token {
+                        Some(ref y) => x.page_token(y),
+                        None => x,
+                    };
+                    async { Ok(x.doit().await?) }
+                })
+                .await?;
+            if !rsp.status().is_success() {
+                return Err(Error::InvalidResponse(rsp.status().as_u16()));
+            }
+            for drive in list.drives.unwrap_or_default() {
+                result.push(SharedDrive::new(drive)?);
+            }
+            match list.next_page_token {
+                Some(token) => next_page_token = Some(token),
+                None => return Ok(result),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Corpus, Query, SearchOptions,
+        test_server::{self, TestServer},
+    };
+
+    /// 共有ドライブを 1 件ずつ返すサーバー
+    async fn drives_server() -> TestServer {
+        TestServer::start(|request| match request.path.as_str() {
+            "/drive/v3/drives" => {
+                let body = match request.query("pageToken") {
+                    None => serde_json::json!({
+                        "nextPageToken": "2",
+                        "drives": [{"id": "team-a", "name": "Team A", "hidden": false}],
+                    }),
+                    Some(_) => serde_json::json!({
+                        "drives": [{
+                            "id": "team-b", "name": "Team B",
+                            "createdTime": "2025-01-02T03:04:05Z",
+                            "capabilities": {"canDownload": true},
+                        }],
+                    }),
+                };
+                test_server::Response::json(200, body)
+            }
+            "/drive/v3/drives/team-a" => test_server::Response::json(
+                200,
+                serde_json::json!({"id": "team-a", "name": "Team A"}),
+            ),
+            "/drive/v3/files" => test_server::Response::json(200, r#"{"files": []}"#),
+            _ => test_server::Response::json(404, r#"{"error": {"code": 404, "message": "no"}}"#),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn lists_and_gets_shared_drives() {
+        let server = drives_server().await;
+        let drive = server.drive();
+
+        let drives = drive.shared_drives().await.unwrap();
+        assert_eq!(drives.len(), 2);
+        assert_eq!(drives[0].id, "team-a".into());
+        assert_eq!(drives[0].hidden, Some(false));
+        assert_eq!(drives[1].name, "Team B");
+        assert_eq!(drives[1].can_download, Some(true));
+        assert!(drives[1].created_time.is_some());
+
+        let team = drive.shared_drive(&"team-a".into()).await.unwrap();
+        assert_eq!(team.name, "Team A");
+        let e = drive.shared_drive(&"missing".into()).await.unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn search_is_scoped_by_corpus() {
+        let server = drives_server().await;
+        let drive = server.drive();
+
+        for corpus in [
+            Corpus::User,
+            Corpus::Drive("team-a".into()),
+            Corpus::AllDrives,
+        ] {
+            let opts = SearchOptions {
+                corpus,
+                ..Default::default()
+            };
+            drive.search(&Query::full_text("x"), &opts).await.unwrap();
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query("corpora"), None);
+        assert_eq!(requests[0].query("driveId"), None);
+        assert_eq!(requests[1].query("corpora"), Some("drive"));
+        assert_eq!(requests[1].query("driveId"), Some("team-a"));
+        assert_eq!(requests[2].query("corpora"), Some("allDrives"));
+        assert_eq!(requests[2].query("driveId"), None);
+    }
+}
@@ -6,15 +6,27 @@
 //!
 //! ## 機能
 //!
-//! - OAuth2認証プロセスをサポート
-//! - ファイルやフォルダの一覧取得
-//! - ファイルのメタデータを取得
-//! - ファイルのダウンロード（バイナリまたはファイルとして保存）
+//! - OAuth2認証プロセスをサポート（サービスアカウント、デバイスコードフロー、取得済みトークンも可）
+//! - トークンの保存先の選択（メモリ、ファイル、暗号化ファイル、コールバック）とアカウントごとのプロファイル
+//! - ファイルやフォルダの一覧取得と、型付きのクエリによる検索（`Stream`でページごとに取得も可）
+//! - ファイルのメタデータを取得（取得するフィールドを選択可能）
+//! - 共有ドライブの一覧取得と、共有ドライブを範囲にした検索
+//! - パス（`My Drive/dir/file`、`共有ドライブ名:/dir/file`）や共有URLからIDを取得、IDからパスを取得
+//! - ファイルのダウンロード（バイナリまたはファイルとして保存、中断からの再開、並列ダウンロード、チェックサムの検証）
+//! - Google Apps形式（ドキュメント、スプレッドシートなど）のエクスポート
+//! - 版の一覧取得、古い版のダウンロードとエクスポート、版の保持
+//! - フォルダをローカルディレクトリにミラーリング
+//! - ファイルのアップロード（再開可能なアップロードを含む）
+//! - フォルダの作成、名前の変更、移動、コピー、ゴミ箱への移動、削除
+//! - 共有設定（ユーザー、グループ、ドメイン、リンクを知っている全員）の取得と変更、オーナーの譲渡
+//! - Changes APIによる変更の取得（ページトークンの保存、定期的な確認）
+//! - 一時的なエラー（429、5xxなど）のリトライとレート制限
+//! - 操作を差し替える`Backend`トレイトと、テスト用のメモリ上のドライブ（`MemoryDrive`）
 //!
 //! ## 使用例
 //!
 //! ```skip
-//! use google_drive::{GDrive, GDriveId};
+//! use google_drive::{GDrive, GDriveId, scope};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -22,6 +34,7 @@
 //!     let drive = GDrive::oauth(
 //!         "client_secret.json",
 //!         "./token.json",
+//!         &[scope::READONLY],
 //!         None,
 //!     ).await?;
 //!
@@ -29,10 +42,10 @@
 //!     let folder_id: GDriveId = "your_folder_id".into();
 //!     let files = drive.list(&folder_id).await?;
 //!
-//!     // ファイルをダウンロード
+//!     // ファイルをダウンロード（Google Apps形式は既定の形式でエクスポート）
 //!     for meta in files {
-//!         if !meta.is_directory() && meta.can_download {
-//!             drive.download_and_save(&meta.id, format!("./downloads/{}", meta.name)).await?;
+//!         if !meta.is_directory() && meta.can_download == Some(true) {
+//!             drive.download_and_save(&meta.id, format!("./downloads/{}", meta.local_name())).await?;
 //!         }
 //!     }
 //!
@@ -40,27 +53,67 @@
 //! }
 //! ```
 
-use std::{
-    future::poll_fn,
-    path::Path,
-    pin::Pin,
-    sync::{Arc, Mutex},
-};
+mod auth;
+mod backend;
+mod changes;
+mod checksum;
+mod download;
+mod drives;
+mod manage;
+mod memory;
+mod mirror;
+mod path;
+mod permission;
+mod query;
+mod retry;
+mod revision;
+#[cfg(test)]
+mod test_server;
+mod token;
+mod upload;
+
+use std::{collections::HashMap, future::poll_fn, path::Path, pin::Pin, sync::Arc};
+
+use futures::{Stream, TryStreamExt, stream};
 
 use google_drive3::{
     DriveHub,
     api::File,
+    common::{self, GetToken},
     hyper::{
         self,
         body::{Body, Bytes},
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
     },
     hyper_rustls::{self, HttpsConnector},
     hyper_util::{self, client::legacy::connect::HttpConnector},
-    yup_oauth2::{InstalledFlowAuthenticator, InstalledFlowReturnMethod, read_application_secret},
+    yup_oauth2::{
+        AuthorizedUserAuthenticator, DeviceFlowAuthenticator, InstalledFlowAuthenticator,
+        InstalledFlowReturnMethod, ServiceAccountAuthenticator,
+        authorized_user::AuthorizedUserSecret, read_application_secret, read_service_account_key,
+    },
 };
 
-pub use google_drive3::yup_oauth2::authenticator_delegate::InstalledFlowDelegate;
-use tokio::stream;
+pub use auth::{AuthListener, CustomServerFlowDelegate};
+pub use backend::{Backend, download_and_save, export_and_save};
+pub use changes::{Change, ChangeCursor};
+use checksum::{Checksum, Hashing};
+use download::FileWriter;
+pub use download::{DEFAULT_CHUNK_SIZE, DownloadOptions};
+pub use drives::SharedDrive;
+pub use google_drive3::yup_oauth2::authenticator_delegate::{
+    DeviceFlowDelegate, InstalledFlowDelegate,
+};
+pub use memory::MemoryDrive;
+pub use mirror::{MirrorOptions, MirrorReport, Removed, mirror};
+pub use path::DuplicatePolicy;
+pub use permission::{Grantee, Permission, Role, ShareOptions};
+pub use query::{Corpus, OrderBy, Query, SearchOptions, SortKey};
+pub use retry::{RateLimiter, RetryPolicy};
+pub use revision::Revision;
+pub use token::{Profiles, TokenCallback, TokenStore};
+use token::{SharedTokens, Tokens};
+pub use upload::{RESUMABLE_THRESHOLD, ResumableOptions, UploadTarget};
 
 /// Google DriveのIDを表す構造体
 ///
@@ -78,7 +131,7 @@ use tokio::stream;
 /// assert_eq!(id1, id2);
 /// println!("ID: {}", id1); // ID: 1wS6tVoVmkdMZ96DgcKew-pSYBpJa9pXA
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GDriveId(String);
 
 impl From<String> for GDriveId {
@@ -111,8 +164,10 @@ impl AsRef<str> for GDriveId {
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// Google Drive API自体からのエラー
+    ///
+    /// `Error`が大きくなりすぎないようにボックス化しています。
     #[error("GoogleDrive API Error: {0}")]
-    GoogleDriveAPIError(#[from] google_drive3::Error),
+    GoogleDriveAPIError(#[source] Box<google_drive3::Error>),
 
     /// ファイルシステム操作に関連するエラー
     #[error("IO Error: {0}")]
@@ -134,18 +189,94 @@ pub enum Error {
     #[error("Download Error: {0} is a directory")]
     DirectoryDownloadError(GDriveId),
 
+    /// ダウンロードしたデータのチェックサムがメタデータと一致しない
+    #[error("Checksum Error: {algorithm} of {id} is {actual}, expected {expected}")]
+    ChecksumMismatch {
+        id: GDriveId,
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+
+    /// レスポンスのJSONが解釈できない
+    #[error("JSON Error: {0}")]
+    JSONError(#[from] serde_json::Error),
+
+    /// 操作する権限が無い（403）
+    #[error("Permission Denied: {0}")]
+    PermissionDenied(String),
+
+    /// ファイルやフォルダが見つからない（404）
+    #[error("Not Found: {0}")]
+    NotFound(String),
+
+    /// パスに一致するアイテムが無い
+    #[error("Path Error: {0} is not found")]
+    PathNotFound(String),
+
+    /// パスに一致するアイテムが複数ある（`DuplicatePolicy::Error`の場合）
+    #[error("Path Error: {0} matches {len} items", len = .1.len())]
+    AmbiguousPath(String, Vec<GDriveId>),
+
+    /// エクスポートできないGoogle Apps形式のファイル（フォーム、サイトなど）
+    #[error("Export Error: {0} cannot be exported")]
+    NotExportable(String),
+
+    /// トークンの保存先の読み書きに失敗した（パスフレーズが違う、プロファイル名が不正など）
+    #[error("Token Store Error: {0}")]
+    TokenStoreError(String),
+
     /// 内部エラー
     #[error("Internal Error")]
     InternalError,
 }
 
+impl Error {
+    /// APIのエラーのうち、403 を`PermissionDenied`、404 を`NotFound`にする
+    ///
+    /// レート制限による 403 はそのまま返す。
+    pub(crate) fn classify(self) -> Self {
+        use google_drive3::common::Error as E;
+
+        let (status, message) = match &self {
+            Error::InvalidResponse(status) => (*status, status.to_string()),
+            Error::GoogleDriveAPIError(e) => match e.as_ref() {
+                E::BadRequest(json) => {
+                    let error = &json["error"];
+                    (
+                        error["code"].as_u64().unwrap_or(0) as u16,
+                        error["message"].as_str().unwrap_or_default().to_string(),
+                    )
+                }
+                E::Failure(rsp) => (rsp.status().as_u16(), rsp.status().to_string()),
+                _ => return self,
+            },
+            _ => return self,
+        };
+        match status {
+            403 if !self.is_retryable() => Error::PermissionDenied(message),
+            404 => Error::NotFound(message),
+            _ => self,
+        }
+    }
+}
+
+impl From<google_drive3::Error> for Error {
+    fn from(e: google_drive3::Error) -> Self {
+        Error::GoogleDriveAPIError(Box::new(e))
+    }
+}
+
 // --------------------------------------------------------
 
 /// Google Driveファイルのメタデータ
 ///
 /// この構造体はGoogle Driveのファイルやフォルダに関するメタデータを表します。
 /// IDや名前、MIMEタイプ、変更日時などの基本的な情報が含まれます。
-#[derive(Debug, Clone)]
+///
+/// `id`、`name`、`mime_type`以外は[`MetaFields`]で要求した時だけ設定されます。
+/// 要求しなかったフィールドは`None`または空になります。
+#[derive(Debug, Clone, Default)]
 pub struct GMeta {
     /// ファイルやフォルダのID
     pub id: GDriveId,
@@ -157,10 +288,74 @@ pub struct GMeta {
     pub mime_type: String,
 
     /// 最終変更日時（UTC）
-    pub modified_time: chrono::DateTime<chrono::offset::Utc>,
+    pub modified_time: Option<chrono::DateTime<chrono::offset::Utc>>,
+
+    /// 作成日時（UTC）
+    pub created_time: Option<chrono::DateTime<chrono::offset::Utc>>,
 
     /// ダウンロード可能かどうか
-    pub can_download: bool,
+    pub can_download: Option<bool>,
+
+    /// 内容のMD5（16進小文字）。Google Apps形式のファイルやフォルダには無い
+    pub md5_checksum: Option<String>,
+
+    /// 内容のSHA-1（16進小文字）。Google Apps形式のファイルやフォルダには無い
+    pub sha1_checksum: Option<String>,
+
+    /// 内容のSHA-256（16進小文字）。Google Apps形式のファイルやフォルダには無い
+    pub sha256_checksum: Option<String>,
+
+    /// ファイルのサイズ（バイト）。Google Apps形式のファイルやフォルダには無い
+    pub size: Option<u64>,
+
+    /// 親フォルダのID
+    pub parents: Vec<GDriveId>,
+
+    /// 共有ドライブのID。マイドライブのアイテムには無い
+    pub drive_id: Option<GDriveId>,
+
+    /// 所有者。共有ドライブのファイルには無い
+    pub owners: Vec<Owner>,
+
+    /// ゴミ箱にあるかどうか
+    pub trashed: Option<bool>,
+
+    /// スターが付いているかどうか
+    pub starred: Option<bool>,
+
+    /// ブラウザで開くリンク
+    pub web_view_link: Option<String>,
+
+    /// ブラウザでダウンロードするリンク。Google Apps形式のファイルやフォルダには無い
+    pub web_content_link: Option<String>,
+
+    /// ショートカットのリンク先。ショートカット以外には無い
+    pub shortcut_details: Option<ShortcutDetails>,
+
+    /// エクスポート先のMIMEタイプとダウンロードするリンク。Google Apps形式のファイルのみ
+    pub export_links: HashMap<String, String>,
+}
+
+/// ファイルの所有者
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owner {
+    /// 表示名
+    pub display_name: Option<String>,
+    /// メールアドレス
+    pub email_address: Option<String>,
+    /// 権限のID
+    pub permission_id: Option<String>,
+    /// 認証したユーザー自身かどうか
+    pub me: Option<bool>,
+}
+
+/// ショートカットのリンク先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortcutDetails {
+    /// リンク先のID
+    pub target_id: GDriveId,
+    /// リンク先のMIMEタイプ
+    pub target_mime_type: Option<String>,
 }
 
 impl GMeta {
@@ -182,7 +377,38 @@ impl GMeta {
         self.is_google_app() && !self.is_directory()
     }
 
+    /// このアイテムがショートカットかどうかを判定
+    pub fn is_shortcut(&self) -> bool {
+        self.mime_type == "application/vnd.google-apps.shortcut"
+    }
+
+    /// このファイルをエクスポートできる形式の一覧。先頭が既定の形式
+    ///
+    /// Google Apps形式以外のファイルや、エクスポートできない形式の場合は空になります。
+    pub fn export_formats(&self) -> &'static [ExportFormat] {
+        ExportFormat::formats_for(&self.mime_type)
+    }
+
+    /// 既定のエクスポート形式
+    pub fn default_export(&self) -> Option<ExportFormat> {
+        self.export_formats().first().copied()
+    }
+
+    /// ローカルに保存する時のファイル名
+    ///
+    /// Google Apps形式のファイルは名前に拡張子が無いため、既定のエクスポート形式の拡張子を付けます。
+    pub fn local_name(&self) -> String {
+        match self.default_export() {
+            Some(format) if self.is_google_app_file() => {
+                format!("{}.{}", self.name, format.extension())
+            }
+            _ => self.name.clone(),
+        }
+    }
+
     /// Google Drive APIのFileオブジェクトからGMetaを作成
+    ///
+    /// `id`、`name`、`mimeType`が無い場合は`Error::MetaIsNull`を返す。
     fn new(file: File) -> Result<Self, Error> {
         let Some(id) = file.id else {
             return Err(Error::MetaIsNull("id"));
@@ -193,28 +419,275 @@ impl GMeta {
         let Some(name) = file.name else {
             return Err(Error::MetaIsNull("name"));
         };
-        let Some(modified_time) = file.modified_time else {
-            return Err(Error::MetaIsNull("modifiedTime"));
-        };
-        let Some(capabilities) = file.capabilities else {
-            return Err(Error::MetaIsNull("capabilities"));
-        };
-        let Some(can_download) = capabilities.can_download else {
-            return Err(Error::MetaIsNull("capabilities.canDownload"));
-        };
         Ok(GMeta {
             id: id.into(),
             name,
             mime_type,
-            modified_time,
-            can_download,
+            modified_time: file.modified_time,
+            created_time: file.created_time,
+            can_download: file.capabilities.and_then(|c| c.can_download),
+            md5_checksum: file.md5_checksum,
+            sha1_checksum: file.sha1_checksum,
+            sha256_checksum: file.sha256_checksum,
+            size: file.size.map(|s| s as u64),
+            parents: file
+                .parents
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            drive_id: file.drive_id.map(Into::into),
+            owners: file
+                .owners
+                .unwrap_or_default()
+                .into_iter()
+                .map(|u| Owner {
+                    display_name: u.display_name,
+                    email_address: u.email_address,
+                    permission_id: u.permission_id,
+                    me: u.me,
+                })
+                .collect(),
+            trashed: file.trashed,
+            starred: file.starred,
+            web_view_link: file.web_view_link,
+            web_content_link: file.web_content_link,
+            shortcut_details: file.shortcut_details.and_then(|d| {
+                Some(ShortcutDetails {
+                    target_id: d.target_id?.into(),
+                    target_mime_type: d.target_mime_type,
+                })
+            }),
+            export_links: file.export_links.unwrap_or_default(),
         })
     }
 }
 
 // --------------------------------------------------------
-const READONLY: &str = "https://www.googleapis.com/auth/drive.readonly";
-const SCOPES: &[&str] = &[READONLY];
+
+/// Google Apps形式のファイルのエクスポート形式
+///
+/// # 例
+///
+/// ```
+/// use google_drive::ExportFormat;
+///
+/// let formats = ExportFormat::formats_for("application/vnd.google-apps.spreadsheet");
+/// assert_eq!(formats, &[ExportFormat::Xlsx, ExportFormat::Csv, ExportFormat::Pdf]);
+///
+/// let format: ExportFormat = "md".parse().unwrap();
+/// assert_eq!(format, ExportFormat::Markdown);
+/// assert_eq!(format.mime_type(), "text/markdown");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    /// Microsoft Word
+    Docx,
+    /// Microsoft Excel
+    Xlsx,
+    /// Microsoft PowerPoint
+    Pptx,
+    /// PDF
+    Pdf,
+    /// Markdown
+    Markdown,
+    /// CSV（スプレッドシートの最初のシートのみ）
+    Csv,
+    /// プレーンテキスト
+    Text,
+    /// PNG画像
+    Png,
+    /// SVG画像
+    Svg,
+}
+
+const DOCUMENT: &str = "application/vnd.google-apps.document";
+const SPREADSHEET: &str = "application/vnd.google-apps.spreadsheet";
+const PRESENTATION: &str = "application/vnd.google-apps.presentation";
+const DRAWING: &str = "application/vnd.google-apps.drawing";
+
+impl ExportFormat {
+    /// 全ての形式
+    pub const ALL: &[ExportFormat] = &[
+        ExportFormat::Docx,
+        ExportFormat::Xlsx,
+        ExportFormat::Pptx,
+        ExportFormat::Pdf,
+        ExportFormat::Markdown,
+        ExportFormat::Csv,
+        ExportFormat::Text,
+        ExportFormat::Png,
+        ExportFormat::Svg,
+    ];
+
+    /// `files.export`に渡すMIMEタイプ
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+            ExportFormat::Pptx => {
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            }
+            ExportFormat::Pdf => "application/pdf",
+            ExportFormat::Markdown => "text/markdown",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Text => "text/plain",
+            ExportFormat::Png => "image/png",
+            ExportFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    /// ファイルの拡張子（`.`は含まない）
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Docx => "docx",
+            ExportFormat::Xlsx => "xlsx",
+            ExportFormat::Pptx => "pptx",
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Text => "txt",
+            ExportFormat::Png => "png",
+            ExportFormat::Svg => "svg",
+        }
+    }
+
+    /// Google Apps形式のMIMEタイプに対するエクスポート形式の一覧。先頭が既定の形式
+    ///
+    /// * ドキュメント: docx, pdf, markdown, txt
+    /// * スプレッドシート: xlsx, csv, pdf
+    /// * スライド: pptx, pdf, txt
+    /// * 図形描画: png, svg, pdf
+    pub fn formats_for(google_mime_type: &str) -> &'static [ExportFormat] {
+        match google_mime_type {
+            DOCUMENT => &[
+                ExportFormat::Docx,
+                ExportFormat::Pdf,
+                ExportFormat::Markdown,
+                ExportFormat::Text,
+            ],
+            SPREADSHEET => &[ExportFormat::Xlsx, ExportFormat::Csv, ExportFormat::Pdf],
+            PRESENTATION => &[ExportFormat::Pptx, ExportFormat::Pdf, ExportFormat::Text],
+            DRAWING => &[ExportFormat::Png, ExportFormat::Svg, ExportFormat::Pdf],
+            _ => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    /// 拡張子（`docx`, `md`など）またはMIMEタイプから変換
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches('.');
+        let s = if s == "markdown" { "md" } else { s };
+        ExportFormat::ALL
+            .iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(s) || f.mime_type() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown export format: {}", s))
+    }
+}
+
+// --------------------------------------------------------
+
+/// `GMeta`に取得するフィールドの組
+///
+/// `GDrive::set_meta_fields`で`list`や`get_meta`が要求するフィールドを選びます。既定は`Standard`です。
+///
+/// # 例
+///
+/// ```
+/// use google_drive::MetaFields;
+///
+/// assert_eq!(MetaFields::Minimal.fields(), "id,name,mimeType");
+/// assert_eq!(
+///     MetaFields::custom("mimeType,starred").fields(),
+///     "id,name,mimeType,starred"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MetaFields {
+    /// `id`、`name`、`mimeType`のみ
+    Minimal,
+    /// ダウンロードやミラーリングに必要なもの（更新日時、サイズ、チェックサム、親、ゴミ箱など）
+    #[default]
+    Standard,
+    /// `GMeta`の全てのフィールド
+    Full,
+    /// 指定したフィールド。`id`、`name`、`mimeType`は必ず含まれる
+    Custom(String),
+}
+
+impl MetaFields {
+    const MINIMAL: &str = "id,name,mimeType";
+    const STANDARD: &str = "id,name,mimeType,modifiedTime,size,md5Checksum,sha1Checksum,\
+         sha256Checksum,parents,driveId,trashed,shortcutDetails,capabilities(canDownload)";
+    const FULL: &str = "id,name,mimeType,modifiedTime,createdTime,size,md5Checksum,\
+         sha1Checksum,sha256Checksum,parents,driveId,trashed,starred,shortcutDetails,\
+         capabilities(canDownload),owners(displayName,emailAddress,permissionId,me),\
+         webViewLink,webContentLink,exportLinks";
+
+    /// `fields`（カンマ区切り）を要求する。`id`、`name`、`mimeType`が無ければ先頭に追加する
+    pub fn custom(fields: &str) -> Self {
+        let fields: Vec<&str> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .collect();
+        let mut v: Vec<&str> = ["id", "name", "mimeType"]
+            .into_iter()
+            .filter(|f| !fields.contains(f))
+            .collect();
+        v.extend(fields);
+        Self::Custom(v.join(","))
+    }
+
+    /// APIの`fields`パラメータに渡す文字列
+    pub fn fields(&self) -> &str {
+        match self {
+            Self::Minimal => Self::MINIMAL,
+            Self::Standard => Self::STANDARD,
+            Self::Full => Self::FULL,
+            Self::Custom(fields) => fields,
+        }
+    }
+
+    /// ダウンロードなどで使うフィールド。`Standard`に足りない場合は`Standard`にする
+    fn for_transfer(&self) -> &Self {
+        match self {
+            Self::Full => self,
+            _ => &Self::Standard,
+        }
+    }
+}
+
+/// `GMeta`の作成に必要なフィールド（ダウンロードやアップロードの結果など）
+const META_FIELDS: &str = MetaFields::STANDARD;
+
+/// OAuth2のスコープ
+///
+/// `GDrive::oauth`に渡して使用します。アップロードには`FULL`か`FILE`が必要です。
+pub mod scope {
+    /// 全てのファイルの読み取り
+    pub const READONLY: &str = "https://www.googleapis.com/auth/drive.readonly";
+    /// 全てのファイルの読み書き
+    pub const FULL: &str = "https://www.googleapis.com/auth/drive";
+    /// このアプリで作成、または開いたファイルの読み書き
+    pub const FILE: &str = "https://www.googleapis.com/auth/drive.file";
+    /// ファイルのメタデータの読み取り
+    pub const METADATA_READONLY: &str = "https://www.googleapis.com/auth/drive.metadata.readonly";
+}
 
 /// ファイルのダウンロード処理を定義するトレイト
 ///
@@ -235,11 +708,29 @@ pub trait DownloadHandler {
     fn write(&self, b: Bytes) -> impl Future<Output = Result<(), Error>> + Send;
 }
 
+const DEFAULT_ROOT_URL: &str = "https://www.googleapis.com/";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+
 /// Google Driveへのアクセスを提供するメインクラス
 ///
 /// この構造体は認証、ファイル一覧の取得、ダウンロードなど、
 /// Google Drive APIとの対話に必要な主要な機能を提供します。
-pub struct GDrive(DriveHub<HttpsConnector<HttpConnector>>);
+pub struct GDrive {
+    hub: DriveHub<HttpsConnector<HttpConnector>>,
+    /// 認証時に要求したスコープ。APIの呼び出し時にも同じものを使う
+    scopes: Vec<String>,
+    /// アップロードなど`DriveHub`を通さないリクエストの接続先
+    root_url: String,
+    /// トークンの保存先。ログアウト時に削除する
+    tokens: Option<Arc<Tokens>>,
+    /// ログアウト時にトークンを無効化するエンドポイント
+    revoke_url: String,
+    retry: RetryPolicy,
+    /// 全てのリクエストで共有するレート制限
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// `list`や`get_meta`で要求するフィールド
+    meta_fields: MetaFields,
+}
 
 impl GDrive {
     /// OAuth2認証を使用してGoogle Driveに接続する
@@ -247,8 +738,11 @@ impl GDrive {
     /// # 引数
     ///
     /// * `client_secret` - クライアントシークレットのJSONファイルのパス
-    /// * `save_token` - 認証トークンを保存するパス
-    /// * `flow_delegate` - 認証フローのカスタマイズに使用できるデリゲート（オプション）
+    /// * `save_token` - 認証トークンの保存先（[`TokenStore`]）。パスを渡すとそのファイルに保存します
+    /// * `scopes` - 要求するスコープ（[`scope`]）。読み取りのみなら`&[scope::READONLY]`
+    /// * `flow_delegate` - 認証フローのカスタマイズに使用できるデリゲート（オプション）。
+    ///   `redirect_uri`を返すデリゲート（[`CustomServerFlowDelegate`]など）の場合は、
+    ///   デリゲートがリダイレクトを受け取って認証コードを返します
     ///
     /// # 戻り値
     ///
@@ -257,7 +751,7 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, InstalledFlowDelegate};
+    /// use google_drive::{GDrive, InstalledFlowDelegate, scope};
     ///
     /// struct MyDelegate;
     ///
@@ -270,6 +764,7 @@ impl GDrive {
     ///     let drive = GDrive::oauth(
     ///         "client_secret.json",
     ///         "./token.json",
+    ///         &[scope::FULL],
     ///         Some(Box::new(MyDelegate {})),
     ///     ).await?;
     ///
@@ -278,25 +773,160 @@ impl GDrive {
     ///     Ok(())
     /// }
     /// ```
-    pub async fn oauth<P1, P2>(
-        client_secret: P1,
-        save_token: P2,
+    pub async fn oauth<P, S>(
+        client_secret: P,
+        save_token: S,
+        scopes: &[&str],
         flow_delegate: Option<Box<dyn InstalledFlowDelegate>>,
     ) -> Result<Self, Error>
     where
-        P1: AsRef<Path>,
-        P2: AsRef<Path>,
+        P: AsRef<Path>,
+        S: Into<TokenStore>,
     {
         let secret = read_application_secret(client_secret).await?;
-        let builder =
-            InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::HTTPRedirect)
-                .persist_tokens_to_disk(save_token.as_ref());
+        // 自分でリダイレクトを受け取るデリゲートなら、yup_oauth2のサーバーは起動しない
+        let method = match &flow_delegate {
+            Some(x) if x.redirect_uri().is_some() => InstalledFlowReturnMethod::Interactive,
+            _ => InstalledFlowReturnMethod::HTTPRedirect,
+        };
+        let tokens = Tokens::new(save_token.into());
+        let builder = InstalledFlowAuthenticator::builder(secret, method)
+            .with_storage(Box::new(SharedTokens(tokens.clone())));
         let builder = match flow_delegate {
             Some(x) => builder.flow_delegate(x),
             None => builder,
         };
         let auth = builder.build().await?;
+        Ok(Self::with_auth(auth, scopes).with_tokens(tokens))
+    }
 
+    /// サービスアカウントのJSONキーを使用してGoogle Driveに接続する
+    ///
+    /// ブラウザを使わないため、CIなどの無人の環境で使えます。
+    /// トークンはメモリ上にだけ保持し、[`GDrive::logout`]で無効化して破棄します。
+    ///
+    /// # 引数
+    ///
+    /// * `service_account_key` - サービスアカウントのJSONキーファイルのパス
+    /// * `subject` - ドメイン全体の委任で成り代わるユーザーのメールアドレス（オプション）。
+    ///   `None`の場合はサービスアカウント自身のDriveにアクセスします
+    /// * `scopes` - 要求するスコープ（[`scope`]）
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::service_account(
+    ///         "service_account.json",
+    ///         Some("user@example.com"),
+    ///         &[scope::READONLY],
+    ///     ).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn service_account<P: AsRef<Path>>(
+        service_account_key: P,
+        subject: Option<&str>,
+        scopes: &[&str],
+    ) -> Result<Self, Error> {
+        let key = read_service_account_key(service_account_key).await?;
+        let tokens = Tokens::new(TokenStore::Memory);
+        let builder = ServiceAccountAuthenticator::builder(key)
+            .with_storage(Box::new(SharedTokens(tokens.clone())));
+        let builder = match subject {
+            Some(x) => builder.subject(x),
+            None => builder,
+        };
+        let auth = builder.build().await?;
+        Ok(Self::with_auth(auth, scopes).with_tokens(tokens))
+    }
+
+    /// デバイスコードフローを使用してGoogle Driveに接続する
+    ///
+    /// 表示されたURLとコードを別の端末のブラウザで入力して認証します。
+    /// ブラウザを開けない環境で使えます。
+    /// Googleのデバイスコードフローで使えるDriveのスコープは[`scope::FILE`]などに限られます。
+    ///
+    /// # 引数
+    ///
+    /// * `client_secret` - クライアントシークレットのJSONファイルのパス（「テレビと入力が限られたデバイス」のクライアント）
+    /// * `save_token` - 認証トークンの保存先（[`TokenStore`]）。パスを渡すとそのファイルに保存します
+    /// * `scopes` - 要求するスコープ（[`scope`]）
+    /// * `flow_delegate` - URLとコードの表示方法を変更するデリゲート（オプション）。
+    ///   `None`の場合は標準出力に表示します
+    pub async fn device_code<P, S>(
+        client_secret: P,
+        save_token: S,
+        scopes: &[&str],
+        flow_delegate: Option<Box<dyn DeviceFlowDelegate>>,
+    ) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+        S: Into<TokenStore>,
+    {
+        let secret = read_application_secret(client_secret).await?;
+        let tokens = Tokens::new(save_token.into());
+        let builder = DeviceFlowAuthenticator::builder(secret)
+            .with_storage(Box::new(SharedTokens(tokens.clone())));
+        let builder = match flow_delegate {
+            Some(x) => builder.flow_delegate(x),
+            None => builder,
+        };
+        let auth = builder.build().await?;
+        Ok(Self::with_auth(auth, scopes).with_tokens(tokens))
+    }
+
+    /// 取得済みのリフレッシュトークンを使用してGoogle Driveに接続する
+    ///
+    /// アクセストークンは必要に応じてリフレッシュトークンから取得し、メモリ上にだけ保持します。
+    /// [`GDrive::logout`]は取得したアクセストークンを無効化します。
+    /// Googleはアクセストークンを無効化すると、対応するリフレッシュトークンも無効化します。
+    ///
+    /// # 引数
+    ///
+    /// * `client_id` - リフレッシュトークンを発行したクライアントのID
+    /// * `client_secret` - リフレッシュトークンを発行したクライアントのシークレット
+    /// * `refresh_token` - リフレッシュトークン
+    /// * `scopes` - 要求するスコープ（[`scope`]）。リフレッシュトークンの発行時に許可された範囲に限られます
+    pub async fn from_refresh_token(
+        client_id: &str,
+        client_secret: &str,
+        refresh_token: &str,
+        scopes: &[&str],
+    ) -> Result<Self, Error> {
+        let secret = AuthorizedUserSecret {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            refresh_token: refresh_token.into(),
+            key_type: "authorized_user".into(),
+        };
+        let tokens = Tokens::new(TokenStore::Memory);
+        let auth = AuthorizedUserAuthenticator::builder(secret)
+            .with_storage(Box::new(SharedTokens(tokens.clone())))
+            .build()
+            .await?;
+        Ok(Self::with_auth(auth, scopes).with_tokens(tokens))
+    }
+
+    /// 取得済みのアクセストークンを使用してGoogle Driveに接続する
+    ///
+    /// アクセストークンは更新されないため、期限が切れるとAPIの呼び出しは失敗します。
+    /// `gcloud auth print-access-token`などで取得したトークンを短時間使う場合向けです。
+    ///
+    /// # 引数
+    ///
+    /// * `access_token` - アクセストークン
+    /// * `scopes` - トークンに許可されているスコープ（[`scope`]）
+    pub fn from_access_token(access_token: impl Into<String>, scopes: &[&str]) -> Self {
+        Self::with_auth(access_token.into(), scopes)
+    }
+
+    /// 認証済みの`auth`を使って`GDrive`を作る
+    fn with_auth<A: GetToken + 'static>(auth: A, scopes: &[&str]) -> Self {
         let client =
             hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
                 .build(
@@ -307,11 +937,205 @@ impl GDrive {
                         .enable_http1()
                         .build(),
                 );
-        Ok(Self(DriveHub::new(client, auth)))
+        Self {
+            hub: DriveHub::new(client, auth),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            root_url: DEFAULT_ROOT_URL.into(),
+            tokens: None,
+            revoke_url: REVOKE_URL.into(),
+            retry: RetryPolicy::default(),
+            rate_limiter: None,
+            meta_fields: MetaFields::default(),
+        }
+    }
+
+    fn with_tokens(self, tokens: Arc<Tokens>) -> Self {
+        Self {
+            tokens: Some(tokens),
+            ..self
+        }
+    }
+
+    /// APIの接続先を変更する
+    ///
+    /// 既定は`https://www.googleapis.com/`です。テスト用のサーバーやプロキシを使う場合に指定します。
+    /// ログアウト時のトークンの無効化も、変更した接続先の`revoke`に送ります。
+    pub fn set_root_url(&mut self, root_url: &str) {
+        let root_url = format!("{}/", root_url.trim_end_matches('/'));
+        self.hub.base_url(format!("{}drive/v3/", root_url));
+        self.hub.root_url(root_url.clone());
+        self.revoke_url = format!("{}revoke", root_url);
+        self.root_url = root_url;
+    }
+
+    /// `list`や`get_meta`で取得するフィールドを設定する
+    ///
+    /// ダウンロードやミラーリングは、この設定に関係なく必要なフィールドを取得します。
+    pub fn set_meta_fields(&mut self, fields: MetaFields) {
+        self.meta_fields = fields;
+    }
+
+    /// ログアウトする
+    ///
+    /// 保存されているトークン（リフレッシュトークンがあればそれ）を無効化し、保存先から削除します。
+    /// [`GDrive::from_access_token`]で作った場合は、そのアクセストークンを無効化します。
+    /// 既に無効になっているトークンはエラーにしません。
+    /// ログアウト後にAPIを呼び出すと、もう一度認証が必要になります。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     drive.logout().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn logout(&self) -> Result<(), Error> {
+        let token = match &self.tokens {
+            Some(tokens) => tokens.revocable_token().await?,
+            None => self.token().await?,
+        };
+        if let Some(token) = token {
+            let request = hyper::Request::post(&self.revoke_url)
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+            let body = format!("token={}", urlencoding::encode(&token));
+            let rsp = self.send_without_auth(request, body.into()).await?;
+            // 400 は既に無効なトークン
+            let status = rsp.status();
+            if !status.is_success() && status != hyper::StatusCode::BAD_REQUEST {
+                return Err(Error::InvalidResponse(status.as_u16()));
+            }
+        }
+        if let Some(tokens) = &self.tokens {
+            tokens.clear().await?;
+        }
+        Ok(())
+    }
+
+    /// アクセストークンを取得する
+    async fn token(&self) -> Result<Option<String>, Error> {
+        let scopes: Vec<_> = self.scopes.iter().map(String::as_str).collect();
+        self.hub
+            .auth
+            .get_token(&scopes)
+            .await
+            .map_err(|e| google_drive3::Error::MissingToken(e).into())
+    }
+
+    /// `DriveHub`を通さずにリクエストを送り、レスポンスのボディを全て読み込む
+    async fn send(
+        &self,
+        request: hyper::http::request::Builder,
+        body: Bytes,
+    ) -> Result<hyper::Response<Bytes>, Error> {
+        let request = match self.token().await? {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        };
+        self.send_without_auth(request, body).await
+    }
+
+    /// `DriveHub`を通さずにリクエストを送り、ボディを読まずにレスポンスを返す
+    ///
+    /// 成功以外のステータスは`DriveHub`と同じエラーにし、[`Error::classify`]で分類する。
+    async fn send_streaming(
+        &self,
+        request: hyper::http::request::Builder,
+    ) -> Result<common::Response, Error> {
+        self.throttle().await;
+        let request = match self.token().await? {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        };
+        let request = request
+            .body(common::to_body::<Bytes>(None))
+            .map_err(|_| Error::InternalError)?;
+        let rsp = self
+            .hub
+            .client
+            .request(request)
+            .await
+            .map_err(google_drive3::Error::HttpError)?;
+        let (parts, body) = rsp.into_parts();
+        if !parts.status.is_success() {
+            let bytes = common::to_bytes(body).await.unwrap_or_default();
+            let e: Error = match serde_json::from_slice(&bytes) {
+                Ok(value) => google_drive3::Error::BadRequest(value),
+                Err(_) => google_drive3::Error::Failure(common::to_response(parts, Some(bytes))),
+            }
+            .into();
+            return Err(e.classify());
+        }
+        Ok(common::Response::from_parts(parts, common::Body::new(body)))
+    }
+
+    /// `DriveHub`を通さずにJSONのリクエストを送り、JSONのレスポンスを返す
+    ///
+    /// 冪等なメソッド（GET、PUT、DELETEなど）は一時的なエラーをリトライする。POSTはリトライしない。
+    /// 成功以外のステータスは`DriveHub`と同じエラーにし、[`Error::classify`]で分類する。
+    pub(crate) async fn request_json<T: serde::de::DeserializeOwned>(
+        &self,
+        method: hyper::Method,
+        url: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<T, Error> {
+        let body = Bytes::from(body.map(|b| b.to_string()).unwrap_or_default());
+        let send = || async {
+            let request = hyper::Request::builder()
+                .method(method.clone())
+                .uri(url)
+                .header(CONTENT_TYPE, "application/json; charset=UTF-8");
+            let rsp = self.send(request, body.clone()).await?;
+            if rsp.status().is_success() {
+                return Ok(rsp);
+            }
+            let (parts, bytes) = rsp.into_parts();
+            let e: Error = match serde_json::from_slice(&bytes) {
+                Ok(value) => google_drive3::Error::BadRequest(value),
+                Err(_) => google_drive3::Error::Failure(common::to_response(parts, Some(bytes))),
+            }
+            .into();
+            Err(e.classify())
+        };
+        let rsp = if method.is_idempotent() {
+            self.retry(send).await?
+        } else {
+            self.once(send).await?
+        };
+        Ok(serde_json::from_slice(rsp.body())?)
+    }
+
+    /// 認証ヘッダーを付けずにリクエストを送り、レスポンスのボディを全て読み込む
+    async fn send_without_auth(
+        &self,
+        request: hyper::http::request::Builder,
+        body: Bytes,
+    ) -> Result<hyper::Response<Bytes>, Error> {
+        self.throttle().await;
+        let request = request
+            .header(CONTENT_LENGTH, body.len())
+            .body(common::to_body(Some(body)))
+            .map_err(|_| Error::InternalError)?;
+        let rsp = self
+            .hub
+            .client
+            .request(request)
+            .await
+            .map_err(google_drive3::Error::HttpError)?;
+        let (parts, body) = rsp.into_parts();
+        let body = common::to_bytes(body).await.unwrap_or_default();
+        Ok(hyper::Response::from_parts(parts, body))
     }
 
     /// 指定されたフォルダ内のファイルとフォルダの一覧を取得
     ///
+    /// ゴミ箱にあるアイテムは含みません。
+    ///
     /// # 引数
     ///
     /// * `id` - 一覧を取得するフォルダのID
@@ -323,11 +1147,11 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, GDriveId};
+    /// use google_drive::{GDrive, GDriveId, scope};
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", None).await?;
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
     ///     let folder_id: GDriveId = "your_folder_id".into();
     ///
     ///     let items = drive.list(&folder_id).await?;
@@ -339,49 +1163,187 @@ impl GDrive {
     /// }
     /// ```
     pub async fn list(&self, id: &GDriveId) -> Result<Vec<GMeta>, Error> {
-        let query = format!("'{}' in parents", id);
-        let mut v = Vec::new();
-        let mut next = self.list_internal(&query, None, &mut v).await?;
-        while next.is_some() {
-            next = self.list_internal(&query, next, &mut v).await?;
-        }
-        Ok(v)
+        self.list_stream(id).try_collect().await
     }
 
-    /// 内部的なページング付きリスト取得関数
+    /// フォルダ内のアイテムを 1 つずつ返す`Stream`
+    ///
+    /// 次のページは、前のページのアイテムを全て読んだ後に取得します。
+    /// 途中で読むのをやめれば、残りのページは取得しません。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use futures::TryStreamExt;
+    /// use google_drive::{GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let folder_id: GDriveId = "your_folder_id".into();
+    ///
+    ///     let items = drive.list_stream(&folder_id);
+    ///     futures::pin_mut!(items);
+    ///     while let Some(item) = items.try_next().await? {
+    ///         println!("{}", item.name);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn list_stream<'a>(
+        &'a self,
+        id: &GDriveId,
+    ) -> impl Stream<Item = Result<GMeta, Error>> + use<'a> {
+        self.search_stream(&Self::children(id), &SearchOptions::default())
+    }
+
+    /// 取得するフィールドを指定してフォルダ内のアイテム一覧を取得
+    pub async fn list_with_fields(
+        &self,
+        id: &GDriveId,
+        fields: &MetaFields,
+    ) -> Result<Vec<GMeta>, Error> {
+        let opts = SearchOptions {
+            fields: Some(fields.clone()),
+            ..Default::default()
+        };
+        self.search_stream(&Self::children(id), &opts)
+            .try_collect()
+            .await
+    }
+
+    /// フォルダの直下にあり、ゴミ箱に無いアイテム
+    fn children(id: &GDriveId) -> Query {
+        Query::parent(id.clone()).and(Query::trashed(false))
+    }
+
+    /// クエリに一致するファイルとフォルダを検索する
+    ///
+    /// # 引数
+    ///
+    /// * `query` - 検索クエリ
+    /// * `opts` - 並び順、1 回に取得する件数、取得するフィールド
+    ///
+    /// # 戻り値
+    ///
+    /// 一致したアイテムのメタデータのリスト、または発生したエラー
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, OrderBy, Query, SearchOptions, SortKey, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///
+    ///     // 1 週間以内に更新したPDFを新しい順に
+    ///     let query = Query::mime_type("application/pdf")
+    ///         .and(Query::modified_after(chrono::Utc::now() - chrono::Duration::days(7)))
+    ///         .and(Query::trashed(false));
+    ///     let opts = SearchOptions {
+    ///         order_by: vec![OrderBy::Desc(SortKey::ModifiedTime)],
+    ///         ..Default::default()
+    ///     };
+    ///     for meta in drive.search(&query, &opts).await? {
+    ///         println!("{}", meta.name);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn search(&self, query: &Query, opts: &SearchOptions) -> Result<Vec<GMeta>, Error> {
+        self.search_stream(query, opts).try_collect().await
+    }
+
+    /// クエリに一致するアイテムを 1 つずつ返す`Stream`
+    ///
+    /// [`GDrive::list_stream`]と同じく、ページは必要になった時に取得します。
+    pub fn search_stream<'a>(
+        &'a self,
+        query: &Query,
+        opts: &SearchOptions,
+    ) -> impl Stream<Item = Result<GMeta, Error>> + use<'a> {
+        let query: Arc<str> = query.to_string().into();
+        let opts = Arc::new(opts.clone());
+        // None: 最後のページまで取得した、Some(token): 次に取得するページ
+        stream::try_unfold(Some(None), move |next| {
+            let query = query.clone();
+            let opts = opts.clone();
+            async move {
+                let Some(token) = next else {
+                    return Ok::<_, Error>(None);
+                };
+                let (items, token) = self.list_internal(&query, &opts, token).await?;
+                Ok(Some((items, token.map(Some))))
+            }
+        })
+        .map_ok(|items| stream::iter(items.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// 1 ページ取得し、アイテムと次のページのトークンを返す
     async fn list_internal(
         &self,
         query: &str,
+        opts: &SearchOptions,
         next_page_token: Option<String>,
-        meta: &mut Vec<GMeta>,
-    ) -> Result<Option<String>, Error> {
-        let x = self
-            .0
-            .files()
-            .list()
-            .q(query)
-            .param(
-                "fields",
-                "nextPageToken, files(id,name,mimeType,modifiedTime,capabilities(canDownload))",
-            )
-            .include_items_from_all_drives(true)
-            .supports_all_drives(true)
-            .add_scopes(SCOPES);
-        let x = match next_page_token {
-            Some(ref y) => x.page_token(y),
-            None => x,
-        };
-        let (rsp, flist) = x.doit().await?;
+    ) -> Result<(Vec<GMeta>, Option<String>), Error> {
+        let fields = opts.fields.as_ref().unwrap_or(&self.meta_fields);
+        let order_by = opts.order_by();
+        let (rsp, flist) = self
+            .retry(|| {
+                let x = self
+                    .hub
+                    .files()
+                    .list()
+                    .param(
+                        "fields",
+                        &format!("nextPageToken, files({})", fields.fields()),
+                    )
+                    .include_items_from_all_drives(true)
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes);
+                // 条件の無いクエリは`q`を付けない。空の`q`はAPIがエラーにする
+                let x = match query {
+                    "" => x,
+                    q => x.q(q),
+                };
+                let x = match next_page_token {
+                    Some(ref y) => x.page_token(y),
+                    None => x,
+                };
+                let x = match &order_by {
+                    Some(order_by) => x.order_by(order_by),
+                    None => x,
+                };
+                let x = match opts.page_size() {
+                    Some(n) => x.page_size(n),
+                    None => x,
+                };
+                let x = match opts.corpus.corpora() {
+                    Some(corpora) => x.corpora(corpora),
+                    None => x,
+                };
+                let x = match &opts.corpus {
+                    Corpus::Drive(id) => x.drive_id(id.as_ref()),
+                    _ => x,
+                };
+                async { Ok(x.doit().await?) }
+            })
+            .await?;
 
         if !rsp.status().is_success() {
             return Err(Error::InvalidResponse(rsp.status().as_u16()));
         }
-        if let Some(files) = flist.files {
-            for file in files {
-                meta.push(GMeta::new(file)?);
-            }
-        }
-        Ok(flist.next_page_token)
+        let items = flist
+            .files
+            .unwrap_or_default()
+            .into_iter()
+            .map(GMeta::new)
+            .collect::<Result<_, _>>()?;
+        Ok((items, flist.next_page_token))
     }
 
     /// ファイルまたはフォルダのメタデータを取得
@@ -397,42 +1359,72 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, GDriveId};
+    /// use google_drive::{GDrive, GDriveId, scope};
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", None).await?;
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
     ///     let file_id: GDriveId = "your_file_id".into();
     ///
     ///     let meta = drive.get_meta(&file_id).await?;
     ///     println!("名前: {}", meta.name);
     ///     println!("MIMEタイプ: {}", meta.mime_type);
-    ///     println!("変更日時: {}", meta.modified_time);
+    ///     println!("変更日時: {:?}", meta.modified_time);
     ///
     ///     Ok(())
     /// }
     /// ```
     pub async fn get_meta(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.get_meta_with_fields(id, &self.meta_fields).await
+    }
+
+    /// 取得するフィールドを指定してメタデータを取得
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, GDriveId, MetaFields, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let file_id: GDriveId = "your_file_id".into();
+    ///
+    ///     let meta = drive.get_meta_with_fields(&file_id, &MetaFields::custom("starred,owners")).await?;
+    ///     println!("スター: {:?}, 所有者: {:?}", meta.starred, meta.owners);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn get_meta_with_fields(
+        &self,
+        id: &GDriveId,
+        fields: &MetaFields,
+    ) -> Result<GMeta, Error> {
         let (rsp, file) = self
-            .0
-            .files()
-            .get(id.as_ref())
-            .param(
-                "fields",
-                "id,name,mimeType,modifiedTime,capabilities(canDownload)",
-            )
-            .supports_all_drives(true)
-            .add_scopes(SCOPES)
-            .doit()
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .files()
+                    .get(id.as_ref())
+                    .param("fields", fields.fields())
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
             .await?;
         if !rsp.status().is_success() {
             return Err(Error::InvalidResponse(rsp.status().as_u16()));
         }
-        Ok(GMeta::new(file)?)
+        GMeta::new(file)
     }
 
     /// カスタムハンドラを使用してファイルをダウンロード
     ///
+    /// `set_size`にはメタデータのサイズを渡します。
+    /// 受信中に接続が切れた場合は、受信済みの位置から`Range`で続きを要求します。
+    ///
     /// # 引数
     ///
     /// * `id` - ダウンロードするファイルのID
@@ -445,7 +1437,7 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, GDriveId, DownloadHandler, Error};
+    /// use google_drive::{GDrive, GDriveId, DownloadHandler, Error, scope};
     /// use hyper::body::Bytes;
     /// use std::sync::Arc;
     ///
@@ -473,7 +1465,7 @@ impl GDrive {
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", None).await?;
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
     ///     let file_id: GDriveId = "your_file_id".into();
     ///
     ///     let data = Arc::new(tokio::sync::Mutex::new(Vec::new()));
@@ -491,29 +1483,86 @@ impl GDrive {
     where
         H: DownloadHandler + Sync + Send + 'static,
     {
-        let (mut rsp, _file) = self
-            .0
-            .files()
-            .get(id.as_ref())
-            .param("alt", "media")
-            .supports_all_drives(true)
-            .add_scopes(SCOPES)
-            .doit()
+        // レスポンスのサイズは当てにならないため、メタデータのサイズを使う
+        let meta = self.transfer_meta(id).await?;
+        handler.set_size(meta.size.unwrap_or(0) as usize).await?;
+        let handler = Arc::new(Hashing::new(handler, Checksum::new(&meta)));
+        self.download_range(id, 0, meta.size, handler.clone())
             .await?;
+        handler.verify()
+    }
 
-        if !rsp.status().is_success() {
-            return Err(Error::InvalidResponse(rsp.status().as_u16()));
-        }
+    /// Google Apps形式のファイルを指定したMIMEタイプでエクスポートし、カスタムハンドラで受け取る
+    ///
+    /// エクスポートできるサイズは10MBまでです。
+    ///
+    /// # 引数
+    ///
+    /// * `id` - エクスポートするファイルのID
+    /// * `mime_type` - エクスポート先のMIMEタイプ（[`ExportFormat::mime_type`]など）
+    /// * `handler` - エクスポートしたデータを処理するハンドラ
+    ///
+    /// # 戻り値
+    ///
+    /// 成功した場合は`Ok(())`、失敗した場合はエラー
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{ExportFormat, GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let file_id: GDriveId = "your_document_id".into();
+    ///
+    ///     // MyHandlerはdownloadの例を参照
+    ///     let data = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+    ///     drive.export(&file_id, ExportFormat::Pdf.mime_type(), MyHandler(data.clone())).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn export<H>(&self, id: &GDriveId, mime_type: &str, handler: H) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let rsp = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .files()
+                    .export(id.as_ref(), mime_type)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
         let hint = rsp.size_hint();
-        let size = hint.upper().unwrap_or(hint.lower()) as usize;
-        handler.set_size(size).await?;
+        handler
+            .set_size(hint.upper().unwrap_or(hint.lower()) as usize)
+            .await?;
+        Self::receive(rsp, Arc::new(handler)).await.1
+    }
 
+    /// レスポンスのボディをハンドラに渡す
+    ///
+    /// 失敗した場合も、それまでにハンドラに渡したバイト数を返す。
+    async fn receive<H>(
+        mut rsp: google_drive3::common::Response,
+        handler: Arc<H>,
+    ) -> (u64, Result<(), Error>)
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        if !rsp.status().is_success() {
+            return (0, Err(Error::InvalidResponse(rsp.status().as_u16())));
+        }
+        let mut received = 0;
         let (tx, mut rx) = tokio::sync::mpsc::channel(16);
         let task = tokio::task::spawn(async move {
             while let Some(data) = rx.recv().await {
-                if let Err(e) = handler.write(data).await {
-                    return Err(e);
-                }
+                handler.write(data).await?;
             }
             Ok(())
         });
@@ -525,22 +1574,42 @@ impl GDrive {
             })
             .await
             {
-                Some(frame) => {
-                    let frame = frame?;
-                    let data = frame.into_data().unwrap();
-                    if let Err(_) = tx.send(data).await {
-                        return Err(Error::InternalError);
+                Some(Ok(frame)) => {
+                    // データ以外のフレーム（トレイラー）は無視する
+                    let Ok(data) = frame.into_data() else {
+                        continue;
+                    };
+                    let len = data.len() as u64;
+                    if tx.send(data).await.is_err() {
+                        break;
                     }
+                    received += len;
+                }
+                Some(Err(e)) => {
+                    drop(tx);
+                    // 受信できた分の書き込みを待ってから返す
+                    return match task.await {
+                        Ok(Ok(())) => (received, Err(e.into())),
+                        Ok(Err(e)) => (received, Err(e)),
+                        Err(_) => (received, Err(Error::InternalError)),
+                    };
                 }
                 None => break,
             }
         }
         drop(tx);
-        task.await.map_err(|_| Error::InternalError)?
+        let result = task.await.unwrap_or(Err(Error::InternalError));
+        (received, result)
     }
 
     /// ファイルをダウンロードして指定したパスに保存
     ///
+    /// Google Apps形式のファイルは[`GMeta::default_export`]の形式でエクスポートします。
+    /// 保存先のファイル名には拡張子が付かないため、必要なら[`GMeta::local_name`]を使ってください。
+    ///
+    /// `<file>.part`に書き込み、チェックサムを確認してから`file`に名前を変更します。
+    /// チェックサムが一致しない場合は`Error::ChecksumMismatch`を返し、`file`は変更しません。
+    ///
     /// # 引数
     ///
     /// * `id` - ダウンロードするファイルのID
@@ -553,11 +1622,11 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, GDriveId};
+    /// use google_drive::{GDrive, GDriveId, scope};
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", None).await?;
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
     ///     let file_id: GDriveId = "your_file_id".into();
     ///
     ///     drive.download_and_save(&file_id, "./downloads/myfile.pdf").await?;
@@ -571,28 +1640,60 @@ impl GDrive {
         id: &GDriveId,
         file: P,
     ) -> Result<(), Error> {
-        let file = file.as_ref();
+        backend::download_and_save(self, id, file).await
+    }
+
+    /// Google Apps形式のファイルをエクスポートして指定したパスに保存
+    ///
+    /// `<file>.part`に書き込み、完了してから`file`に名前を変更します。
+    ///
+    /// # 引数
+    ///
+    /// * `id` - エクスポートするファイルのID
+    /// * `format` - エクスポート形式
+    /// * `file` - 保存先のファイルパス
+    ///
+    /// # 戻り値
+    ///
+    /// 成功した場合は`Ok(())`、失敗した場合はエラー
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{ExportFormat, GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let file_id: GDriveId = "your_document_id".into();
+    ///
+    ///     drive.export_and_save(&file_id, ExportFormat::Markdown, "./downloads/doc.md").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn export_and_save<P: AsRef<Path>>(
+        &self,
+        id: &GDriveId,
+        format: ExportFormat,
+        file: P,
+    ) -> Result<(), Error> {
+        backend::export_and_save(self, id, format, file).await
+    }
+
+    /// ダウンロードに必要なフィールドを含むメタデータを取得する
+    pub(crate) async fn transfer_meta(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.get_meta_with_fields(id, self.meta_fields.for_transfer())
+            .await
+    }
+
+    /// ファイルに書き込むハンドラを作る
+    pub(crate) async fn file_handler(file: &Path) -> Result<FileWriter, Error> {
         if let Some(dir) = file.parent() {
             tokio::fs::create_dir_all(dir).await?;
         }
         let file = tokio::fs::File::create(file).await?;
-        struct X(Arc<tokio::sync::Mutex<tokio::fs::File>>);
-        impl DownloadHandler for X {
-            fn set_size(&self, _size: usize) -> impl Future<Output = Result<(), Error>> + Send {
-                async { Ok(()) }
-            }
-            fn write(&self, b: Bytes) -> impl Future<Output = Result<(), Error>> + Send {
-                let file = self.0.clone();
-                async move {
-                    use tokio::io::AsyncWriteExt;
-                    let mut f = file.lock().await;
-                    f.write_all(&b).await?;
-                    Ok(())
-                }
-            }
-        }
-        let handler = X(Arc::new(tokio::sync::Mutex::new(file)));
-        self.download(id, handler).await
+        Ok(FileWriter::new(file))
     }
 
     /// ファイルをダウンロードしてバイト配列として返す
@@ -608,11 +1709,11 @@ impl GDrive {
     /// # 例
     ///
     /// ```skip
-    /// use google_drive::{GDrive, GDriveId};
+    /// use google_drive::{GDrive, GDriveId, scope};
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", None).await?;
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
     ///     let file_id: GDriveId = "your_file_id".into();
     ///
     ///     let data = drive.download_as_binary(&file_id).await?;
@@ -649,132 +1750,276 @@ impl GDrive {
     }
 }
 
-//-------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
 
-/// 認証の開始と終了の通知を受ける
-pub trait AuthListener {
-    fn on_auth_start(&self);
-    fn on_auth_complete(&self, error_message: Option<String>);
-}
+    use super::*;
+    use crate::test_server::{self, TestServer};
 
-pub struct CustomServerFlowDelegate<L: AuthListener> {
-    port: u16,
-    server: Arc<Mutex<MyServer>>,
-    listener: Arc<L>,
-}
+    #[tokio::test]
+    async fn access_token_is_sent_as_bearer() {
+        let server = TestServer::start(|_| {
+            test_server::Response::json(
+                200,
+                r#"{
+                    "id": "file-id",
+                    "name": "a.txt",
+                    "mimeType": "text/plain",
+                    "modifiedTime": "2025-01-01T00:00:00Z",
+                    "capabilities": {"canDownload": true}
+                }"#,
+            )
+        })
+        .await;
+        let mut drive = GDrive::from_access_token("ya29.token", &[scope::READONLY]);
+        drive.set_root_url(&server.url);
 
-struct MyServer {}
-
-impl MyServer {
-    pub async fn new<L: AuthListener>(
-        port: Option<u16>,
-        listener: Arc<L>,
-    ) -> std::io::Result<Self> {
-        use tokio::net::TcpListener;
-        use tokio::sync::oneshot;
-        let addr = ("localhost", port.unwrap_or(0));
-        let tcp_listener = TcpListener::bind(addr).await?;
-
-        let port = tcp_listener.local_addr()?.port();
-        let (shutdown_tx, shutdown) = oneshot::channel();
-
-        let handle = tokio::spawn(async move {
-            loop {
-                let Some(x) = tokio::select!(
-                    _ = shutdown => None,
-                    x = tcp_listener.accept() => Some(x),
-                ) else {
-                    break;
-                };
-                match x {
-                    Ok((stream, _)) => {}
-                    Err(_) => {}
-                }
-            }
-        });
+        let meta = drive.get_meta(&"file-id".into()).await.unwrap();
+        assert_eq!(meta.name, "a.txt");
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/drive/v3/files/file-id");
+        assert_eq!(
+            requests[0].header("Authorization"),
+            Some("Bearer ya29.token")
+        );
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn logout_revokes_and_removes_saved_token() {
+        let server = TestServer::start(|_| test_server::Response::new(200)).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        std::fs::write(
+            &path,
+            r#"[{"scopes":["s"],"token":{"access_token":"a","refresh_token":"1//r+t","expires_at":null,"id_token":null}}]"#,
+        )
+        .unwrap();
+        let mut drive = GDrive::from_access_token("a", &[scope::READONLY])
+            .with_tokens(Tokens::new(TokenStore::File(path.clone())));
+        drive.set_root_url(&server.url);
+
+        drive.logout().await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/revoke");
+        assert_eq!(requests[0].body, b"token=1%2F%2Fr%2Bt");
+        assert_eq!(requests[0].header("Authorization"), None);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn logout_ignores_already_revoked_token() {
+        let server =
+            TestServer::start(|_| test_server::Response::json(400, r#"{"error":"invalid_token"}"#))
+                .await;
+        let mut drive = GDrive::from_access_token("a", &[scope::READONLY]);
+        drive.set_root_url(&server.url);
+        drive.logout().await.unwrap();
+        assert_eq!(server.requests()[0].body, b"token=a");
+    }
+
+    #[tokio::test]
+    async fn missing_optional_fields_are_none() {
+        let server = TestServer::start(|_| {
+            test_server::Response::json(
+                200,
+                r#"{"id": "file-id", "name": "a.txt", "mimeType": "text/plain"}"#,
+            )
+        })
+        .await;
+        let mut drive = server.drive();
+        drive.set_meta_fields(MetaFields::Minimal);
+
+        let meta = drive.get_meta(&"file-id".into()).await.unwrap();
+        assert_eq!(meta.modified_time, None);
+        assert_eq!(meta.can_download, None);
+        assert!(meta.parents.is_empty());
+        assert_eq!(
+            server.requests()[0].query("fields"),
+            Some("id,name,mimeType")
+        );
+    }
+
+    #[tokio::test]
+    async fn full_fields_are_parsed() {
+        let server = TestServer::start(|_| {
+            test_server::Response::json(
+                200,
+                r#"{
+                    "id": "shortcut-id",
+                    "name": "report",
+                    "mimeType": "application/vnd.google-apps.shortcut",
+                    "createdTime": "2024-12-31T00:00:00Z",
+                    "parents": ["folder-id"],
+                    "owners": [{"displayName": "Alice", "emailAddress": "alice@example.com", "me": true}],
+                    "trashed": false,
+                    "starred": true,
+                    "webViewLink": "https://drive.google.com/file/d/shortcut-id/view",
+                    "shortcutDetails": {"targetId": "target-id", "targetMimeType": "application/pdf"},
+                    "exportLinks": {"application/pdf": "https://example.com/export"}
+                }"#,
+            )
+        })
+        .await;
+        let mut drive = server.drive();
+        drive.set_meta_fields(MetaFields::Full);
+
+        let meta = drive.get_meta(&"shortcut-id".into()).await.unwrap();
+        assert!(meta.is_shortcut());
+        assert_eq!(
+            meta.created_time,
+            Some("2024-12-31T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(meta.parents, [GDriveId::from("folder-id")]);
+        assert_eq!(
+            meta.owners[0].email_address.as_deref(),
+            Some("alice@example.com")
+        );
+        assert_eq!(meta.owners[0].me, Some(true));
+        assert_eq!(meta.trashed, Some(false));
+        assert_eq!(meta.starred, Some(true));
+        assert_eq!(
+            meta.shortcut_details,
+            Some(ShortcutDetails {
+                target_id: "target-id".into(),
+                target_mime_type: Some("application/pdf".into()),
+            })
+        );
+        assert_eq!(meta.export_links.len(), 1);
+        let fields = server.requests()[0].query("fields").unwrap().to_string();
+        assert!(fields.contains("owners("));
+        assert!(fields.contains("exportLinks"));
     }
 
-    async fn handle_request(stream: tokio::net::TcpStream) -> Result<String, String> {
-        use tokio::io::BufStream;
-        use tokio::io::AsyncReadExt;
+    #[test]
+    fn custom_fields_always_include_required_fields() {
+        assert_eq!(
+            MetaFields::custom("size, id ,starred").fields(),
+            "name,mimeType,size,id,starred"
+        );
+        assert_eq!(MetaFields::custom("").fields(), "id,name,mimeType");
+    }
 
-        let mut buf = [0; 2048];
-        let mut stream = BufStream::new(stream);
-        let x = stream.read_buf(&mut buf);
+    #[tokio::test]
+    async fn search_sends_query_and_options() {
+        let server =
+            TestServer::start(|_| test_server::Response::json(200, r#"{"files": []}"#)).await;
+        let drive = server.drive();
 
-        // リクエストヘッダー読み込み
-        let Ok(n) = stream.read(&mut buf).await {
-            return Err("Fail to read response".into());
+        drive.list(&"it's".into()).await.unwrap();
+        let opts = SearchOptions {
+            order_by: vec![OrderBy::Desc(SortKey::ModifiedTime)],
+            page_size: Some(50),
+            fields: Some(MetaFields::Minimal),
+            corpus: Corpus::AllDrives,
         };
-        let request = String::from_utf8_lossy(&buf[..n]);
-
-        // URLからクエリパラメータ抽出
-        let request_line = request.lines().next().ok_or("無効なリクエスト")?;
-        let path_and_query = request_line
-            .split_whitespace()
-            .nth(1)
-            .ok_or("パスが見つかりません")?;
-
-        // クエリパラメータ解析
-        let query = if let Some(idx) = path_and_query.find('?') {
-            &path_and_query[idx + 1..]
-        } else {
-            ""
+        drive
+            .search(&Query::name_contains("report"), &opts)
+            .await
+            .unwrap();
+        let opts = SearchOptions {
+            page_size: Some(u32::MAX),
+            ..Default::default()
         };
+        drive.search(&Query::And(vec![]), &opts).await.unwrap();
 
-        // URLデコード
-        let decoded_query = urlencoding::decode(query).unwrap_or_default().to_string();
-
-        // 認証コードまたはエラー抽出
-        let mut code = None;
-        let mut error = None;
+        let requests = server.requests();
+        assert_eq!(requests[2].query("q"), None);
+        assert_eq!(requests[2].query("pageSize"), Some("1000"));
+        assert_eq!(
+            requests[0].query("q"),
+            Some(r"'it\'s' in parents and trashed = false")
+        );
+        assert_eq!(requests[1].query("q"), Some("name contains 'report'"));
+        assert_eq!(requests[1].query("orderBy"), Some("modifiedTime desc"));
+        assert_eq!(requests[1].query("pageSize"), Some("50"));
+        assert_eq!(requests[1].query("corpora"), Some("allDrives"));
+        assert_eq!(
+            requests[1].query("fields"),
+            Some("nextPageToken, files(id,name,mimeType)")
+        );
+    }
 
-        for param in decoded_query.split('&') {
-            if let Some((key, value)) = param.split_once('=') {
-                if key == "code" {
-                    code = Some(value.to_string());
-                } else if key == "error" {
-                    error = Some(value.to_string());
-                }
+    /// `total`個のアイテムを`pageSize`（既定は 2）ずつ返すサーバー。`fail_at`のページは 404 を返す
+    async fn paged_server(total: usize, fail_at: Option<&'static str>) -> TestServer {
+        TestServer::start(move |request| {
+            if request.query("pageToken") == fail_at && fail_at.is_some() {
+                return test_server::Response::json(404, r#"{"error": {"code": 404}}"#);
             }
-        }
-
-        // 結果をチャネルを通じて通知
-        let mut result_tx = server.result_tx.lock().await;
-        if let Some(tx) = result_tx.take() {
-            match (code, error) {
-                (Some(code), _) => {
-                    let _ = tx.send(Ok(code));
-                }
-                (_, Some(error)) => {
-                    let _ = tx.send(Err(error));
-                }
-                _ => {
-                    let _ = tx.send(Err("認証コードもエラーも見つかりませんでした".to_string()));
-                }
+            let size: usize = request.query("pageSize").map_or(2, |n| n.parse().unwrap());
+            let start: usize = request.query("pageToken").map_or(0, |t| t.parse().unwrap());
+            let end = (start + size).min(total);
+            let files: Vec<_> = (start..end)
+                .map(|i| {
+                    serde_json::json!({
+                        "id": format!("id-{}", i),
+                        "name": format!("file-{}", i),
+                        "mimeType": "text/plain",
+                    })
+                })
+                .collect();
+            let mut body = serde_json::json!({ "files": files });
+            if end < total {
+                body["nextPageToken"] = end.to_string().into();
             }
-        }
+            test_server::Response::json(200, &body)
+        })
+        .await
+    }
+
+    fn page_tokens(server: &TestServer) -> Vec<Option<String>> {
+        server
+            .requests()
+            .iter()
+            .map(|r| r.query("pageToken").map(String::from))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_collects_all_pages() {
+        let server = paged_server(5, None).await;
+        let drive = server.drive();
 
-        // 確認ページを返信
-        let response = format!(
-            "HTTP/1.1 200 OK\r\n\
-             Content-Type: text/html; charset=utf-8\r\n\
-             Connection: close\r\n\
-             \r\n\
-             <html>\
-             <head><title>認証完了</title></head>\
-             <body>\
-             <h1>認証が完了しました</h1>\
-             <p>このページを閉じて、アプリケーションに戻ってください。</p>\
-             </body>\
-             </html>"
+        let items = drive.list(&"folder-id".into()).await.unwrap();
+        let names: Vec<_> = items.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["file-0", "file-1", "file-2", "file-3", "file-4"]);
+        assert_eq!(
+            page_tokens(&server),
+            [None, Some("2".into()), Some("4".into())]
         );
+    }
 
-        stream.write_all(response.as_bytes()).await?;
-        stream.flush().await?;
+    #[tokio::test]
+    async fn stream_fetches_pages_on_demand() {
+        let server = paged_server(100, None).await;
+        let drive = server.drive();
+        let opts = SearchOptions {
+            page_size: Some(3),
+            ..Default::default()
+        };
 
-        Ok(())
+        let items = drive.search_stream(&Query::full_text("file"), &opts);
+        futures::pin_mut!(items);
+        assert_eq!(items.try_next().await.unwrap().unwrap().name, "file-0");
+        assert_eq!(server.requests().len(), 1);
+        for _ in 0..3 {
+            items.try_next().await.unwrap().unwrap();
+        }
+        // 4 つ目を読むまで 2 ページ目は取得しない
+        assert_eq!(page_tokens(&server), [None, Some("3".into())]);
+    }
+
+    #[tokio::test]
+    async fn stream_stops_at_failed_page() {
+        let server = paged_server(5, Some("2")).await;
+        let drive = server.drive();
+
+        let items: Vec<_> = drive.list_stream(&"folder-id".into()).collect().await;
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok() && items[1].is_ok());
+        assert!(items[2].is_err());
+        assert!(drive.list(&"folder-id".into()).await.is_err());
     }
 }
@@ -0,0 +1,557 @@
+//! フォルダの作成、名前の変更、移動、コピー、ゴミ箱、削除
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use futures::TryStreamExt;
+use google_drive3::{api::File, hyper::Method};
+
+use crate::{
+    DuplicatePolicy, Error, GDrive, GDriveId, GMeta, MetaFields, OrderBy, Query, SearchOptions,
+    SortKey, path::parse_path,
+};
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+impl GDrive {
+    /// フォルダ`parent`に`name`という名前のフォルダを作成する
+    ///
+    /// 同じ名前のフォルダがあっても新しく作成します。
+    /// 作成された後にエラーになった可能性があるため、失敗してもリトライしません。
+    ///
+    /// # 戻り値
+    ///
+    /// 作成したフォルダのメタデータ
+    pub async fn create_folder(&self, parent: &GDriveId, name: &str) -> Result<GMeta, Error> {
+        let url = format!(
+            "{}drive/v3/files?supportsAllDrives=true&fields={}",
+            self.root_url,
+            urlencoding::encode(self.meta_fields.fields())
+        );
+        let body = serde_json::json!({
+            "name": name,
+            "mimeType": FOLDER_MIME_TYPE,
+            "parents": [parent.as_ref()],
+        });
+        let file: File = self.request_json(Method::POST, &url, Some(&body)).await?;
+        GMeta::new(file)
+    }
+
+    /// パスのフォルダを、途中のフォルダも含めて作成する（`mkdir -p`）
+    ///
+    /// パスの形式は[`GDrive::resolve_path`]と同じです。既にあるフォルダはそのまま使い、
+    /// 同じ名前のフォルダが複数ある場合は最も古いものを使います。
+    ///
+    /// # 戻り値
+    ///
+    /// 最後のフォルダのメタデータ
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, UploadTarget, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///
+    ///     let folder = drive.create_folders("My Drive/Reports/2025").await?;
+    ///     let target = UploadTarget::New { parent: folder.id, name: "q1.csv".into() };
+    ///     drive.upload(&target, "text/csv", b"a,b\n1,2\n".to_vec()).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_folders(&self, path: &str) -> Result<GMeta, Error> {
+        let (root, names) = parse_path(path);
+        let mut current = self.path_root(&root, path, DuplicatePolicy::Oldest).await?;
+        let mut created = false;
+        for name in names {
+            // 作成したフォルダの中には何も無いので探さない
+            (current, created) = self.ensure_folder(&current.id, name, !created).await?;
+        }
+        Ok(current)
+    }
+
+    /// `parent`の中の`name`フォルダを返す。無ければ作成し、作成したかどうかも返す
+    ///
+    /// 作成が一時的なエラーで失敗した場合は、サーバーでは作成されている可能性があるため、
+    /// 探し直してから作成し直します。
+    async fn ensure_folder(
+        &self,
+        parent: &GDriveId,
+        name: &str,
+        mut search: bool,
+    ) -> Result<(GMeta, bool), Error> {
+        let mut attempt = 1;
+        loop {
+            if search && let Some(folder) = self.find_folder(parent, name).await? {
+                return Ok((folder, false));
+            }
+            match self.create_folder(parent, name).await {
+                Err(e) if attempt < self.retry.max_attempts && e.is_retryable() => {
+                    tokio::time::sleep(self.retry_wait(&e, attempt)).await;
+                    attempt += 1;
+                    search = true;
+                }
+                result => return result.map(|folder| (folder, true)),
+            }
+        }
+    }
+
+    /// `parent`の中の`name`フォルダのうち最も古いもの
+    async fn find_folder(&self, parent: &GDriveId, name: &str) -> Result<Option<GMeta>, Error> {
+        let query = Query::parent(parent.clone())
+            .and(Query::name_eq(name))
+            .and(Query::folder())
+            .and(Query::trashed(false));
+        let opts = SearchOptions {
+            order_by: vec![OrderBy::Asc(SortKey::CreatedTime)],
+            page_size: Some(1),
+            ..Default::default()
+        };
+        let found = self.search_stream(&query, &opts);
+        futures::pin_mut!(found);
+        found.try_next().await
+    }
+
+    /// 名前を変更する
+    pub async fn rename(&self, id: &GDriveId, name: &str) -> Result<GMeta, Error> {
+        let file = File {
+            name: Some(name.into()),
+            ..Default::default()
+        };
+        self.update_file(id, file, None, None).await
+    }
+
+    /// 別のフォルダに移動する
+    ///
+    /// 今の親フォルダからは全て取り除き、`parent`だけを親にします。
+    pub async fn move_to(&self, id: &GDriveId, parent: &GDriveId) -> Result<GMeta, Error> {
+        let current = self
+            .get_meta_with_fields(id, &MetaFields::custom("parents"))
+            .await?;
+        let remove: Vec<&str> = current.parents.iter().map(AsRef::as_ref).collect();
+        let remove = remove.join(",");
+        let remove = (!remove.is_empty()).then_some(remove.as_str());
+        self.update_file(id, File::default(), Some(parent.as_ref()), remove)
+            .await
+    }
+
+    /// サーバー上でファイルをコピーする
+    ///
+    /// フォルダはコピーできません。
+    /// コピーが作成された後にエラーになった可能性があるため、失敗してもリトライしません。
+    ///
+    /// # 引数
+    ///
+    /// * `id` - コピーするファイルのID
+    /// * `parent` - コピー先のフォルダ。`None`なら元のファイルと同じフォルダ
+    /// * `name` - コピーの名前。`None`なら元のファイルと同じ名前
+    ///
+    /// # 戻り値
+    ///
+    /// 作成したコピーのメタデータ
+    pub async fn copy(
+        &self,
+        id: &GDriveId,
+        parent: Option<&GDriveId>,
+        name: Option<&str>,
+    ) -> Result<GMeta, Error> {
+        let file = File {
+            name: name.map(Into::into),
+            parents: parent.map(|p| vec![p.to_string()]),
+            ..Default::default()
+        };
+        let (_, file) = self
+            .once(|| async {
+                Ok(self
+                    .hub
+                    .files()
+                    .copy(file, id.as_ref())
+                    .param("fields", self.meta_fields.fields())
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        GMeta::new(file)
+    }
+
+    /// ゴミ箱に移動する
+    pub async fn trash(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.set_trashed(id, true).await
+    }
+
+    /// ゴミ箱から元に戻す
+    pub async fn untrash(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.set_trashed(id, false).await
+    }
+
+    async fn set_trashed(&self, id: &GDriveId, trashed: bool) -> Result<GMeta, Error> {
+        let file = File {
+            trashed: Some(trashed),
+            ..Default::default()
+        };
+        self.update_file(id, file, None, None).await
+    }
+
+    /// ゴミ箱を通さずに完全に削除する
+    ///
+    /// フォルダを削除すると中のアイテムも全て削除されます。元に戻せません。
+    ///
+    /// # 戻り値
+    ///
+    /// 削除する直前のメタデータ
+    pub async fn delete(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        let meta = self.get_meta(id).await?;
+        let attempts = AtomicU32::new(0);
+        let result = self
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                self.hub
+                    .files()
+                    .delete(id.as_ref())
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?;
+                Ok(())
+            })
+            .await;
+        match result {
+            // 前の試行で削除された後にエラーになっていた
+            Err(Error::NotFound(_)) if attempts.into_inner() > 1 => {}
+            result => result?,
+        }
+        Ok(meta)
+    }
+
+    /// メタデータを変更する
+    async fn update_file(
+        &self,
+        id: &GDriveId,
+        file: File,
+        add_parents: Option<&str>,
+        remove_parents: Option<&str>,
+    ) -> Result<GMeta, Error> {
+        let (_, file) = self
+            .retry(|| {
+                let call = self
+                    .hub
+                    .files()
+                    .update(file.clone(), id.as_ref())
+                    .param("fields", self.meta_fields.fields())
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes);
+                let call = match add_parents {
+                    Some(p) => call.add_parents(p),
+                    None => call,
+                };
+                let call = match remove_parents {
+                    Some(p) => call.remove_parents(p),
+                    None => call,
+                };
+                async { Ok(call.doit_without_upload().await?) }
+            })
+            .await?;
+        GMeta::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        RetryPolicy,
+        test_server::{Request, Response, TestServer},
+    };
+
+    /// Drive APIの`files`の代わりをするメモリ上のファイル一覧
+    ///
+    /// `readonly`のアイテムを変更しようとすると 403 を返す。
+    #[derive(Default)]
+    struct FakeFiles {
+        files: BTreeMap<String, Value>,
+        next_id: usize,
+        /// この回数だけ、POSTとDELETEを処理した後に 503 を返す
+        fail_after_commit: usize,
+    }
+
+    impl FakeFiles {
+        fn add(&mut self, id: &str, name: &str, mime_type: &str, parent: &str) {
+            self.files.insert(
+                id.into(),
+                json!({"id": id, "name": name, "mimeType": mime_type, "parents": [parent], "trashed": false}),
+            );
+        }
+
+        fn new_id(&mut self) -> String {
+            self.next_id += 1;
+            format!("new-{}", self.next_id)
+        }
+
+        fn handle(&mut self, request: &Request) -> Response {
+            let response = self.apply(request);
+            if matches!(request.method.as_str(), "POST" | "DELETE")
+                && response.status < 300
+                && self.fail_after_commit > 0
+            {
+                self.fail_after_commit -= 1;
+                return Response::json(503, json!({"error": {"code": 503, "message": "backend"}}));
+            }
+            response
+        }
+
+        fn apply(&mut self, request: &Request) -> Response {
+            let error = |code: u16, message: &str| {
+                Response::json(code, json!({"error": {"code": code, "message": message}}))
+            };
+            let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+            let path = request.path.trim_start_matches("/drive/v3/files");
+            let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+            match (request.method.as_str(), segments.as_slice()) {
+                ("GET", []) => {
+                    let files: Vec<_> = self
+                        .files
+                        .values()
+                        .filter(|f| matches(f, request.query("q").unwrap_or_default()))
+                        .cloned()
+                        .collect();
+                    Response::json(200, json!({"files": files}))
+                }
+                ("POST", []) => {
+                    let id = self.new_id();
+                    let mut file = body;
+                    file["id"] = id.clone().into();
+                    file["trashed"] = false.into();
+                    self.files.insert(id, file.clone());
+                    Response::json(200, file)
+                }
+                (_, [id, ..]) if !self.files.contains_key(*id) => {
+                    error(404, &format!("File not found: {}", id))
+                }
+                ("GET", [id]) => Response::json(200, &self.files[*id]),
+                (_, [id, ..]) if id.starts_with("readonly") => error(
+                    403,
+                    "The user does not have sufficient permissions for this file.",
+                ),
+                ("PATCH", [id]) => {
+                    let file = self.files.get_mut(*id).unwrap();
+                    for (key, value) in body.as_object().unwrap() {
+                        file[key] = value.clone();
+                    }
+                    let mut parents: Vec<Value> =
+                        file["parents"].as_array().cloned().unwrap_or_default();
+                    if let Some(remove) = request.query("removeParents") {
+                        parents.retain(|p| !remove.split(',').any(|r| p == r));
+                    }
+                    if let Some(add) = request.query("addParents") {
+                        parents.push(add.into());
+                    }
+                    file["parents"] = parents.into();
+                    Response::json(200, &*file)
+                }
+                ("POST", [id, "copy"]) => {
+                    let mut file = self.files[*id].clone();
+                    for (key, value) in body.as_object().unwrap() {
+                        file[key] = value.clone();
+                    }
+                    let new_id = self.new_id();
+                    file["id"] = new_id.clone().into();
+                    self.files.insert(new_id, file.clone());
+                    Response::json(200, file)
+                }
+                ("DELETE", [id]) => {
+                    self.files.remove(*id);
+                    Response::new(204)
+                }
+                _ => error(400, "unexpected request"),
+            }
+        }
+    }
+
+    /// `'p' in parents and name = 'n' and mimeType = 'm' and trashed = false`の形のクエリだけを解釈する
+    fn matches(file: &Value, q: &str) -> bool {
+        q.split(" and ").all(|clause| {
+            let value = || clause.split('\'').nth(1).unwrap().replace("\\'", "'");
+            if clause.ends_with(" in parents") {
+                file["parents"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .any(|p| *p == value())
+            } else if clause.starts_with("name = ") {
+                file["name"] == value()
+            } else if clause.starts_with("mimeType = ") {
+                file["mimeType"] == value()
+            } else if let Some(trashed) = clause.strip_prefix("trashed = ") {
+                file["trashed"] == (trashed == "true")
+            } else {
+                panic!("unsupported query: {}", clause)
+            }
+        })
+    }
+
+    async fn start() -> (TestServer, Arc<Mutex<FakeFiles>>, GDrive) {
+        let mut files = FakeFiles::default();
+        files.add("root-id", "My Drive", FOLDER_MIME_TYPE, "");
+        files.add("reports", "Reports", FOLDER_MIME_TYPE, "root-id");
+        files.add("archive", "Archive", FOLDER_MIME_TYPE, "root-id");
+        files.add("q1", "q1.xlsx", "text/plain", "reports");
+        files.add("readonly-doc", "locked.txt", "text/plain", "reports");
+        files.files.get_mut("root-id").unwrap()["parents"] = json!([]);
+        let files = Arc::new(Mutex::new(files));
+        let state = files.clone();
+        let server = TestServer::start(move |request| {
+            let mut request = request.clone();
+            if request.path == "/drive/v3/files/root" {
+                request.path = "/drive/v3/files/root-id".into();
+            }
+            state.lock().unwrap().handle(&request)
+        })
+        .await;
+        let drive = server.drive();
+        (server, files, drive)
+    }
+
+    #[tokio::test]
+    async fn creates_folders_like_mkdir_p() {
+        let (server, files, drive) = start().await;
+
+        let folder = drive
+            .create_folders("My Drive/Reports/2025/Q1")
+            .await
+            .unwrap();
+        assert_eq!(folder.name, "Q1");
+        assert!(folder.is_directory());
+        {
+            let files = files.lock().unwrap();
+            let year = files.files.values().find(|f| f["name"] == "2025").unwrap();
+            assert_eq!(year["parents"], json!(["reports"]));
+            assert_eq!(year["mimeType"], FOLDER_MIME_TYPE);
+            assert_eq!(
+                files.files[folder.id.as_ref()]["parents"],
+                json!([year["id"]])
+            );
+            // 既存の Reports を探した後は、作成したフォルダの中を探さない
+            let lists = server
+                .requests()
+                .iter()
+                .filter(|r| r.method == "GET" && r.path == "/drive/v3/files")
+                .count();
+            assert_eq!(lists, 2);
+        }
+
+        let again = drive.create_folders("/Reports/2025/Q1").await.unwrap();
+        assert_eq!(again.id, folder.id);
+    }
+
+    #[tokio::test]
+    async fn does_not_duplicate_after_committed_failures() {
+        let (_server, files, mut drive) = start().await;
+        drive.set_retry_policy(RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            jitter: false,
+            ..RetryPolicy::default()
+        });
+        let count = |name: &str| {
+            let files = files.lock().unwrap();
+            files.files.values().filter(|f| f["name"] == name).count()
+        };
+
+        files.lock().unwrap().fail_after_commit = 2;
+        let folder = drive.create_folders("/Reports/2025/Q1").await.unwrap();
+        assert_eq!(folder.name, "Q1");
+        assert_eq!(count("2025"), 1);
+        assert_eq!(count("Q1"), 1);
+
+        files.lock().unwrap().fail_after_commit = 1;
+        assert!(
+            drive
+                .copy(&"q1".into(), None, Some("copy.xlsx"))
+                .await
+                .is_err()
+        );
+        assert_eq!(count("copy.xlsx"), 1);
+
+        files.lock().unwrap().fail_after_commit = 1;
+        drive.delete(&"q1".into()).await.unwrap();
+        assert_eq!(count("q1.xlsx"), 0);
+    }
+
+    #[tokio::test]
+    async fn renames_moves_and_copies() {
+        let (_server, _files, drive) = start().await;
+
+        let meta = drive.rename(&"q1".into(), "q1-final.xlsx").await.unwrap();
+        assert_eq!(meta.name, "q1-final.xlsx");
+
+        let meta = drive
+            .move_to(&"q1".into(), &"archive".into())
+            .await
+            .unwrap();
+        assert_eq!(meta.parents, [GDriveId::from("archive")]);
+
+        let copy = drive
+            .copy(&"q1".into(), Some(&"reports".into()), Some("copy.xlsx"))
+            .await
+            .unwrap();
+        assert_ne!(copy.id, "q1".into());
+        assert_eq!(copy.name, "copy.xlsx");
+        assert_eq!(copy.parents, [GDriveId::from("reports")]);
+    }
+
+    #[tokio::test]
+    async fn trashes_and_deletes() {
+        let (_server, files, drive) = start().await;
+
+        let meta = drive.trash(&"q1".into()).await.unwrap();
+        assert_eq!(meta.trashed, Some(true));
+        assert!(
+            drive
+                .list(&"reports".into())
+                .await
+                .unwrap()
+                .iter()
+                .all(|m| m.id != meta.id)
+        );
+        let meta = drive.untrash(&"q1".into()).await.unwrap();
+        assert_eq!(meta.trashed, Some(false));
+
+        let meta = drive.delete(&"q1".into()).await.unwrap();
+        assert_eq!(meta.name, "q1.xlsx");
+        assert!(!files.lock().unwrap().files.contains_key("q1"));
+    }
+
+    #[tokio::test]
+    async fn reports_permission_and_not_found_errors() {
+        let (_server, _files, drive) = start().await;
+
+        let result = drive.rename(&"readonly-doc".into(), "x").await;
+        assert!(
+            matches!(&result, Err(Error::PermissionDenied(m)) if m.contains("permissions")),
+            "{:?}",
+            result
+        );
+        assert!(matches!(
+            drive.delete(&"readonly-doc".into()).await,
+            Err(Error::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            drive.trash(&"missing".into()).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            drive.move_to(&"missing".into(), &"archive".into()).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,491 @@
+//! テスト用にメモリ上でファイルを管理するバックエンド
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use google_drive3::hyper::body::Bytes;
+use md5::{Digest, Md5};
+
+use crate::{Backend, DownloadHandler, Error, ExportFormat, GDriveId, GMeta, UploadTarget};
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+/// 1 回の`write`で渡すバイト数
+const CHUNK_SIZE: usize = 4096;
+
+/// メモリ上でファイルとフォルダを管理する[`Backend`]
+///
+/// フォルダ、ファイル、Google Apps形式のファイルを追加でき、一覧はページに分けて取得します。
+/// [`MemoryDrive::fail`]でアイテムごとにエラーを起こすことができます。
+///
+/// 更新日時は 2025-01-01T00:00:00Z から、変更するたびに 1 秒ずつ進みます。
+///
+/// # 例
+///
+/// ```
+/// use google_drive::{Backend, MemoryDrive};
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let drive = MemoryDrive::new();
+/// let docs = drive.add_folder(&drive.root(), "docs");
+/// drive.add_file(&docs, "memo.txt", "text/plain", "hello");
+/// drive.add_file(&docs, "plan", "application/vnd.google-apps.document", "# plan");
+///
+/// let items = drive.list(&docs).await.unwrap();
+/// assert_eq!(items.len(), 2);
+/// assert_eq!(items[0].local_name(), "memo.txt");
+/// assert_eq!(items[1].local_name(), "plan.docx");
+/// # });
+/// ```
+pub struct MemoryDrive {
+    state: Mutex<State>,
+}
+
+/// エラーを作る関数
+type ErrorFn = Box<dyn Fn() -> Error + Send + Sync>;
+
+struct State {
+    items: BTreeMap<GDriveId, Item>,
+    next_id: usize,
+    page_size: usize,
+    /// 一覧のページを取得した回数
+    list_pages: usize,
+    errors: HashMap<GDriveId, ErrorFn>,
+    now: DateTime<Utc>,
+}
+
+struct Item {
+    meta: GMeta,
+    /// ファイルの内容。Google Apps形式ならエクスポートした内容
+    content: Vec<u8>,
+}
+
+impl Default for MemoryDrive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryDrive {
+    /// マイドライブ（[`MemoryDrive::root`]）だけがあるドライブを作る
+    pub fn new() -> Self {
+        let mut state = State {
+            items: BTreeMap::new(),
+            next_id: 0,
+            page_size: 100,
+            list_pages: 0,
+            errors: HashMap::new(),
+            now: "2025-01-01T00:00:00Z".parse().unwrap(),
+        };
+        let root = state.new_meta("root".into(), "My Drive", FOLDER_MIME_TYPE, None);
+        state.items.insert(
+            root.id.clone(),
+            Item {
+                meta: root,
+                content: Vec::new(),
+            },
+        );
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// マイドライブのID
+    pub fn root(&self) -> GDriveId {
+        "root".into()
+    }
+
+    /// 一覧の 1 ページに含めるアイテムの数（既定は 100）
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.lock().unwrap().page_size = page_size.max(1);
+    }
+
+    /// 一覧のページを取得した回数
+    pub fn list_pages(&self) -> usize {
+        self.state.lock().unwrap().list_pages
+    }
+
+    /// フォルダを追加し、IDを返す
+    pub fn add_folder(&self, parent: &GDriveId, name: &str) -> GDriveId {
+        self.add(parent, name, FOLDER_MIME_TYPE, Vec::new())
+    }
+
+    /// ファイルを追加し、IDを返す
+    ///
+    /// `mime_type`がGoogle Apps形式の場合、`content`はどの形式でエクスポートしても返す内容になります。
+    pub fn add_file(
+        &self,
+        parent: &GDriveId,
+        name: &str,
+        mime_type: &str,
+        content: impl Into<Vec<u8>>,
+    ) -> GDriveId {
+        self.add(parent, name, mime_type, content.into())
+    }
+
+    fn add(&self, parent: &GDriveId, name: &str, mime_type: &str, content: Vec<u8>) -> GDriveId {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id: GDriveId = format!("id-{}", state.next_id).into();
+        let meta = state.new_meta(id.clone(), name, mime_type, Some(parent));
+        state.items.insert(id.clone(), Item::new(meta, content));
+        id
+    }
+
+    /// ファイルの内容を置き換え、更新日時を進める
+    pub fn update(&self, id: &GDriveId, content: impl Into<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        let now = state.tick();
+        let item = state.items.get_mut(id).expect("no such item");
+        item.content = content.into();
+        item.meta.modified_time = Some(now);
+        item.refresh();
+    }
+
+    /// ゴミ箱に移動する。一覧には含まれなくなる
+    pub fn trash(&self, id: &GDriveId) {
+        let mut state = self.state.lock().unwrap();
+        let now = state.tick();
+        let item = state.items.get_mut(id).expect("no such item");
+        item.meta.trashed = Some(true);
+        item.meta.modified_time = Some(now);
+    }
+
+    /// 完全に削除する
+    pub fn remove(&self, id: &GDriveId) {
+        self.state.lock().unwrap().items.remove(id);
+    }
+
+    /// ダウンロードできるかどうかを設定する
+    pub fn set_can_download(&self, id: &GDriveId, can_download: bool) {
+        let mut state = self.state.lock().unwrap();
+        let item = state.items.get_mut(id).expect("no such item");
+        item.meta.can_download = Some(can_download);
+    }
+
+    /// `id`に対する全ての操作（フォルダなら一覧の取得も）を`error`が返すエラーで失敗させる
+    pub fn fail<F>(&self, id: &GDriveId, error: F)
+    where
+        F: Fn() -> Error + Send + Sync + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.errors.insert(id.clone(), Box::new(error));
+    }
+
+    /// [`MemoryDrive::fail`]で設定したエラーを取り除く
+    pub fn recover(&self, id: &GDriveId) {
+        self.state.lock().unwrap().errors.remove(id);
+    }
+
+    /// アイテムのメタデータ
+    pub fn meta(&self, id: &GDriveId) -> Option<GMeta> {
+        let state = self.state.lock().unwrap();
+        state.items.get(id).map(|item| item.meta.clone())
+    }
+
+    /// ファイルの内容
+    pub fn content(&self, id: &GDriveId) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.items.get(id).map(|item| item.content.clone())
+    }
+
+    /// 注入したエラーを確かめてからアイテムを取り出す
+    fn with_item<T>(
+        &self,
+        id: &GDriveId,
+        f: impl FnOnce(&Item) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let state = self.state.lock().unwrap();
+        if let Some(error) = state.errors.get(id) {
+            return Err(error());
+        }
+        match state.items.get(id) {
+            Some(item) if item.meta.trashed != Some(true) => f(item),
+            _ => Err(Error::NotFound(format!("File not found: {}", id))),
+        }
+    }
+
+    /// 1 ページ分の子を返す。`start`は何番目の子から返すか
+    fn list_page(
+        &self,
+        folder: &GDriveId,
+        start: usize,
+    ) -> Result<(Vec<GMeta>, Option<usize>), Error> {
+        // フォルダ以外の子は無いので空になる
+        self.with_item(folder, |_| Ok(()))?;
+        let mut state = self.state.lock().unwrap();
+        state.list_pages += 1;
+        let mut children: Vec<&GMeta> = state
+            .items
+            .values()
+            .map(|item| &item.meta)
+            .filter(|meta| meta.parents.contains(folder) && meta.trashed != Some(true))
+            .collect();
+        children.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+        let end = (start + state.page_size).min(children.len());
+        let page = children[start..end].iter().map(|m| (*m).clone()).collect();
+        Ok((page, (end < children.len()).then_some(end)))
+    }
+}
+
+impl State {
+    /// 時計を 1 秒進めて返す
+    fn tick(&mut self) -> DateTime<Utc> {
+        self.now += TimeDelta::seconds(1);
+        self.now
+    }
+
+    fn new_meta(
+        &mut self,
+        id: GDriveId,
+        name: &str,
+        mime_type: &str,
+        parent: Option<&GDriveId>,
+    ) -> GMeta {
+        let now = self.tick();
+        GMeta {
+            id,
+            name: name.into(),
+            mime_type: mime_type.into(),
+            modified_time: Some(now),
+            created_time: Some(now),
+            can_download: Some(true),
+            parents: parent.into_iter().cloned().collect(),
+            trashed: Some(false),
+            ..Default::default()
+        }
+    }
+}
+
+impl Item {
+    fn new(meta: GMeta, content: Vec<u8>) -> Self {
+        let mut item = Self { meta, content };
+        item.refresh();
+        item
+    }
+
+    /// 内容からサイズとMD5を設定する。Google Apps形式には無い
+    fn refresh(&mut self) {
+        if self.meta.is_google_app() {
+            return;
+        }
+        self.meta.size = Some(self.content.len() as u64);
+        self.meta.md5_checksum = Some(format!("{:x}", Md5::digest(&self.content)));
+    }
+}
+
+/// データをハンドラに少しずつ渡す
+async fn send<H: DownloadHandler>(data: Vec<u8>, handler: H) -> Result<(), Error> {
+    handler.set_size(data.len()).await?;
+    let data = Bytes::from(data);
+    for start in (0..data.len()).step_by(CHUNK_SIZE) {
+        let end = (start + CHUNK_SIZE).min(data.len());
+        handler.write(data.slice(start..end)).await?;
+    }
+    Ok(())
+}
+
+impl Backend for MemoryDrive {
+    async fn list(&self, folder: &GDriveId) -> Result<Vec<GMeta>, Error> {
+        let mut items = Vec::new();
+        let mut start = Some(0);
+        while let Some(s) = start {
+            let (page, next) = self.list_page(folder, s)?;
+            items.extend(page);
+            start = next;
+        }
+        Ok(items)
+    }
+
+    async fn get_meta(&self, id: &GDriveId) -> Result<GMeta, Error> {
+        self.with_item(id, |item| Ok(item.meta.clone()))
+    }
+
+    async fn download<H>(&self, id: &GDriveId, handler: H) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let data = self.with_item(id, |item| {
+            if item.meta.is_directory() {
+                return Err(Error::DirectoryDownloadError(item.meta.id.clone()));
+            }
+            if item.meta.is_google_app() || item.meta.can_download == Some(false) {
+                return Err(Error::PermissionDenied(format!(
+                    "{} cannot be downloaded",
+                    item.meta.id
+                )));
+            }
+            Ok(item.content.clone())
+        })?;
+        send(data, handler).await
+    }
+
+    async fn export<H>(&self, id: &GDriveId, mime_type: &str, handler: H) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let data = self.with_item(id, |item| {
+            let formats = ExportFormat::formats_for(&item.meta.mime_type);
+            if !formats.iter().any(|f| f.mime_type() == mime_type) {
+                return Err(Error::NotExportable(mime_type.into()));
+            }
+            if item.meta.can_download == Some(false) {
+                return Err(Error::PermissionDenied(format!(
+                    "{} cannot be exported",
+                    item.meta.id
+                )));
+            }
+            Ok(item.content.clone())
+        })?;
+        send(data, handler).await
+    }
+
+    async fn upload(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> Result<GMeta, Error> {
+        let id = match target {
+            UploadTarget::New { parent, name } => {
+                self.with_item(parent, |item| match item.meta.is_directory() {
+                    true => Ok(()),
+                    false => Err(Error::NotFound(format!("Folder not found: {}", parent))),
+                })?;
+                self.add(parent, name, mime_type, data)
+            }
+            UploadTarget::Existing(id) => {
+                self.with_item(id, |_| Ok(()))?;
+                {
+                    let mut state = self.state.lock().unwrap();
+                    let item = state.items.get_mut(id).unwrap();
+                    item.meta.mime_type = mime_type.into();
+                }
+                self.update(id, data);
+                id.clone()
+            }
+        };
+        self.with_item(&id, |item| Ok(item.meta.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl DownloadHandler for Collect {
+        async fn set_size(&self, _size: usize) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn write(&self, b: Bytes) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(&b);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_in_pages() {
+        let drive = MemoryDrive::new();
+        drive.set_page_size(2);
+        for name in ["c", "a", "e", "b", "d"] {
+            drive.add_file(&drive.root(), name, "text/plain", name);
+        }
+        let trashed = drive.add_file(&drive.root(), "f", "text/plain", "f");
+        drive.trash(&trashed);
+
+        let names: Vec<_> = drive
+            .list(&drive.root())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, ["a", "b", "c", "d", "e"]);
+        assert_eq!(drive.list_pages(), 3);
+    }
+
+    #[tokio::test]
+    async fn downloads_exports_and_uploads() {
+        let drive = MemoryDrive::new();
+        let big = vec![7; CHUNK_SIZE * 2 + 1];
+        let file = drive.add_file(
+            &drive.root(),
+            "big.bin",
+            "application/octet-stream",
+            big.clone(),
+        );
+        let doc = drive.add_file(
+            &drive.root(),
+            "doc",
+            "application/vnd.google-apps.document",
+            "# doc",
+        );
+
+        let data = Collect::default();
+        drive.download(&file, data.clone()).await.unwrap();
+        assert_eq!(*data.0.lock().unwrap(), big);
+        let meta = drive.get_meta(&file).await.unwrap();
+        assert_eq!(meta.size, Some(big.len() as u64));
+        assert!(meta.md5_checksum.is_some());
+
+        let data = Collect::default();
+        drive
+            .export(&doc, ExportFormat::Markdown.mime_type(), data.clone())
+            .await
+            .unwrap();
+        assert_eq!(*data.0.lock().unwrap(), b"# doc");
+        let e = drive
+            .export(&doc, ExportFormat::Xlsx.mime_type(), Collect::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotExportable(_)), "{:?}", e);
+        let e = drive.download(&doc, Collect::default()).await.unwrap_err();
+        assert!(matches!(e, Error::PermissionDenied(_)), "{:?}", e);
+
+        let target = UploadTarget::New {
+            parent: drive.root(),
+            name: "new.txt".into(),
+        };
+        let created = drive
+            .upload(&target, "text/plain", b"new".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(drive.content(&created.id).unwrap(), b"new");
+        let updated = drive
+            .upload(
+                &UploadTarget::Existing(created.id.clone()),
+                "text/plain",
+                b"newer".to_vec(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.size, Some(5));
+        assert!(updated.modified_time > created.modified_time);
+    }
+
+    #[tokio::test]
+    async fn injects_errors() {
+        let drive = MemoryDrive::new();
+        let folder = drive.add_folder(&drive.root(), "folder");
+        drive.fail(&folder, || Error::InvalidResponse(500));
+
+        let e = drive.list(&folder).await.unwrap_err();
+        assert!(matches!(e, Error::InvalidResponse(500)), "{:?}", e);
+        let e = drive.get_meta(&folder).await.unwrap_err();
+        assert!(matches!(e, Error::InvalidResponse(500)), "{:?}", e);
+        drive.recover(&folder);
+        assert!(drive.list(&folder).await.unwrap().is_empty());
+
+        let e = drive.get_meta(&"missing".into()).await.unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+    }
+}
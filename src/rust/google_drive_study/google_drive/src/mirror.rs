@@ -0,0 +1,669 @@
+//! フォルダをローカルディレクトリにミラーリングする
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use futures::{StreamExt, stream};
+use md5::{Digest, Md5};
+
+use crate::{Backend, Error, GDrive, GDriveId, GMeta, download::partial_path};
+
+/// リモートで削除されたファイルがローカルに残っている場合の扱い
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Removed {
+    /// 削除する
+    Delete,
+    /// 指定したディレクトリの下に、実行日時ごとのディレクトリを作って移動する
+    ///
+    /// 相対パスの場合はミラー先のディレクトリからの相対パスになります。
+    Quarantine(PathBuf),
+    /// 何もしない
+    Keep,
+}
+
+impl Default for Removed {
+    fn default() -> Self {
+        Removed::Quarantine(".gdrive-quarantine".into())
+    }
+}
+
+/// `GDrive::mirror`のオプション
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// 同時にダウンロードするファイルの数
+    pub concurrency: usize,
+    /// リモートで削除されたファイルの扱い
+    pub removed: Removed,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            removed: Removed::default(),
+        }
+    }
+}
+
+/// `GDrive::mirror`の結果。パスは全てローカルのパス
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+    /// 新規、または更新されていたためダウンロードしたファイル
+    pub downloaded: Vec<PathBuf>,
+    /// 新規、または更新されていたためエクスポートしたGoogle Apps形式のファイル
+    pub exported: Vec<PathBuf>,
+    /// ローカルと一致していたファイル
+    pub unchanged: Vec<PathBuf>,
+    /// ダウンロードできないファイル（権限が無い、エクスポートできない、同じ名前が既にある）
+    pub unavailable: Vec<PathBuf>,
+    /// リモートに無いため削除、または隔離したファイルとディレクトリ
+    pub removed: Vec<PathBuf>,
+    /// 失敗したファイルとフォルダ
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl MirrorReport {
+    /// 失敗が無ければ`true`
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl std::fmt::Display for MirrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "downloaded: {}, exported: {}, unchanged: {}, unavailable: {}, removed: {}, failed: {}",
+            self.downloaded.len(),
+            self.exported.len(),
+            self.unchanged.len(),
+            self.unavailable.len(),
+            self.removed.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// 1 ファイルの処理結果
+enum Outcome {
+    Downloaded,
+    Exported,
+    Unchanged,
+    Unavailable,
+}
+
+/// リモートのフォルダ構成から求めた、ローカルにあるべきもの
+#[derive(Default)]
+struct Tree {
+    /// ローカルにあるべきファイルとディレクトリ
+    expected: HashSet<PathBuf>,
+    /// ローカルにあるべきディレクトリ
+    folders: HashSet<PathBuf>,
+    /// 一覧の取得に失敗したため、中身を消してはいけないディレクトリ
+    incomplete: HashSet<PathBuf>,
+}
+
+impl GDrive {
+    /// フォルダ以下を再帰的にローカルディレクトリにミラーリングする
+    ///
+    /// フォルダ構成をローカルに再現し、ファイルを`opts.concurrency`個ずつ並列にダウンロードします。
+    /// Google Apps形式のファイルは既定の形式でエクスポートします（[`GMeta::local_name`]）。
+    ///
+    /// ダウンロードしたファイルの更新日時はリモートの`modified_time`に合わせます。
+    /// 2 回目以降は、更新日時とMD5がローカルと一致するファイルをダウンロードしません。
+    /// Google Apps形式のファイルにはMD5が無いため、更新日時のみで判定します。
+    ///
+    /// リモートに無いローカルのファイルは`opts.removed`に従って削除、または隔離します。
+    ///
+    /// # 引数
+    ///
+    /// * `folder` - ミラーリングするフォルダのID
+    /// * `dir` - ミラー先のディレクトリ
+    /// * `opts` - オプション
+    ///
+    /// # 戻り値
+    ///
+    /// 処理結果。個々のファイルの失敗は`MirrorReport::failed`に入り、
+    /// `folder`自体の一覧が取得できない場合などはエラーになります。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, GDriveId, MirrorOptions, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///     let folder_id: GDriveId = "your_folder_id".into();
+    ///
+    ///     let report = drive.mirror(&folder_id, "./mirror", &MirrorOptions::default()).await?;
+    ///     println!("{}", report);
+    ///     for (path, e) in &report.failed {
+    ///         println!("{}: {}", path.display(), e);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn mirror<P: AsRef<Path>>(
+        &self,
+        folder: &GDriveId,
+        dir: P,
+        opts: &MirrorOptions,
+    ) -> Result<MirrorReport, Error> {
+        mirror(self, folder, dir, opts).await
+    }
+}
+
+/// [`GDrive::mirror`]と同じく、`backend`のフォルダをローカルディレクトリにミラーリングする
+///
+/// [`MemoryDrive`](crate::MemoryDrive)を渡すと、Google Driveに接続せずにミラーリングを試せます。
+pub async fn mirror<B: Backend, P: AsRef<Path>>(
+    backend: &B,
+    folder: &GDriveId,
+    dir: P,
+    opts: &MirrorOptions,
+) -> Result<MirrorReport, Error> {
+    let dir = dir.as_ref();
+    let mut report = MirrorReport::default();
+    let mut tree = Tree::default();
+    let mut files = Vec::new();
+
+    // フォルダを辿ってローカルのパスを決める
+    let mut pending = vec![(folder.clone(), dir.to_path_buf())];
+    while let Some((id, local)) = pending.pop() {
+        tokio::fs::create_dir_all(&local).await?;
+        let items = match backend.list(&id).await {
+            Ok(items) => items,
+            Err(e) if local != dir => {
+                report.failed.push((local.clone(), e));
+                tree.incomplete.insert(local);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for meta in items {
+            let path = local.join(local_file_name(&meta));
+            if !tree.expected.insert(path.clone()) {
+                report.unavailable.push(path);
+                continue;
+            }
+            if meta.is_directory() {
+                tree.folders.insert(path.clone());
+                pending.push((meta.id, path));
+            } else {
+                files.push((meta, path));
+            }
+        }
+    }
+
+    let results: Vec<_> = stream::iter(files)
+        .map(|(meta, path)| async move {
+            let result = mirror_file(backend, &meta, &path).await;
+            (path, result)
+        })
+        .buffer_unordered(opts.concurrency.max(1))
+        .collect()
+        .await;
+    for (path, result) in results {
+        match result {
+            Ok(Outcome::Downloaded) => report.downloaded.push(path),
+            Ok(Outcome::Exported) => report.exported.push(path),
+            Ok(Outcome::Unchanged) => report.unchanged.push(path),
+            Ok(Outcome::Unavailable) => report.unavailable.push(path),
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+
+    prune(dir, &mut tree, &opts.removed, &mut report).await?;
+    Ok(report)
+}
+
+/// 1 ファイルをミラーリングする
+async fn mirror_file<B: Backend>(backend: &B, meta: &GMeta, path: &Path) -> Result<Outcome, Error> {
+    if meta.can_download == Some(false) {
+        return Ok(Outcome::Unavailable);
+    }
+    let export = if meta.is_google_app_file() {
+        let Some(format) = meta.default_export() else {
+            return Ok(Outcome::Unavailable);
+        };
+        Some(format)
+    } else {
+        None
+    };
+    if is_up_to_date(meta, path).await? {
+        return Ok(Outcome::Unchanged);
+    }
+
+    match export {
+        Some(format) => {
+            // 失敗しても前回の内容が残るように、別のファイルに書いてから置き換える
+            let part = partial_path(path);
+            let handler = GDrive::file_handler(&part).await?;
+            if let Err(e) = backend.export(&meta.id, format.mime_type(), handler).await {
+                let _ = tokio::fs::remove_file(&part).await;
+                return Err(e);
+            }
+            tokio::fs::rename(&part, path).await?;
+        }
+        None => backend.save(meta, path).await?,
+    }
+    if let Some(modified) = meta.modified_time {
+        set_modified(path, modified.into())?;
+    }
+    Ok(match export {
+        Some(_) => Outcome::Exported,
+        None => Outcome::Downloaded,
+    })
+}
+
+/// Driveの名前をローカルのファイル名にする
+///
+/// Driveの名前には`/`も使えるため置き換える。`.`や`..`も別の名前にする。
+fn local_file_name(meta: &GMeta) -> String {
+    let name = meta.local_name().replace(['/', '\\'], "_");
+    if name.trim_matches('.').is_empty() {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// ローカルのファイルの更新日時とMD5がリモートと一致するか
+async fn is_up_to_date(meta: &GMeta, path: &Path) -> Result<bool, Error> {
+    let Ok(local) = tokio::fs::metadata(path).await else {
+        return Ok(false);
+    };
+    if !local.is_file() {
+        return Ok(false);
+    }
+    // ファイルシステムによって精度が違うため秒単位で比較する
+    let secs = |t: SystemTime| {
+        t.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .ok()
+    };
+    if secs(local.modified()?) != meta.modified_time.and_then(|t| secs(t.into())) {
+        return Ok(false);
+    }
+    match &meta.md5_checksum {
+        Some(md5) => Ok(md5_file(path).await?.eq_ignore_ascii_case(md5)),
+        None => Ok(true),
+    }
+}
+
+/// ファイルのMD5を16進小文字で返す
+async fn md5_file(path: &Path) -> std::io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut md5 = Md5::new();
+        std::io::copy(&mut file, &mut md5)?;
+        Ok(format!("{:x}", md5.finalize()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+fn set_modified(path: &Path, time: SystemTime) -> std::io::Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time)
+}
+
+/// リモートに無いローカルのファイルとディレクトリを削除、または隔離する
+///
+/// 一覧を取得できなかったフォルダの中は触らない。
+async fn prune(
+    dir: &Path,
+    tree: &mut Tree,
+    removed: &Removed,
+    report: &mut MirrorReport,
+) -> Result<(), Error> {
+    let quarantine = match removed {
+        Removed::Keep => return Ok(()),
+        Removed::Delete => None,
+        Removed::Quarantine(q) => {
+            let q = dir.join(q);
+            // 隔離先がミラー先の中にある場合、隔離先自体は消さない
+            tree.expected.insert(q.clone());
+            let now = chrono::Local::now().format("%Y%m%d-%H%M%S");
+            Some(q.join(now.to_string()))
+        }
+    };
+
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        if tree.incomplete.contains(&current) {
+            continue;
+        }
+        let mut entries = tokio::fs::read_dir(&current).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_dir = entry.file_type().await?.is_dir();
+            if tree.expected.contains(&path) {
+                if is_dir && tree.folders.contains(&path) {
+                    stack.push(path);
+                }
+                continue;
+            }
+            let result = match &quarantine {
+                Some(q) => {
+                    let to = q.join(path.strip_prefix(dir).unwrap_or(&path));
+                    move_to(&path, &to).await
+                }
+                None if is_dir => tokio::fs::remove_dir_all(&path).await,
+                None => tokio::fs::remove_file(&path).await,
+            };
+            match result {
+                Ok(()) => report.removed.push(path),
+                Err(e) => report.failed.push((path, e.into())),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn move_to(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::rename(from, to).await
+}
+
+#[cfg(test)]
+mod tests {
+    use google_drive3::api::{File, FileCapabilities};
+
+    use super::*;
+    use crate::MemoryDrive;
+
+    const DOCUMENT: &str = "application/vnd.google-apps.document";
+
+    /// root/
+    ///   a.txt, doc（ドキュメント）, form（エクスポートできない）, locked.txt（ダウンロード不可）
+    ///   sub/b.txt
+    fn sample() -> (MemoryDrive, GDriveId, GDriveId) {
+        let drive = MemoryDrive::new();
+        let root = drive.root();
+        let a = drive.add_file(&root, "a.txt", "text/plain", "a");
+        drive.add_file(&root, "doc", DOCUMENT, "# doc");
+        drive.add_file(&root, "form", "application/vnd.google-apps.form", "");
+        let locked = drive.add_file(&root, "locked.txt", "text/plain", "secret");
+        drive.set_can_download(&locked, false);
+        let sub = drive.add_folder(&root, "sub");
+        drive.add_file(&sub, "b.txt", "text/plain", "b");
+        (drive, a, sub)
+    }
+
+    fn names(paths: &[PathBuf], dir: &Path) -> Vec<String> {
+        let mut v: Vec<_> = paths
+            .iter()
+            .map(|p| p.strip_prefix(dir).unwrap().display().to_string())
+            .collect();
+        v.sort();
+        v
+    }
+
+    #[tokio::test]
+    async fn mirrors_tree_and_skips_unchanged_files() {
+        let (drive, a, _) = sample();
+        drive.set_page_size(2);
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let opts = MirrorOptions::default();
+
+        let report = mirror(&drive, &drive.root(), dir, &opts).await.unwrap();
+        assert!(report.is_ok(), "{:?}", report.failed);
+        assert_eq!(names(&report.downloaded, dir), ["a.txt", "sub/b.txt"]);
+        assert_eq!(names(&report.exported, dir), ["doc.docx"]);
+        assert_eq!(names(&report.unavailable, dir), ["form", "locked.txt"]);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"a");
+        assert_eq!(std::fs::read(dir.join("sub/b.txt")).unwrap(), b"b");
+        assert_eq!(std::fs::read(dir.join("doc.docx")).unwrap(), b"# doc");
+        let modified = std::fs::metadata(dir.join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let expected: SystemTime = drive.meta(&a).unwrap().modified_time.unwrap().into();
+        assert_eq!(modified, expected);
+
+        let report = mirror(&drive, &drive.root(), dir, &opts).await.unwrap();
+        assert!(report.downloaded.is_empty() && report.exported.is_empty());
+        assert_eq!(
+            names(&report.unchanged, dir),
+            ["a.txt", "doc.docx", "sub/b.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_changed_files_and_quarantines_removed_ones() {
+        let (drive, a, sub) = sample();
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap();
+
+        drive.update(&a, "new a");
+        drive.trash(&sub);
+        let report = mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&report.downloaded, dir), ["a.txt"]);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"new a");
+        assert_eq!(names(&report.removed, dir), ["sub"]);
+        assert!(!dir.join("sub").exists());
+        let quarantine = std::fs::read_dir(dir.join(".gdrive-quarantine"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert_eq!(std::fs::read(quarantine.join("sub/b.txt")).unwrap(), b"b");
+
+        std::fs::write(dir.join("stray.txt"), "x").unwrap();
+        let opts = MirrorOptions {
+            removed: Removed::Delete,
+            ..Default::default()
+        };
+        let report = mirror(&drive, &drive.root(), dir, &opts).await.unwrap();
+        assert!(names(&report.removed, dir).contains(&"stray.txt".to_string()));
+        assert!(!dir.join("stray.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn keeps_local_files_of_failed_folders() {
+        let (drive, a, sub) = sample();
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap();
+
+        let doc = Backend::list(&drive, &drive.root())
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|m| m.name == "doc")
+            .unwrap()
+            .id;
+        drive.update(&a, "new a");
+        drive.update(&doc, "# new doc");
+        drive.fail(&sub, || Error::InvalidResponse(500));
+        drive.fail(&a, || Error::InvalidResponse(503));
+        drive.fail(&doc, || Error::InvalidResponse(503));
+        let report = mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap();
+        let failed: Vec<_> = report.failed.iter().map(|(p, _)| p.clone()).collect();
+        assert_eq!(names(&failed, dir), ["a.txt", "doc.docx", "sub"]);
+        assert!(report.removed.is_empty());
+        assert_eq!(std::fs::read(dir.join("sub/b.txt")).unwrap(), b"b");
+        // 失敗したファイルは前の内容のまま
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"a");
+        assert_eq!(std::fs::read(dir.join("doc.docx")).unwrap(), b"# doc");
+        assert!(!dir.join("doc.docx.part").exists());
+
+        drive.fail(&drive.root(), || Error::InvalidResponse(500));
+        let e = mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::InvalidResponse(500)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn skips_duplicate_names() {
+        let drive = MemoryDrive::new();
+        drive.add_file(&drive.root(), "same.txt", "text/plain", "1");
+        drive.add_file(&drive.root(), "same.txt", "text/plain", "2");
+        drive.add_file(&drive.root(), "a/b", "text/plain", "slash");
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let report = mirror(&drive, &drive.root(), dir, &MirrorOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(names(&report.downloaded, dir), ["a_b", "same.txt"]);
+        assert_eq!(names(&report.unavailable, dir), ["same.txt"]);
+        assert_eq!(std::fs::read(dir.join("same.txt")).unwrap(), b"1");
+    }
+
+    fn meta(name: &str, mime_type: &str, md5: Option<&str>) -> GMeta {
+        GMeta::new(File {
+            id: Some("id".into()),
+            name: Some(name.into()),
+            mime_type: Some(mime_type.into()),
+            modified_time: Some("2024-01-02T03:04:05Z".parse().unwrap()),
+            capabilities: Some(FileCapabilities {
+                can_download: Some(true),
+                ..Default::default()
+            }),
+            md5_checksum: md5.map(Into::into),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sanitizes_local_file_names() {
+        let name = |n: &str, mime: &str| local_file_name(&meta(n, mime, None));
+        assert_eq!(name("a/b\\c.txt", "text/plain"), "a_b_c.txt");
+        assert_eq!(name(".", "text/plain"), "_.");
+        assert_eq!(name("..", "text/plain"), "_..");
+        assert_eq!(name(".env", "text/plain"), ".env");
+        assert_eq!(
+            name("doc", "application/vnd.google-apps.document"),
+            "doc.docx"
+        );
+    }
+
+    #[tokio::test]
+    async fn compares_modified_time_and_md5() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        // "hello" のMD5
+        let md5 = "5d41402abc4b2a76b9719d911017c592";
+        let remote = meta("a.txt", "text/plain", Some(md5));
+        assert!(!is_up_to_date(&remote, &path).await.unwrap());
+
+        std::fs::write(&path, "hello").unwrap();
+        set_modified(&path, remote.modified_time.unwrap().into()).unwrap();
+        assert!(is_up_to_date(&remote, &path).await.unwrap());
+        let upper = meta("a.txt", "text/plain", Some(&md5.to_uppercase()));
+        assert!(is_up_to_date(&upper, &path).await.unwrap());
+        let other = meta("a.txt", "text/plain", Some("0".repeat(32).as_str()));
+        assert!(!is_up_to_date(&other, &path).await.unwrap());
+        // MD5が無ければ更新日時だけで判断する
+        let no_md5 = meta("a.txt", "text/plain", None);
+        assert!(is_up_to_date(&no_md5, &path).await.unwrap());
+
+        let mut newer = remote.clone();
+        newer.modified_time = remote
+            .modified_time
+            .map(|t| t + chrono::Duration::seconds(1));
+        assert!(!is_up_to_date(&newer, &path).await.unwrap());
+        assert!(!is_up_to_date(&remote, dir.path()).await.unwrap());
+    }
+
+    /// keep.txt, gone.txt, sub/{keep.txt, gone.txt}, broken/stray.txt を作る
+    ///
+    /// `broken`は一覧の取得に失敗したディレクトリとして扱う。
+    fn local_tree(dir: &Path) -> Tree {
+        for name in ["keep.txt", "gone.txt", "sub/keep.txt", "sub/gone.txt"] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, name).unwrap();
+        }
+        std::fs::create_dir(dir.join("broken")).unwrap();
+        std::fs::write(dir.join("broken/stray.txt"), "stray").unwrap();
+
+        let mut tree = Tree::default();
+        for name in ["keep.txt", "sub", "sub/keep.txt", "broken"] {
+            tree.expected.insert(dir.join(name));
+        }
+        tree.folders.insert(dir.join("sub"));
+        tree.folders.insert(dir.join("broken"));
+        tree.incomplete.insert(dir.join("broken"));
+        tree
+    }
+
+    #[tokio::test]
+    async fn prunes_removed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let mut tree = local_tree(dir);
+        let mut report = MirrorReport::default();
+        prune(dir, &mut tree, &Removed::Delete, &mut report)
+            .await
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.failed);
+        assert_eq!(names(&report.removed, dir), ["gone.txt", "sub/gone.txt"]);
+        assert!(dir.join("keep.txt").exists() && dir.join("sub/keep.txt").exists());
+        assert!(!dir.join("gone.txt").exists() && !dir.join("sub/gone.txt").exists());
+        assert!(dir.join("broken/stray.txt").exists());
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let mut tree = local_tree(dir);
+        let mut report = MirrorReport::default();
+        prune(dir, &mut tree, &Removed::Keep, &mut report)
+            .await
+            .unwrap();
+        assert!(report.removed.is_empty());
+        assert!(dir.join("gone.txt").exists() && dir.join("sub/gone.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn quarantines_removed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let mut tree = local_tree(dir);
+        let mut report = MirrorReport::default();
+        let removed = Removed::Quarantine(".trash".into());
+        prune(dir, &mut tree, &removed, &mut report).await.unwrap();
+        assert_eq!(names(&report.removed, dir), ["gone.txt", "sub/gone.txt"]);
+        let moved = std::fs::read_dir(dir.join(".trash"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert_eq!(std::fs::read(moved.join("gone.txt")).unwrap(), b"gone.txt");
+        assert_eq!(
+            std::fs::read(moved.join("sub/gone.txt")).unwrap(),
+            b"sub/gone.txt"
+        );
+        assert!(dir.join("broken/stray.txt").exists());
+
+        // 2回目は隔離先自体を消さない
+        let mut report = MirrorReport::default();
+        prune(dir, &mut tree, &removed, &mut report).await.unwrap();
+        assert!(report.removed.is_empty(), "{:?}", report.removed);
+    }
+}
@@ -0,0 +1,444 @@
+//! パスや共有URLからIDを求める、IDからパスを求める
+
+use crate::{
+    Error, GDrive, GDriveId, GMeta, MetaFields, OrderBy, Query, SearchOptions, SortKey,
+    query::Quoted,
+};
+
+/// マイドライブを表すパスの先頭
+const MY_DRIVE: &str = "My Drive";
+
+/// 同じフォルダに同じ名前のアイテムが複数ある場合の扱い
+///
+/// Google Driveでは同じフォルダに同じ名前のファイルを置けるため、パスだけでは 1 つに決まらないことがあります。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// `Error::AmbiguousPath`を返す
+    #[default]
+    Error,
+    /// 最も古く作成されたものを使う
+    Oldest,
+    /// 最も新しく作成されたものを使う
+    Newest,
+}
+
+impl GDriveId {
+    /// Google DriveやGoogleドキュメントの共有URLからIDを取り出す
+    ///
+    /// 次のような形式に対応しています。
+    ///
+    /// - `https://drive.google.com/file/d/<id>/view?usp=sharing`
+    /// - `https://drive.google.com/open?id=<id>`、`https://drive.google.com/uc?id=<id>&export=download`
+    /// - `https://drive.google.com/drive/folders/<id>`、`https://drive.google.com/drive/u/0/folders/<id>`
+    /// - `https://docs.google.com/document/d/<id>/edit`（spreadsheets、presentation、drawings、formsも同様）
+    ///
+    /// # 例
+    ///
+    /// ```
+    /// use google_drive::GDriveId;
+    ///
+    /// let id = GDriveId::from_url("https://drive.google.com/file/d/1wS6tVoVmkdMZ96DgcKew-pSYBpJa9pXA/view?usp=sharing");
+    /// assert_eq!(id, Some("1wS6tVoVmkdMZ96DgcKew-pSYBpJa9pXA".into()));
+    /// assert_eq!(GDriveId::from_url("https://example.com/file/d/abc"), None);
+    /// ```
+    pub fn from_url(url: &str) -> Option<GDriveId> {
+        let rest = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .unwrap_or(url);
+        let (host, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if host != "google.com" && !host.ends_with(".google.com") {
+            return None;
+        }
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let from_query = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == "id")
+            .map(|(_, id)| id);
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let from_path = segments
+            .iter()
+            .position(|s| *s == "d" || *s == "folders")
+            .and_then(|i| match segments.get(i + 1) {
+                // 公開したフォームは /forms/d/e/<id>/viewform
+                Some(&"e") => segments.get(i + 2),
+                id => id,
+            })
+            .copied();
+
+        from_path
+            .or(from_query)
+            .filter(|id| is_id(id))
+            .map(Into::into)
+    }
+}
+
+/// IDに使われる文字だけでできているか
+fn is_id(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// パスの起点
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Root<'a> {
+    MyDrive,
+    SharedDrive(&'a str),
+}
+
+/// パスを起点と名前に分ける
+///
+/// `共有ドライブ名:/a/b`は共有ドライブ、それ以外はマイドライブからのパスとする。
+/// マイドライブのパスの先頭の`My Drive`と`/`は省略できる。
+pub(crate) fn parse_path(path: &str) -> (Root<'_>, Vec<&str>) {
+    let (root, rest) = match path.split_once(":/") {
+        Some((drive, rest)) if !drive.contains('/') => (Root::SharedDrive(drive), rest),
+        _ => {
+            let rest = path.trim_start_matches('/');
+            let rest = match rest.strip_prefix(MY_DRIVE) {
+                Some(r) if r.is_empty() || r.starts_with('/') => r,
+                _ => rest,
+            };
+            (Root::MyDrive, rest)
+        }
+    };
+    let names = rest.split('/').filter(|s| !s.is_empty()).collect();
+    (root, names)
+}
+
+impl GDrive {
+    /// パスからアイテムのメタデータを求める
+    ///
+    /// `My Drive/Reports/2025/q1.xlsx`（`My Drive`は省略可）はマイドライブから、
+    /// `共有ドライブ名:/dir/file`は共有ドライブから順にたどります。
+    /// 途中のショートカットはリンク先のフォルダをたどります。名前に`/`を含むアイテムは指定できません。
+    ///
+    /// # 引数
+    ///
+    /// * `path` - アイテムのパス
+    /// * `duplicates` - 同じ名前のアイテムが複数ある場合の扱い
+    ///
+    /// # 戻り値
+    ///
+    /// 見つかったアイテムのメタデータ。見つからなければ`Error::PathNotFound`
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{DuplicatePolicy, GDrive, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+    ///
+    ///     let meta = drive.resolve_path("My Drive/Reports/2025/q1.xlsx", DuplicatePolicy::Error).await?;
+    ///     drive.download_and_save(&meta.id, "./q1.xlsx").await?;
+    ///
+    ///     let meta = drive.resolve_path("Team:/design/logo.png", DuplicatePolicy::Newest).await?;
+    ///     println!("{}", meta.id);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn resolve_path(
+        &self,
+        path: &str,
+        duplicates: DuplicatePolicy,
+    ) -> Result<GMeta, Error> {
+        let (root, names) = parse_path(path);
+        let mut current = self.path_root(&root, path, duplicates).await?;
+        for (i, name) in names.iter().enumerate() {
+            let parent = match &current.shortcut_details {
+                Some(shortcut) => shortcut.target_id.clone(),
+                None => current.id.clone(),
+            };
+            let query = Query::parent(parent)
+                .and(Query::name_eq(*name))
+                .and(Query::trashed(false));
+            let opts = SearchOptions {
+                order_by: vec![OrderBy::Asc(SortKey::CreatedTime)],
+                ..Default::default()
+            };
+            let found = self.search(&query, &opts).await?;
+            let partial = names[..=i].join("/");
+            current = pick(found, duplicates, |items| {
+                Error::AmbiguousPath(partial.clone(), items.map(|m| m.id.clone()).collect())
+            })
+            .ok_or_else(|| Error::PathNotFound(partial.clone()))??;
+        }
+        Ok(current)
+    }
+
+    /// パスの起点（マイドライブまたは共有ドライブ）のメタデータ
+    pub(crate) async fn path_root(
+        &self,
+        root: &Root<'_>,
+        path: &str,
+        duplicates: DuplicatePolicy,
+    ) -> Result<GMeta, Error> {
+        match root {
+            Root::MyDrive => self.get_meta(&"root".into()).await,
+            Root::SharedDrive(name) => {
+                let id = self.find_shared_drive(name, path, duplicates).await?;
+                self.get_meta(&id).await
+            }
+        }
+    }
+
+    /// 名前から共有ドライブのIDを求める
+    async fn find_shared_drive(
+        &self,
+        name: &str,
+        path: &str,
+        duplicates: DuplicatePolicy,
+    ) -> Result<GDriveId, Error> {
+        let q = format!("name = {}", Quoted(name));
+        let mut drives = self.list_shared_drives(Some(&q)).await?;
+        drives.sort_by_key(|d| d.created_time);
+        let ids = drives.into_iter().map(|d| d.id).collect();
+        pick(ids, duplicates, |ids| {
+            Error::AmbiguousPath(format!("{}:", name), ids.cloned().collect())
+        })
+        .ok_or_else(|| Error::PathNotFound(path.into()))?
+    }
+
+    /// アイテムのパスを求める
+    ///
+    /// 親を順にたどり、[`GDrive::resolve_path`]に渡せる形式（`My Drive/a/b`、`共有ドライブ名:/a/b`）で返します。
+    /// 親が複数ある場合は最初の親をたどります。
+    /// 自分と共有されているだけのアイテムなど、親をたどれないアイテムはたどれた所からのパスになります。
+    pub async fn full_path(&self, id: &GDriveId) -> Result<String, Error> {
+        let fields = MetaFields::custom("parents,driveId");
+        let mut names = Vec::new();
+        let mut meta = self.get_meta_with_fields(id, &fields).await?;
+        while let Some(parent) = meta.parents.first() {
+            let parent = self.get_meta_with_fields(parent, &fields).await?;
+            names.push(std::mem::replace(&mut meta, parent).name);
+        }
+        names.reverse();
+        let rest = names.join("/");
+        if meta.drive_id.as_ref() == Some(&meta.id) {
+            Ok(format!("{}:/{}", meta.name, rest))
+        } else if rest.is_empty() {
+            Ok(meta.name)
+        } else {
+            Ok(format!("{}/{}", meta.name, rest))
+        }
+    }
+}
+
+/// 作成順に並んだ`items`から`duplicates`に従って 1 つ選ぶ。空なら`None`
+fn pick<T>(
+    mut items: Vec<T>,
+    duplicates: DuplicatePolicy,
+    ambiguous: impl FnOnce(std::slice::Iter<'_, T>) -> Error,
+) -> Option<Result<T, Error>> {
+    match (items.len(), duplicates) {
+        (0, _) => None,
+        (1, _) | (_, DuplicatePolicy::Oldest) => Some(Ok(items.swap_remove(0))),
+        (_, DuplicatePolicy::Newest) => items.pop().map(Ok),
+        (_, DuplicatePolicy::Error) => Some(Err(ambiguous(items.iter()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    #[test]
+    fn parses_share_urls() {
+        let id = Some(GDriveId::from("1AbC-d_9"));
+        for url in [
+            "https://drive.google.com/file/d/1AbC-d_9/view?usp=sharing",
+            "https://drive.google.com/file/d/1AbC-d_9",
+            "https://drive.google.com/open?id=1AbC-d_9",
+            "https://drive.google.com/uc?export=download&id=1AbC-d_9",
+            "https://drive.google.com/drive/folders/1AbC-d_9?usp=sharing",
+            "https://drive.google.com/drive/u/1/folders/1AbC-d_9",
+            "https://docs.google.com/document/d/1AbC-d_9/edit#heading=h.x",
+            "https://docs.google.com/spreadsheets/d/1AbC-d_9/edit?gid=0",
+            "https://docs.google.com/presentation/d/1AbC-d_9/",
+            "https://docs.google.com/forms/d/e/1AbC-d_9/viewform",
+            "drive.google.com/file/d/1AbC-d_9/view",
+        ] {
+            assert_eq!(GDriveId::from_url(url), id, "{}", url);
+        }
+        assert_eq!(GDriveId::from_url("https://evil.example/file/d/1AbC"), None);
+        assert_eq!(
+            GDriveId::from_url("https://drive.google.com/drive/my-drive"),
+            None
+        );
+        assert_eq!(
+            GDriveId::from_url("https://drive.google.com/open?id=a'b"),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(
+            parse_path("My Drive/Reports/2025/q1.xlsx"),
+            (Root::MyDrive, vec!["Reports", "2025", "q1.xlsx"])
+        );
+        assert_eq!(parse_path("/a//b/"), (Root::MyDrive, vec!["a", "b"]));
+        assert_eq!(parse_path("My Drive"), (Root::MyDrive, vec![]));
+        assert_eq!(
+            parse_path("My Drives/a"),
+            (Root::MyDrive, vec!["My Drives", "a"])
+        );
+        assert_eq!(
+            parse_path("Team A:/dir/file"),
+            (Root::SharedDrive("Team A"), vec!["dir", "file"])
+        );
+        assert_eq!(parse_path("a/b:/c"), (Root::MyDrive, vec!["a", "b:", "c"]));
+    }
+
+    /// (id, name, mimeType, parent, driveId)
+    type Item = (
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+    );
+
+    const FOLDER: &str = "application/vnd.google-apps.folder";
+
+    const ITEMS: &[Item] = &[
+        ("root-id", "My Drive", FOLDER, "", ""),
+        ("reports", "Reports", FOLDER, "root-id", ""),
+        ("q1-old", "q1.xlsx", "text/plain", "reports", ""),
+        ("q1-new", "q1.xlsx", "text/plain", "reports", ""),
+        ("notes", "notes.txt", "text/plain", "reports", ""),
+        ("team-id", "Team", FOLDER, "", "team-id"),
+        ("design", "design", FOLDER, "team-id", "team-id"),
+        ("logo", "logo.png", "image/png", "design", "team-id"),
+    ];
+
+    fn to_json(item: &Item) -> serde_json::Value {
+        let (id, name, mime_type, parent, drive_id) = *item;
+        let mut v = serde_json::json!({"id": id, "name": name, "mimeType": mime_type});
+        if !parent.is_empty() {
+            v["parents"] = serde_json::json!([parent]);
+        }
+        if !drive_id.is_empty() {
+            v["driveId"] = drive_id.into();
+        }
+        v
+    }
+
+    /// `ITEMS`を返す偽のDrive API
+    async fn tree_server() -> TestServer {
+        TestServer::start(|request| {
+            if request.path == "/drive/v3/drives" {
+                let drives: Vec<_> = ITEMS
+                    .iter()
+                    .filter(|i| {
+                        i.0 == i.4 && request.query("q") == Some(&format!("name = '{}'", i.1))
+                    })
+                    .map(|i| serde_json::json!({"id": i.0, "name": i.1}))
+                    .collect();
+                return test_server::Response::json(200, serde_json::json!({"drives": drives}));
+            }
+            if request.path == "/drive/v3/files" {
+                // 'parent' in parents and name = 'name' and trashed = false
+                let q = request.query("q").unwrap();
+                let parent = q.split('\'').nth(1).unwrap();
+                let name = q.split('\'').nth(3).unwrap();
+                let files: Vec<_> = ITEMS
+                    .iter()
+                    .filter(|i| i.3 == parent && i.1 == name)
+                    .map(to_json)
+                    .collect();
+                return test_server::Response::json(200, serde_json::json!({"files": files}));
+            }
+            let id = request.path.trim_start_matches("/drive/v3/files/");
+            let id = if id == "root" { "root-id" } else { id };
+            match ITEMS.iter().find(|i| i.0 == id) {
+                Some(item) => test_server::Response::json(200, to_json(item)),
+                None => test_server::Response::json(404, r#"{"error": {"code": 404}}"#),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn resolves_paths() {
+        let server = tree_server().await;
+        let drive = server.drive();
+
+        let meta = drive
+            .resolve_path("My Drive/Reports/notes.txt", DuplicatePolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!(meta.id, "notes".into());
+        let meta = drive
+            .resolve_path("Team:/design/logo.png", DuplicatePolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!(meta.id, "logo".into());
+
+        let result = drive
+            .resolve_path("Reports/missing.txt", DuplicatePolicy::Error)
+            .await;
+        assert!(matches!(result, Err(Error::PathNotFound(p)) if p == "Reports/missing.txt"));
+        let result = drive
+            .resolve_path("Nobody:/design", DuplicatePolicy::Error)
+            .await;
+        assert!(matches!(result, Err(Error::PathNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn handles_duplicate_names() {
+        let server = tree_server().await;
+        let drive = server.drive();
+
+        let result = drive
+            .resolve_path("Reports/q1.xlsx", DuplicatePolicy::Error)
+            .await;
+        let Err(Error::AmbiguousPath(path, ids)) = result else {
+            panic!("unexpected result: {:?}", result);
+        };
+        assert_eq!(path, "Reports/q1.xlsx");
+        assert_eq!(ids, [GDriveId::from("q1-old"), "q1-new".into()]);
+
+        let oldest = drive
+            .resolve_path("Reports/q1.xlsx", DuplicatePolicy::Oldest)
+            .await
+            .unwrap();
+        assert_eq!(oldest.id, "q1-old".into());
+        let newest = drive
+            .resolve_path("Reports/q1.xlsx", DuplicatePolicy::Newest)
+            .await
+            .unwrap();
+        assert_eq!(newest.id, "q1-new".into());
+        assert!(
+            server
+                .requests()
+                .iter()
+                .filter(|r| r.path == "/drive/v3/files")
+                .all(|r| r.query("orderBy") == Some("createdTime"))
+        );
+    }
+
+    #[tokio::test]
+    async fn builds_full_path() {
+        let server = tree_server().await;
+        let drive = server.drive();
+
+        assert_eq!(
+            drive.full_path(&"notes".into()).await.unwrap(),
+            "My Drive/Reports/notes.txt"
+        );
+        assert_eq!(
+            drive.full_path(&"logo".into()).await.unwrap(),
+            "Team:/design/logo.png"
+        );
+        assert_eq!(drive.full_path(&"team-id".into()).await.unwrap(), "Team:/");
+    }
+}
@@ -0,0 +1,571 @@
+//! 共有設定（権限）の取得と変更
+
+use chrono::{DateTime, Utc};
+
+use crate::{Error, GDrive, GDriveId};
+
+/// 取得する権限のフィールド
+const FIELDS: &str = "id,type,role,emailAddress,domain,displayName,\
+                      allowFileDiscovery,expirationTime,deleted,pendingOwner";
+
+/// 権限の役割
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// オーナー
+    Owner,
+    /// 共有ドライブの管理者
+    Organizer,
+    /// 共有ドライブのコンテンツ管理者
+    FileOrganizer,
+    /// 編集者
+    Writer,
+    /// 閲覧者（コメント可）
+    Commenter,
+    /// 閲覧者
+    Reader,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Organizer => "organizer",
+            Self::FileOrganizer => "fileOrganizer",
+            Self::Writer => "writer",
+            Self::Commenter => "commenter",
+            Self::Reader => "reader",
+        }
+    }
+
+    fn parse(role: &str) -> Option<Self> {
+        [
+            Self::Owner,
+            Self::Organizer,
+            Self::FileOrganizer,
+            Self::Writer,
+            Self::Commenter,
+            Self::Reader,
+        ]
+        .into_iter()
+        .find(|r| r.as_str() == role)
+    }
+}
+
+/// 権限を与える相手
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Grantee {
+    /// ユーザー（メールアドレス）
+    User(String),
+    /// Googleグループ（メールアドレス）
+    Group(String),
+    /// ドメインの全員（`example.com`など）
+    Domain(String),
+    /// リンクを知っている全員
+    Anyone,
+}
+
+impl Grantee {
+    fn type_str(&self) -> &'static str {
+        match self {
+            Self::User(_) => "user",
+            Self::Group(_) => "group",
+            Self::Domain(_) => "domain",
+            Self::Anyone => "anyone",
+        }
+    }
+}
+
+/// ファイルやフォルダの権限
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permission {
+    /// 権限のID
+    pub id: String,
+
+    /// 権限を与えた相手
+    ///
+    /// 削除されたアカウントや、見る権限が無い場合などでメールアドレスやドメインが
+    /// 返されなかった場合は`None`。
+    pub grantee: Option<Grantee>,
+
+    /// 役割
+    pub role: Role,
+
+    /// 相手の表示名
+    pub display_name: Option<String>,
+
+    /// `Domain`、`Anyone`の場合に、検索で見つけられるか
+    pub allow_file_discovery: Option<bool>,
+
+    /// 権限の有効期限
+    pub expiration_time: Option<DateTime<Utc>>,
+
+    /// 相手のアカウントが削除されているか
+    pub deleted: Option<bool>,
+
+    /// オーナーの譲渡を保留しているか
+    pub pending_owner: Option<bool>,
+}
+
+impl Permission {
+    /// Google Drive APIのPermissionオブジェクトからPermissionを作成
+    ///
+    /// `id`、`type`、`role`が無い場合は`Error::MetaIsNull`を返す。
+    /// ユーザーやグループのメールアドレス、ドメインが無い場合は`grantee`を`None`にする。
+    fn new(permission: google_drive3::api::Permission) -> Result<Self, Error> {
+        let Some(id) = permission.id else {
+            return Err(Error::MetaIsNull("id"));
+        };
+        let Some(role) = permission.role else {
+            return Err(Error::MetaIsNull("role"));
+        };
+        let role = Role::parse(&role).ok_or_else(|| unknown("role", &role))?;
+        let grantee = match permission.type_.as_deref() {
+            Some("user") => permission.email_address.map(Grantee::User),
+            Some("group") => permission.email_address.map(Grantee::Group),
+            Some("domain") => permission.domain.map(Grantee::Domain),
+            Some("anyone") => Some(Grantee::Anyone),
+            Some(t) => return Err(unknown("type", t)),
+            None => return Err(Error::MetaIsNull("type")),
+        };
+        Ok(Self {
+            id,
+            grantee,
+            role,
+            display_name: permission.display_name,
+            allow_file_discovery: permission.allow_file_discovery,
+            expiration_time: permission.expiration_time,
+            deleted: permission.deleted,
+            pending_owner: permission.pending_owner,
+        })
+    }
+}
+
+/// 解釈できない値
+fn unknown(field: &str, value: &str) -> Error {
+    <serde_json::Error as serde::de::Error>::custom(format!("unknown {}: {}", field, value)).into()
+}
+
+/// [`GDrive::create_permission`]のオプション
+#[derive(Debug, Clone, Default)]
+pub struct ShareOptions {
+    /// 通知メールを送るか。`None`ならAPIの既定（ユーザーとグループには送る）
+    pub send_notification_email: Option<bool>,
+    /// 通知メールに添えるメッセージ
+    pub email_message: Option<String>,
+    /// オーナーを譲渡する。`Role::Owner`の場合は常に譲渡する
+    pub transfer_ownership: bool,
+    /// `Domain`、`Anyone`の場合に、検索で見つけられるようにするか
+    pub allow_file_discovery: Option<bool>,
+    /// 権限の有効期限
+    pub expiration_time: Option<DateTime<Utc>>,
+}
+
+impl GDrive {
+    /// ファイルやフォルダの権限の一覧を取得する（`permissions.list`）
+    pub async fn permissions(&self, id: &GDriveId) -> Result<Vec<Permission>, Error> {
+        let fields = format!("nextPageToken,permissions({})", FIELDS);
+        let mut result = Vec::new();
+        let mut next_page_token: Option<String> = None;
+        loop {
+            let (_, list) = self
+                .retry(|| {
+                    let x = self
+                        .hub
+                        .permissions()
+                        .list(id.as_ref())
+                        .param("fields", &fields)
+                        .supports_all_drives(true)
+                        .add_scopes(&self.scopes);
+                    let x = match next_page_token {
+                        Some(ref y) => x.page_token(y),
+                        None => x,
+                    };
+                    async { Ok(x.doit().await?) }
+                })
+                .await?;
+            for permission in list.permissions.unwrap_or_default() {
+                result.push(Permission::new(permission)?);
+            }
+            match list.next_page_token {
+                Some(token) => next_page_token = Some(token),
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// ファイルやフォルダを共有する（`permissions.create`）
+    ///
+    /// 作成された後にエラーになった可能性があるため、失敗してもリトライしません。
+    /// リトライすると通知メールが 2 回送られることがあります。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, Grantee, Role, ShareOptions, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///     let report = drive.resolve_path("My Drive/Reports/q1.pdf", Default::default()).await?;
+    ///
+    ///     // メッセージを添えて閲覧者として共有する
+    ///     let opts = ShareOptions {
+    ///         email_message: Some("今期のレポートです".into()),
+    ///         ..Default::default()
+    ///     };
+    ///     let user = Grantee::User("alice@example.com".into());
+    ///     drive.create_permission(&report.id, &user, Role::Reader, &opts).await?;
+    ///
+    ///     // リンクを知っている全員が閲覧できるようにする
+    ///     drive.create_permission(&report.id, &Grantee::Anyone, Role::Reader, &ShareOptions::default()).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_permission(
+        &self,
+        id: &GDriveId,
+        grantee: &Grantee,
+        role: Role,
+        opts: &ShareOptions,
+    ) -> Result<Permission, Error> {
+        let mut request = google_drive3::api::Permission {
+            type_: Some(grantee.type_str().into()),
+            role: Some(role.as_str().into()),
+            allow_file_discovery: opts.allow_file_discovery,
+            expiration_time: opts.expiration_time,
+            ..Default::default()
+        };
+        match grantee {
+            Grantee::User(email) | Grantee::Group(email) => {
+                request.email_address = Some(email.clone())
+            }
+            Grantee::Domain(domain) => request.domain = Some(domain.clone()),
+            Grantee::Anyone => {}
+        }
+        let (_, permission) = self
+            .once(|| {
+                let x = self
+                    .hub
+                    .permissions()
+                    .create(request, id.as_ref())
+                    .param("fields", FIELDS)
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes);
+                let x = if opts.transfer_ownership || role == Role::Owner {
+                    x.transfer_ownership(true)
+                } else {
+                    x
+                };
+                let x = match opts.send_notification_email {
+                    Some(send) => x.send_notification_email(send),
+                    None => x,
+                };
+                let x = match &opts.email_message {
+                    Some(message) => x.email_message(message),
+                    None => x,
+                };
+                async { Ok(x.doit().await?) }
+            })
+            .await?;
+        Permission::new(permission)
+    }
+
+    /// 権限の役割を変更する（`permissions.update`）
+    ///
+    /// `Role::Owner`にするとオーナーを譲渡します。
+    pub async fn update_permission(
+        &self,
+        id: &GDriveId,
+        permission_id: &str,
+        role: Role,
+    ) -> Result<Permission, Error> {
+        let request = google_drive3::api::Permission {
+            role: Some(role.as_str().into()),
+            ..Default::default()
+        };
+        let (_, permission) = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .permissions()
+                    .update(request.clone(), id.as_ref(), permission_id)
+                    .param("fields", FIELDS)
+                    .transfer_ownership(role == Role::Owner)
+                    .supports_all_drives(true)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        Permission::new(permission)
+    }
+
+    /// 権限を削除する（`permissions.delete`）
+    pub async fn delete_permission(&self, id: &GDriveId, permission_id: &str) -> Result<(), Error> {
+        self.retry(|| async {
+            Ok(self
+                .hub
+                .permissions()
+                .delete(id.as_ref(), permission_id)
+                .supports_all_drives(true)
+                .add_scopes(&self.scopes)
+                .doit()
+                .await?)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::test_server::{Request, Response, TestServer};
+
+    /// Drive APIの`permissions`の代わりをするメモリ上の権限一覧
+    ///
+    /// ファイル`doc`だけがあり、`readonly`では変更できない。
+    /// `flaky`は`doc`と同じ権限一覧を持つが、POSTを処理した後に 503 を返す。
+    struct FakePermissions {
+        permissions: Vec<Value>,
+        next_id: usize,
+    }
+
+    impl FakePermissions {
+        fn handle(&mut self, request: &Request) -> Response {
+            let response = self.apply(request);
+            if request.path.starts_with("/drive/v3/files/flaky/")
+                && request.method == "POST"
+                && response.status < 300
+            {
+                return Response::json(503, json!({"error": {"code": 503, "message": "backend"}}));
+            }
+            response
+        }
+
+        fn apply(&mut self, request: &Request) -> Response {
+            let error = |code: u16, message: &str| {
+                Response::json(code, json!({"error": {"code": code, "message": message}}))
+            };
+            let path = request.path.trim_start_matches("/drive/v3/files/");
+            let segments: Vec<&str> = path.split('/').collect();
+            let (file, rest) = (segments[0], &segments[1..]);
+            if !matches!(file, "doc" | "readonly" | "flaky") {
+                return error(404, &format!("File not found: {}", file));
+            }
+            if file == "readonly" && request.method != "GET" {
+                return error(403, "The user does not have sufficient permissions.");
+            }
+            let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+            match (request.method.as_str(), rest) {
+                ("GET", ["permissions"]) => {
+                    // 1 件ずつページに分ける
+                    let page: usize = request.query("pageToken").map_or(0, |t| t.parse().unwrap());
+                    let mut list = json!({"permissions": [self.permissions[page]]});
+                    if page + 1 < self.permissions.len() {
+                        list["nextPageToken"] = (page + 1).to_string().into();
+                    }
+                    Response::json(200, list)
+                }
+                ("POST", ["permissions"]) => {
+                    if body["role"] == "owner" && request.query("transferOwnership") != Some("true")
+                    {
+                        return error(400, "transferOwnership is required");
+                    }
+                    self.next_id += 1;
+                    let mut permission = body;
+                    permission["id"] = format!("p{}", self.next_id).into();
+                    self.permissions.push(permission.clone());
+                    Response::json(200, permission)
+                }
+                ("PATCH", ["permissions", pid]) => {
+                    match self.permissions.iter_mut().find(|p| p["id"] == *pid) {
+                        Some(permission) => {
+                            permission["role"] = body["role"].clone();
+                            Response::json(200, &*permission)
+                        }
+                        None => error(404, &format!("Permission not found: {}", pid)),
+                    }
+                }
+                ("DELETE", ["permissions", pid]) => {
+                    let len = self.permissions.len();
+                    self.permissions.retain(|p| p["id"] != *pid);
+                    if self.permissions.len() == len {
+                        return error(404, &format!("Permission not found: {}", pid));
+                    }
+                    Response::new(204)
+                }
+                _ => error(400, "unexpected request"),
+            }
+        }
+    }
+
+    async fn start() -> (TestServer, GDrive) {
+        let permissions = Arc::new(Mutex::new(FakePermissions {
+            permissions: vec![
+                json!({"id": "owner", "type": "user", "role": "owner",
+                       "emailAddress": "me@example.com", "displayName": "Me"}),
+                json!({"id": "team", "type": "domain", "role": "reader",
+                       "domain": "example.com", "allowFileDiscovery": false}),
+                json!({"id": "gone", "type": "user", "role": "writer", "deleted": true}),
+            ],
+            next_id: 0,
+        }));
+        let server =
+            TestServer::start(move |request| permissions.lock().unwrap().handle(request)).await;
+        let drive = server.drive();
+        (server, drive)
+    }
+
+    #[tokio::test]
+    async fn lists_typed_permissions() {
+        let (_server, drive) = start().await;
+
+        let permissions = drive.permissions(&"doc".into()).await.unwrap();
+        assert_eq!(permissions.len(), 3);
+        assert_eq!(
+            permissions[0].grantee,
+            Some(Grantee::User("me@example.com".into()))
+        );
+        assert_eq!(permissions[0].role, Role::Owner);
+        assert_eq!(permissions[0].display_name.as_deref(), Some("Me"));
+        assert_eq!(
+            permissions[1].grantee,
+            Some(Grantee::Domain("example.com".into()))
+        );
+        assert_eq!(permissions[1].role, Role::Reader);
+        assert_eq!(permissions[1].allow_file_discovery, Some(false));
+        // 削除されたアカウントにはメールアドレスが無い
+        assert_eq!(permissions[2].grantee, None);
+        assert_eq!(permissions[2].deleted, Some(true));
+    }
+
+    #[tokio::test]
+    async fn creates_updates_and_deletes_permissions() {
+        let (server, drive) = start().await;
+        let doc: GDriveId = "doc".into();
+
+        let opts = ShareOptions {
+            send_notification_email: Some(true),
+            email_message: Some("please review".into()),
+            ..Default::default()
+        };
+        let group = Grantee::Group("team@example.com".into());
+        let created = drive
+            .create_permission(&doc, &group, Role::Commenter, &opts)
+            .await
+            .unwrap();
+        assert_eq!(created.grantee, Some(group));
+        assert_eq!(created.role, Role::Commenter);
+        let anyone = drive
+            .create_permission(
+                &doc,
+                &Grantee::Anyone,
+                Role::Reader,
+                &ShareOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(anyone.grantee, Some(Grantee::Anyone));
+
+        let updated = drive
+            .update_permission(&doc, &created.id, Role::Writer)
+            .await
+            .unwrap();
+        assert_eq!(updated.role, Role::Writer);
+
+        drive.delete_permission(&doc, &anyone.id).await.unwrap();
+        let ids: Vec<_> = drive
+            .permissions(&doc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.id)
+            .collect();
+        assert_eq!(ids, ["owner", "team", "gone", "p1"]);
+
+        let requests = server.requests();
+        let create = &requests[0];
+        assert_eq!(create.method, "POST");
+        assert_eq!(create.query("sendNotificationEmail"), Some("true"));
+        assert_eq!(create.query("emailMessage"), Some("please review"));
+        let body: Value = serde_json::from_slice(&create.body).unwrap();
+        assert_eq!(
+            body,
+            json!({"type": "group", "role": "commenter", "emailAddress": "team@example.com"})
+        );
+        assert_eq!(requests[1].query("sendNotificationEmail"), None);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_create() {
+        let (server, mut drive) = start().await;
+        drive.set_retry_policy(crate::RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        });
+        let flaky: GDriveId = "flaky".into();
+
+        let user = Grantee::User("bob@example.com".into());
+        let result = drive
+            .create_permission(&flaky, &user, Role::Reader, &ShareOptions::default())
+            .await;
+        assert!(result.is_err());
+        let posts = server
+            .requests()
+            .iter()
+            .filter(|r| r.method == "POST")
+            .count();
+        assert_eq!(posts, 1);
+        assert_eq!(drive.permissions(&flaky).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn transfers_ownership() {
+        let (server, drive) = start().await;
+        let doc: GDriveId = "doc".into();
+
+        let user = Grantee::User("bob@example.com".into());
+        let owner = drive
+            .create_permission(&doc, &user, Role::Owner, &ShareOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(owner.role, Role::Owner);
+        drive
+            .update_permission(&doc, "team", Role::Owner)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].query("transferOwnership"), Some("true"));
+        assert_eq!(requests[1].query("transferOwnership"), Some("true"));
+    }
+
+    #[tokio::test]
+    async fn reports_permission_and_not_found_errors() {
+        let (_server, drive) = start().await;
+
+        let e = drive
+            .create_permission(
+                &"readonly".into(),
+                &Grantee::Anyone,
+                Role::Reader,
+                &ShareOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::PermissionDenied(_)), "{:?}", e);
+        let e = drive.permissions(&"missing".into()).await.unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+        let e = drive
+            .delete_permission(&"doc".into(), "nobody")
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+    }
+}
@@ -0,0 +1,386 @@
+//! `files.list`の検索クエリ
+
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{GDriveId, MetaFields};
+
+/// `files.list`の検索クエリ
+///
+/// 文字列は`'`と`\`をエスケープしてクエリに埋め込みます。
+/// `and`、`or`、`!`で組み合わせることができます。
+///
+/// # 例
+///
+/// ```
+/// use google_drive::Query;
+///
+/// let q = Query::parent("folder-id")
+///     .and(Query::name_contains("Bob's"))
+///     .and(!Query::trashed(true));
+/// assert_eq!(
+///     q.to_string(),
+///     r"'folder-id' in parents and name contains 'Bob\'s' and not trashed = true"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// フォルダの直下にある
+    Parent(GDriveId),
+    /// 名前が一致する
+    NameEq(String),
+    /// 名前に含む
+    NameContains(String),
+    /// MIMEタイプが一致する
+    MimeType(String),
+    /// 指定した日時より後に更新された
+    ModifiedAfter(DateTime<Utc>),
+    /// 指定した日時より前に更新された
+    ModifiedBefore(DateTime<Utc>),
+    /// ゴミ箱にある（`true`）、無い（`false`）
+    Trashed(bool),
+    /// スターが付いている（`true`）、いない（`false`）
+    Starred(bool),
+    /// 名前、説明、内容のいずれかに含む
+    FullText(String),
+    /// 所有者のメールアドレス
+    Owner(String),
+    /// 全てを満たす。空なら条件なし
+    And(Vec<Query>),
+    /// いずれかを満たす。空なら条件なし
+    Or(Vec<Query>),
+    /// 満たさない
+    Not(Box<Query>),
+}
+
+impl Query {
+    pub fn parent<I: Into<GDriveId>>(id: I) -> Self {
+        Self::Parent(id.into())
+    }
+
+    pub fn name_eq<S: Into<String>>(name: S) -> Self {
+        Self::NameEq(name.into())
+    }
+
+    pub fn name_contains<S: Into<String>>(name: S) -> Self {
+        Self::NameContains(name.into())
+    }
+
+    pub fn mime_type<S: Into<String>>(mime_type: S) -> Self {
+        Self::MimeType(mime_type.into())
+    }
+
+    /// フォルダのみ
+    pub fn folder() -> Self {
+        Self::mime_type("application/vnd.google-apps.folder")
+    }
+
+    pub fn modified_after(time: DateTime<Utc>) -> Self {
+        Self::ModifiedAfter(time)
+    }
+
+    pub fn modified_before(time: DateTime<Utc>) -> Self {
+        Self::ModifiedBefore(time)
+    }
+
+    pub fn trashed(trashed: bool) -> Self {
+        Self::Trashed(trashed)
+    }
+
+    pub fn starred(starred: bool) -> Self {
+        Self::Starred(starred)
+    }
+
+    pub fn full_text<S: Into<String>>(text: S) -> Self {
+        Self::FullText(text.into())
+    }
+
+    pub fn owner<S: Into<String>>(email: S) -> Self {
+        Self::Owner(email.into())
+    }
+
+    /// `self`と`other`の両方を満たす
+    pub fn and(self, other: Query) -> Self {
+        match self {
+            Self::And(mut v) => {
+                v.push(other);
+                Self::And(v)
+            }
+            q => Self::And(vec![q, other]),
+        }
+    }
+
+    /// `self`と`other`のいずれかを満たす
+    pub fn or(self, other: Query) -> Self {
+        match self {
+            Self::Or(mut v) => {
+                v.push(other);
+                Self::Or(v)
+            }
+            q => Self::Or(vec![q, other]),
+        }
+    }
+
+    /// 条件が無い（空の`and`、`or`だけでできている）かどうか
+    ///
+    /// 条件が無いクエリは空文字列になり、検索では`q`を付けずに全てのアイテムを対象にします。
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::And(v) | Self::Or(v) => v.iter().all(Query::is_empty),
+            Self::Not(q) => q.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// `and`、`or`の中に書く時に括弧が必要かどうか
+    fn is_compound(&self) -> bool {
+        match self {
+            Self::And(v) | Self::Or(v) => v.iter().filter(|q| !q.is_empty()).count() > 1,
+            _ => false,
+        }
+    }
+
+    fn fmt_joined(f: &mut fmt::Formatter<'_>, v: &[Query], op: &str) -> fmt::Result {
+        for (i, q) in v.iter().filter(|q| !q.is_empty()).enumerate() {
+            if i > 0 {
+                write!(f, " {} ", op)?;
+            }
+            if q.is_compound() {
+                write!(f, "({})", q)?;
+            } else {
+                write!(f, "{}", q)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::Not for Query {
+    type Output = Query;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Self::Not(q) => *q,
+            q => Self::Not(Box::new(q)),
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parent(id) => write!(f, "{} in parents", Quoted(id.as_ref())),
+            Self::NameEq(name) => write!(f, "name = {}", Quoted(name)),
+            Self::NameContains(name) => write!(f, "name contains {}", Quoted(name)),
+            Self::MimeType(mime_type) => write!(f, "mimeType = {}", Quoted(mime_type)),
+            Self::ModifiedAfter(time) => write!(f, "modifiedTime > {}", Quoted(&rfc3339(time))),
+            Self::ModifiedBefore(time) => write!(f, "modifiedTime < {}", Quoted(&rfc3339(time))),
+            Self::Trashed(trashed) => write!(f, "trashed = {}", trashed),
+            Self::Starred(starred) => write!(f, "starred = {}", starred),
+            Self::FullText(text) => write!(f, "fullText contains {}", Quoted(text)),
+            Self::Owner(email) => write!(f, "{} in owners", Quoted(email)),
+            Self::And(v) => Self::fmt_joined(f, v, "and"),
+            Self::Or(v) => Self::fmt_joined(f, v, "or"),
+            Self::Not(q) if q.is_empty() => Ok(()),
+            Self::Not(q) if q.is_compound() => write!(f, "not ({})", q),
+            Self::Not(q) => write!(f, "not {}", q),
+        }
+    }
+}
+
+/// `'`で囲み、`'`と`\`をエスケープした文字列
+pub(crate) struct Quoted<'a>(pub(crate) &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'")?;
+        for c in self.0.chars() {
+            if c == '\'' || c == '\\' {
+                write!(f, "\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        write!(f, "'")
+    }
+}
+
+fn rfc3339(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// 並び替えのキー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// フォルダを先にする
+    Folder,
+    Name,
+    ModifiedTime,
+    CreatedTime,
+    Starred,
+    /// 使用容量
+    QuotaBytesUsed,
+}
+
+impl SortKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Folder => "folder",
+            Self::Name => "name_natural",
+            Self::ModifiedTime => "modifiedTime",
+            Self::CreatedTime => "createdTime",
+            Self::Starred => "starred",
+            Self::QuotaBytesUsed => "quotaBytesUsed",
+        }
+    }
+}
+
+/// 並び順
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBy {
+    /// 昇順
+    Asc(SortKey),
+    /// 降順
+    Desc(SortKey),
+}
+
+/// 検索する範囲（`corpora`）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Corpus {
+    /// 自分が作成、開いた、共有されたアイテム（APIの既定）
+    #[default]
+    User,
+    /// 組織内で共有されたアイテム
+    Domain,
+    /// 指定した共有ドライブの中のアイテム
+    Drive(GDriveId),
+    /// マイドライブとアクセスできる全ての共有ドライブ
+    ///
+    /// 範囲が広いため、APIが一部の結果しか返さないことがあります。
+    AllDrives,
+}
+
+impl Corpus {
+    /// `corpora`パラメータに渡す文字列。既定値なら`None`
+    pub(crate) fn corpora(&self) -> Option<&'static str> {
+        match self {
+            Self::User => None,
+            Self::Domain => Some("domain"),
+            Self::Drive(_) => Some("drive"),
+            Self::AllDrives => Some("allDrives"),
+        }
+    }
+}
+
+/// `GDrive::search`のオプション
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// 並び順。先頭のキーを優先する
+    pub order_by: Vec<OrderBy>,
+    /// 1 回のリクエストで取得する件数（1〜1000）。`None`ならAPIの既定値
+    pub page_size: Option<u32>,
+    /// 取得するフィールド。`None`なら`GDrive::set_meta_fields`の設定
+    pub fields: Option<MetaFields>,
+    /// 検索する範囲
+    pub corpus: Corpus,
+}
+
+impl SearchOptions {
+    /// `orderBy`パラメータに渡す文字列。並び順が無ければ`None`
+    pub(crate) fn order_by(&self) -> Option<String> {
+        if self.order_by.is_empty() {
+            return None;
+        }
+        let v: Vec<String> = self
+            .order_by
+            .iter()
+            .map(|o| match o {
+                OrderBy::Asc(key) => key.as_str().to_string(),
+                OrderBy::Desc(key) => format!("{} desc", key.as_str()),
+            })
+            .collect();
+        Some(v.join(","))
+    }
+
+    /// `pageSize`パラメータに渡す値。APIが受け付ける 1〜1000 に収める
+    pub(crate) fn page_size(&self) -> Option<i32> {
+        self.page_size.map(|n| n.clamp(1, 1000) as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_quotes_and_backslashes() {
+        assert_eq!(
+            Query::name_eq(r"it's a\b").to_string(),
+            r"name = 'it\'s a\\b'"
+        );
+        assert_eq!(
+            Query::owner("a@example.com").to_string(),
+            "'a@example.com' in owners"
+        );
+    }
+
+    #[test]
+    fn composes_with_parentheses() {
+        let time = "2025-01-02T03:04:05Z".parse().unwrap();
+        let q = Query::parent("p")
+            .and(Query::mime_type("text/plain").or(Query::folder()))
+            .and(!Query::starred(true).and(Query::modified_after(time)));
+        assert_eq!(
+            q.to_string(),
+            "'p' in parents \
+             and (mimeType = 'text/plain' or mimeType = 'application/vnd.google-apps.folder') \
+             and not (starred = true and modifiedTime > '2025-01-02T03:04:05Z')"
+        );
+        assert_eq!(!!Query::trashed(false), Query::trashed(false));
+        assert_eq!(
+            Query::full_text("x")
+                .or(Query::name_contains("y"))
+                .to_string(),
+            "fullText contains 'x' or name contains 'y'"
+        );
+    }
+
+    #[test]
+    fn order_by_joins_keys() {
+        let opts = SearchOptions {
+            order_by: vec![
+                OrderBy::Asc(SortKey::Folder),
+                OrderBy::Desc(SortKey::ModifiedTime),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(opts.order_by().as_deref(), Some("folder,modifiedTime desc"));
+        assert_eq!(SearchOptions::default().order_by(), None);
+    }
+
+    #[test]
+    fn clamps_page_size() {
+        let opts = |n| SearchOptions {
+            page_size: Some(n),
+            ..Default::default()
+        };
+        assert_eq!(opts(0).page_size(), Some(1));
+        assert_eq!(opts(50).page_size(), Some(50));
+        assert_eq!(opts(u32::MAX).page_size(), Some(1000));
+        assert_eq!(SearchOptions::default().page_size(), None);
+    }
+
+    #[test]
+    fn empty_and_or_are_no_filter() {
+        assert!(Query::And(vec![]).is_empty());
+        assert_eq!(Query::Or(vec![]).to_string(), "");
+        assert_eq!((!Query::And(vec![])).to_string(), "");
+        let q = Query::And(vec![
+            Query::Or(vec![]),
+            Query::name_eq("a"),
+            Query::And(vec![Query::Or(vec![]), Query::trashed(false)]),
+        ]);
+        assert!(!q.is_empty());
+        assert_eq!(q.to_string(), "name = 'a' and trashed = false");
+    }
+}
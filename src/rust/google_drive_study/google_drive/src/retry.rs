@@ -0,0 +1,460 @@
+//! 失敗したリクエストのリトライとレート制限
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use google_drive3::hyper::{HeaderMap, header::RETRY_AFTER};
+use tokio::time::Instant;
+
+use crate::{Error, GDrive};
+
+/// リトライの方針
+///
+/// 待ち時間は`initial_backoff`から試行ごとに倍になり、`max_backoff`で頭打ちになります。
+/// レスポンスに`Retry-After`があればその時間だけ待ちます。ただし`max_backoff`を超えては待ちません。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// 最初の試行を含む最大の試行回数。1 ならリトライしない
+    pub max_attempts: u32,
+    /// 1 回目のリトライまでの待ち時間
+    pub initial_backoff: Duration,
+    /// 待ち時間の上限
+    pub max_backoff: Duration,
+    /// 待ち時間を 0 から計算した時間までの間でランダムにする
+    ///
+    /// 並列に動いているリクエストが同時にリトライしないようにします。
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(32),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// リトライしない
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// `attempt`回目（1 始まり）の試行が失敗した後に待つ時間
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        if self.jitter {
+            backoff.mul_f64(fastrand::f64())
+        } else {
+            backoff
+        }
+    }
+}
+
+/// 1 秒あたりのリクエスト数を制限する
+///
+/// `Arc`で共有すると、複数の`GDrive`や並列のリクエストをまとめて制限できます。
+///
+/// # 例
+///
+/// ```skip
+/// use std::sync::Arc;
+/// use google_drive::{GDrive, RateLimiter, scope};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::READONLY], None).await?;
+///     drive.set_rate_limiter(Some(Arc::new(RateLimiter::new(10.0))));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    /// 次のリクエストを送ってよい時刻
+    next: Mutex<Instant>,
+}
+
+/// リクエストの間隔の上限。これより小さいレートはこの間隔として扱う
+const MAX_INTERVAL: Duration = Duration::from_secs(u32::MAX as u64);
+
+impl RateLimiter {
+    /// 1 秒あたり`requests_per_second`回までに制限する
+    ///
+    /// 0 以下や NaN なら制限しません。
+    pub fn new(requests_per_second: f64) -> Self {
+        let interval = if requests_per_second > 0.0 {
+            Duration::try_from_secs_f64(1.0 / requests_per_second)
+                .map_or(MAX_INTERVAL, |d| d.min(MAX_INTERVAL))
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// 次のリクエストを送れるまで待つ
+    pub async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+impl Error {
+    /// リトライすれば成功する可能性があるエラーか
+    ///
+    /// 接続の失敗、408、429、5xx、レート制限による 403 が該当します。
+    pub fn is_retryable(&self) -> bool {
+        use google_drive3::common::Error as E;
+
+        match self {
+            Error::InvalidResponse(status) => is_retryable_status(*status),
+            Error::GoogleDriveAPIError(e) => match e.as_ref() {
+                E::HttpError(_) => true,
+                E::Failure(rsp) => is_retryable_status(rsp.status().as_u16()),
+                E::BadRequest(json) => {
+                    let error = &json["error"];
+                    let status = error["code"].as_u64().unwrap_or(0) as u16;
+                    let reason = error["errors"][0]["reason"].as_str().unwrap_or("");
+                    is_retryable_status(status)
+                        || (status == 403
+                            && matches!(reason, "rateLimitExceeded" | "userRateLimitExceeded"))
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// レスポンスの`Retry-After`で指定された待ち時間
+    ///
+    /// `google_drive3`はJSONのエラーを返したレスポンスのヘッダーを保持しないため、
+    /// その場合は`None`になります。
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::GoogleDriveAPIError(e) => match e.as_ref() {
+                google_drive3::common::Error::Failure(rsp) => parse_retry_after(rsp.headers()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// 秒数かHTTP日付の`Retry-After`を解釈する
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.to_utc() - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+impl GDrive {
+    /// リトライの方針を変更する。既定は[`RetryPolicy::default`]
+    ///
+    /// 一覧、メタデータの取得、ダウンロードとエクスポートの開始、
+    /// 再開可能なアップロードのチャンクの送信がリトライの対象です。
+    /// ダウンロード中のデータの受信に失敗した場合はリトライしません。
+    /// フォルダの作成やコピーなど、繰り返すと結果が重複するリクエストもリトライしません。
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// リクエストのレート制限を設定する。`None`なら制限しない（既定）
+    pub fn set_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.rate_limiter = limiter;
+    }
+
+    /// レート制限を待つ
+    pub(crate) async fn throttle(&self) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+    }
+
+    /// `f`をリトライの方針に従って実行する
+    ///
+    /// APIのエラーは[`Error::classify`]で`PermissionDenied`や`NotFound`にして返します。
+    pub(crate) async fn retry<T, F, Fut>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            self.throttle().await;
+            match f().await.map_err(Error::classify) {
+                Err(e) if attempt < self.retry.max_attempts && e.is_retryable() => {
+                    tokio::time::sleep(self.retry_wait(&e, attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// `f`をリトライせずに 1 回だけ実行する
+    ///
+    /// 作成などの冪等でないリクエスト用です。サーバーで処理された後にエラーになった場合、
+    /// リトライすると同じものが 2 つ作られてしまいます。
+    pub(crate) async fn once<T, Fut>(&self, f: impl FnOnce() -> Fut) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.throttle().await;
+        f().await.map_err(Error::classify)
+    }
+
+    /// `attempt`回目（1 始まり）の試行が`e`で失敗した後に待つ時間
+    pub(crate) fn retry_wait(&self, e: &Error, attempt: u32) -> Duration {
+        match e.retry_after() {
+            Some(wait) => wait.min(self.retry.max_backoff),
+            None => self.retry.backoff(attempt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    const FILE_JSON: &str = r#"{
+        "id": "file-id",
+        "name": "a.txt",
+        "mimeType": "text/plain",
+        "modifiedTime": "2025-01-01T00:00:00Z",
+        "capabilities": {"canDownload": true}
+    }"#;
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            jitter: true,
+        }
+    }
+
+    /// 最初の`failures`回は`failure`を返し、その後は`FILE_JSON`を返すサーバー
+    async fn failing_server(
+        failures: usize,
+        failure: test_server::Response,
+    ) -> (TestServer, GDrive) {
+        let count = AtomicUsize::new(0);
+        let server = TestServer::start(move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) < failures {
+                failure.clone()
+            } else {
+                test_server::Response::json(200, FILE_JSON)
+            }
+        })
+        .await;
+        let mut drive = server.drive();
+        drive.set_retry_policy(fast());
+        (server, drive)
+    }
+
+    fn api_error(status: u16, reason: &str) -> test_server::Response {
+        test_server::Response::json(
+            status,
+            serde_json::json!({
+                "error": {
+                    "code": status,
+                    "message": "error",
+                    "errors": [{"reason": reason}],
+                }
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (server, drive) = failing_server(3, api_error(503, "backendError")).await;
+        let meta = drive.get_meta(&"file-id".into()).await.unwrap();
+        assert_eq!(meta.name, "a.txt");
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limit_exceeded() {
+        let (server, drive) = failing_server(1, api_error(403, "userRateLimitExceeded")).await;
+        drive.get_meta(&"file-id".into()).await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let (server, drive) = failing_server(10, api_error(500, "backendError")).await;
+        let e = drive.get_meta(&"file-id".into()).await.unwrap_err();
+        assert!(e.is_retryable());
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (server, drive) = failing_server(10, api_error(404, "notFound")).await;
+        let e = drive.get_meta(&"file-id".into()).await.unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+        assert!(!e.is_retryable());
+        assert_eq!(server.requests().len(), 1);
+
+        let (server, drive) =
+            failing_server(10, api_error(403, "insufficientFilePermissions")).await;
+        let e = drive.get_meta(&"file-id".into()).await.unwrap_err();
+        assert!(matches!(e, Error::PermissionDenied(_)), "{:?}", e);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn waits_for_retry_after() {
+        let failure = test_server::Response::new(429)
+            .header("Retry-After", "1")
+            .body("slow down");
+        let (server, mut drive) = failing_server(1, failure).await;
+        drive.set_retry_policy(RetryPolicy {
+            max_backoff: Duration::from_secs(2),
+            ..fast()
+        });
+        let start = Instant::now();
+        drive.get_meta(&"file-id".into()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn caps_retry_after_by_max_backoff() {
+        let failure = test_server::Response::new(503)
+            .header("Retry-After", "3600")
+            .body("unavailable");
+        let (server, drive) = failing_server(1, failure).await;
+        let start = Instant::now();
+        drive.get_meta(&"file-id".into()).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_list_pages() {
+        let count = AtomicUsize::new(0);
+        let server = TestServer::start(move |request| {
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            if n == 1 {
+                return api_error(503, "backendError");
+            }
+            let (id, next) = match request.query("pageToken") {
+                None => ("a", Some("page2")),
+                Some(_) => ("b", None),
+            };
+            test_server::Response::json(
+                200,
+                serde_json::json!({
+                    "nextPageToken": next,
+                    "files": [{
+                        "id": id,
+                        "name": id,
+                        "mimeType": "text/plain",
+                        "modifiedTime": "2025-01-01T00:00:00Z",
+                        "capabilities": {"canDownload": true}
+                    }],
+                }),
+            )
+        })
+        .await;
+        let mut drive = server.drive();
+        drive.set_retry_policy(fast());
+
+        let items = drive.list(&"folder".into()).await.unwrap();
+        let ids: Vec<_> = items.iter().map(|m| m.id.as_ref()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_is_shared_by_concurrent_requests() {
+        let (server, mut drive) = failing_server(0, api_error(500, "")).await;
+        drive.set_rate_limiter(Some(Arc::new(RateLimiter::new(20.0))));
+        let start = Instant::now();
+        let id = "file-id".into();
+        let requests = (0..5).map(|_| drive.get_meta(&id));
+        for result in futures::future::join_all(requests).await {
+            result.unwrap();
+        }
+        // 1 つ目はすぐに送れるため、残りの 4 つ分待つ
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_accepts_any_rate() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let limiter = RateLimiter::new(rate);
+            let start = Instant::now();
+            for _ in 0..100 {
+                limiter.acquire().await;
+            }
+            assert!(start.elapsed() < Duration::from_secs(1), "{}", rate);
+        }
+        for rate in [f64::MIN_POSITIVE, 1e-300, f64::INFINITY] {
+            let limiter = RateLimiter::new(rate);
+            assert!(limiter.interval <= MAX_INTERVAL);
+            limiter.acquire().await;
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        let secs: Vec<_> = (1..=7).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(secs, [1, 2, 4, 8, 16, 32, 32]);
+        let jittered = RetryPolicy::default().backoff(3);
+        assert!(jittered <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+}
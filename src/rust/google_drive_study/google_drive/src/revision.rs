@@ -0,0 +1,422 @@
+//! 版（リビジョン）の一覧、古い版のダウンロード、版の保持
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use google_drive3::hyper::{Request, body::Body};
+
+use crate::{
+    DownloadHandler, Error, GDrive, GDriveId, Owner,
+    checksum::{Checksum, Hashing},
+};
+
+/// 取得する版のフィールド
+const FIELDS: &str = "id,mimeType,modifiedTime,size,md5Checksum,keepForever,originalFilename,\
+                      lastModifyingUser(displayName,emailAddress,permissionId,me),exportLinks";
+
+/// ファイルの版
+#[derive(Debug, Clone, Default)]
+pub struct Revision {
+    /// 版のID
+    pub id: String,
+
+    /// MIMEタイプ
+    pub mime_type: Option<String>,
+
+    /// 更新日時
+    pub modified_time: Option<DateTime<Utc>>,
+
+    /// サイズ（バイト）。Google Apps形式のファイルには無い
+    pub size: Option<u64>,
+
+    /// MD5チェックサム。Google Apps形式のファイルには無い
+    pub md5_checksum: Option<String>,
+
+    /// 自動で削除されないように保持されているか。Google Apps形式のファイルには無い
+    pub keep_forever: Option<bool>,
+
+    /// アップロードした時のファイル名
+    pub original_filename: Option<String>,
+
+    /// この版を作成したユーザー
+    pub author: Option<Owner>,
+
+    /// Google Apps形式のファイルのエクスポート先（MIMEタイプからURL）
+    pub export_links: HashMap<String, String>,
+}
+
+impl Revision {
+    /// Google Drive APIのRevisionオブジェクトからRevisionを作成
+    ///
+    /// `id`が無い場合は`Error::MetaIsNull`を返す。
+    fn new(revision: google_drive3::api::Revision) -> Result<Self, Error> {
+        let Some(id) = revision.id else {
+            return Err(Error::MetaIsNull("id"));
+        };
+        Ok(Self {
+            id,
+            mime_type: revision.mime_type,
+            modified_time: revision.modified_time,
+            size: revision.size.map(|s| s as u64),
+            md5_checksum: revision.md5_checksum,
+            keep_forever: revision.keep_forever,
+            original_filename: revision.original_filename,
+            author: revision.last_modifying_user.map(|u| Owner {
+                display_name: u.display_name,
+                email_address: u.email_address,
+                permission_id: u.permission_id,
+                me: u.me,
+            }),
+            export_links: revision.export_links.unwrap_or_default(),
+        })
+    }
+}
+
+impl GDrive {
+    /// ファイルの版の一覧を古い順に取得する（`revisions.list`）
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///     let file_id: GDriveId = "your_file_id".into();
+    ///
+    ///     for revision in drive.revisions(&file_id).await? {
+    ///         let author = revision.author.and_then(|a| a.display_name);
+    ///         println!("{} {:?} {:?}", revision.id, revision.modified_time, author);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn revisions(&self, id: &GDriveId) -> Result<Vec<Revision>, Error> {
+        let fields = format!("nextPageToken,revisions({})", FIELDS);
+        let mut result = Vec::new();
+        let mut next_page_token: Option<String> = None;
+        loop {
+            let (_, list) = self
+                .retry(|| {
+                    let x = self
+                        .hub
+                        .revisions()
+                        .list(id.as_ref())
+                        .param("fields", &fields)
+                        .page_size(1000)
+                        .add_scopes(&self.scopes);
+                    let x = match next_page_token {
+                        Some(ref y) => x.page_token(y),
+                        None => x,
+                    };
+                    async { Ok(x.doit().await?) }
+                })
+                .await?;
+            for revision in list.revisions.unwrap_or_default() {
+                result.push(Revision::new(revision)?);
+            }
+            match list.next_page_token {
+                Some(token) => next_page_token = Some(token),
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// 版の情報を取得する（`revisions.get`）
+    pub async fn revision(&self, id: &GDriveId, revision_id: &str) -> Result<Revision, Error> {
+        let (_, revision) = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .revisions()
+                    .get(id.as_ref(), revision_id)
+                    .param("fields", FIELDS)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        Revision::new(revision)
+    }
+
+    /// 指定した版の内容をダウンロードし、カスタムハンドラで受け取る
+    ///
+    /// Google Apps形式のファイルは[`GDrive::export_revision`]を使ってください。
+    /// MD5チェックサムがあれば、受信したデータと一致するか確かめます。
+    pub async fn download_revision<H>(
+        &self,
+        id: &GDriveId,
+        revision_id: &str,
+        handler: H,
+    ) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let revision = self.revision(id, revision_id).await?;
+        handler
+            .set_size(revision.size.unwrap_or(0) as usize)
+            .await?;
+        let checksum = Checksum::from_md5(id, revision.md5_checksum.as_deref());
+        let handler = Arc::new(Hashing::new(handler, checksum));
+        let url = format!(
+            "{}drive/v3/files/{}/revisions/{}?alt=media",
+            self.root_url,
+            urlencoding::encode(id.as_ref()),
+            urlencoding::encode(revision_id)
+        );
+        let rsp = self
+            .retry(|| self.send_streaming(Request::get(&url)))
+            .await?;
+        Self::receive(rsp, handler.clone()).await.1?;
+        handler.verify()
+    }
+
+    /// Google Apps形式のファイルの指定した版を、`mime_type`でエクスポートしてカスタムハンドラで受け取る
+    ///
+    /// その版に`mime_type`のエクスポート先が無い場合は`Error::NotExportable`を返します。
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{ExportFormat, GDrive, GDriveId, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///     let sheet: GDriveId = "your_spreadsheet_id".into();
+    ///
+    ///     // 最初の版をExcel形式で取り出す。MyHandlerはdownloadの例を参照
+    ///     let first = drive.revisions(&sheet).await?.remove(0);
+    ///     let data = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+    ///     drive.export_revision(&sheet, &first.id, ExportFormat::Xlsx.mime_type(), MyHandler(data.clone())).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn export_revision<H>(
+        &self,
+        id: &GDriveId,
+        revision_id: &str,
+        mime_type: &str,
+        handler: H,
+    ) -> Result<(), Error>
+    where
+        H: DownloadHandler + Sync + Send + 'static,
+    {
+        let revision = self.revision(id, revision_id).await?;
+        let Some(url) = revision.export_links.get(mime_type) else {
+            return Err(Error::NotExportable(mime_type.into()));
+        };
+        let rsp = self
+            .retry(|| self.send_streaming(Request::get(url)))
+            .await?;
+        let hint = rsp.size_hint();
+        handler
+            .set_size(hint.upper().unwrap_or(hint.lower()) as usize)
+            .await?;
+        Self::receive(rsp, Arc::new(handler)).await.1
+    }
+
+    /// 版を自動で削除されないように保持する
+    ///
+    /// Google Apps形式ではないファイルの版だけが保持できます。1 つのファイルで保持できる版は 200 までです。
+    pub async fn pin_revision(&self, id: &GDriveId, revision_id: &str) -> Result<Revision, Error> {
+        self.set_keep_forever(id, revision_id, true).await
+    }
+
+    /// 版の保持をやめる。最新の版でなければ、やがて自動で削除されます
+    pub async fn unpin_revision(
+        &self,
+        id: &GDriveId,
+        revision_id: &str,
+    ) -> Result<Revision, Error> {
+        self.set_keep_forever(id, revision_id, false).await
+    }
+
+    async fn set_keep_forever(
+        &self,
+        id: &GDriveId,
+        revision_id: &str,
+        keep_forever: bool,
+    ) -> Result<Revision, Error> {
+        let request = google_drive3::api::Revision {
+            keep_forever: Some(keep_forever),
+            ..Default::default()
+        };
+        let (_, revision) = self
+            .retry(|| async {
+                Ok(self
+                    .hub
+                    .revisions()
+                    .update(request.clone(), id.as_ref(), revision_id)
+                    .param("fields", FIELDS)
+                    .add_scopes(&self.scopes)
+                    .doit()
+                    .await?)
+            })
+            .await?;
+        Revision::new(revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use google_drive3::hyper::body::Bytes;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        ExportFormat,
+        test_server::{self, TestServer},
+    };
+
+    /// ファイル`bin`に 2 つの版、ドキュメント`doc`に 1 つの版があるサーバー
+    async fn revisions_server(md5: &'static str) -> TestServer {
+        let keep = Mutex::new(false);
+        TestServer::start(move |request| {
+            let keep_forever = {
+                let mut keep = keep.lock().unwrap();
+                if request.method == "PATCH" {
+                    let body: Value = serde_json::from_slice(&request.body).unwrap();
+                    *keep = body["keepForever"].as_bool().unwrap();
+                }
+                *keep
+            };
+            let old = json!({
+                "id": "r1", "mimeType": "text/plain", "modifiedTime": "2025-01-01T00:00:00Z",
+                "size": "3", "md5Checksum": md5, "keepForever": keep_forever,
+                "lastModifyingUser": {"displayName": "Alice", "emailAddress": "alice@example.com"},
+            });
+            let host = request.header("host").unwrap_or_default();
+            let doc = json!({
+                "id": "d1", "mimeType": "application/vnd.google-apps.document",
+                "exportLinks": {"text/markdown": format!("http://{}/export/d1.md", host)},
+            });
+            match request.path.as_str() {
+                "/drive/v3/files/bin/revisions" => {
+                    let list = match request.query("pageToken") {
+                        None => json!({"nextPageToken": "2", "revisions": [old]}),
+                        Some(_) => json!({"revisions": [{"id": "r2", "size": "5"}]}),
+                    };
+                    test_server::Response::json(200, list)
+                }
+                "/drive/v3/files/bin/revisions/r1" if request.query("alt") == Some("media") => {
+                    test_server::Response::new(200).body(b"old".to_vec())
+                }
+                "/drive/v3/files/bin/revisions/r1" => test_server::Response::json(200, old),
+                "/drive/v3/files/doc/revisions/d1" => test_server::Response::json(200, doc),
+                "/export/d1.md" => test_server::Response::new(200).body(b"# old".to_vec()),
+                _ => {
+                    test_server::Response::json(404, r#"{"error": {"code": 404, "message": "no"}}"#)
+                }
+            }
+        })
+        .await
+    }
+
+    /// 受け取ったデータを保持するハンドラ
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl DownloadHandler for Collect {
+        async fn set_size(&self, _size: usize) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn write(&self, b: Bytes) -> Result<(), Error> {
+            self.0.lock().unwrap().extend_from_slice(&b);
+            Ok(())
+        }
+    }
+
+    // "old"のMD5
+    const OLD_MD5: &str = "149603e6c03516362a8da23f624db945";
+
+    #[tokio::test]
+    async fn lists_typed_revisions() {
+        let server = revisions_server(OLD_MD5).await;
+        let drive = server.drive();
+
+        let revisions = drive.revisions(&"bin".into()).await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].id, "r1");
+        assert_eq!(revisions[0].size, Some(3));
+        assert_eq!(revisions[0].keep_forever, Some(false));
+        assert!(revisions[0].modified_time.is_some());
+        let author = revisions[0].author.as_ref().unwrap();
+        assert_eq!(author.display_name.as_deref(), Some("Alice"));
+        assert_eq!(revisions[1].id, "r2");
+        assert_eq!(revisions[1].author, None);
+
+        let e = drive.revisions(&"missing".into()).await.unwrap_err();
+        assert!(matches!(e, Error::NotFound(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn downloads_and_exports_old_revisions() {
+        let server = revisions_server(OLD_MD5).await;
+        let drive = server.drive();
+
+        let data = Collect::default();
+        drive
+            .download_revision(&"bin".into(), "r1", data.clone())
+            .await
+            .unwrap();
+        assert_eq!(*data.0.lock().unwrap(), b"old");
+
+        let data = Collect::default();
+        drive
+            .export_revision(
+                &"doc".into(),
+                "d1",
+                ExportFormat::Markdown.mime_type(),
+                data.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(*data.0.lock().unwrap(), b"# old");
+
+        let e = drive
+            .export_revision(
+                &"doc".into(),
+                "d1",
+                ExportFormat::Pdf.mime_type(),
+                Collect::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotExportable(_)), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupted_revision() {
+        let server = revisions_server("00000000000000000000000000000000").await;
+        let drive = server.drive();
+
+        let e = drive
+            .download_revision(&"bin".into(), "r1", Collect::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::ChecksumMismatch { .. }), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn pins_and_unpins_revisions() {
+        let server = revisions_server(OLD_MD5).await;
+        let drive = server.drive();
+
+        let pinned = drive.pin_revision(&"bin".into(), "r1").await.unwrap();
+        assert_eq!(pinned.keep_forever, Some(true));
+        let unpinned = drive.unpin_revision(&"bin".into(), "r1").await.unwrap();
+        assert_eq!(unpinned.keep_forever, Some(false));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "PATCH");
+        let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body, json!({"keepForever": true}));
+    }
+}
@@ -0,0 +1,613 @@
+//! 認証トークンの保存先
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use argon2::Argon2;
+use async_trait::async_trait;
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng, rand_core::RngCore},
+};
+use google_drive3::yup_oauth2::storage::{TokenInfo, TokenStorage};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::Error;
+
+/// 暗号化したファイルの先頭に付ける識別子
+const MAGIC: &[u8] = b"GDTOKEN1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// 保存されたトークンを読み書きするコールバック
+///
+/// OSのキーチェーンなど、このクレートが対応していない場所に保存する場合に実装します。
+/// データはトークンをシリアライズしたバイト列で、中身を解釈する必要はありません。
+pub trait TokenCallback: Send + Sync {
+    /// 保存したデータを読み込む。まだ無ければ`None`
+    fn load(&self) -> Result<Option<Vec<u8>>, Error>;
+
+    /// データを保存する
+    fn save(&self, data: &[u8]) -> Result<(), Error>;
+
+    /// 保存したデータを削除する
+    fn clear(&self) -> Result<(), Error>;
+}
+
+/// 認証トークンの保存先
+///
+/// `GDrive::oauth`などの`save_token`に渡します。パスを渡した場合は`TokenStore::File`になります。
+#[derive(Clone)]
+pub enum TokenStore {
+    /// メモリ上にだけ保持する。プロセスが終了すると失われる
+    Memory,
+    /// ファイルに平文で保存する。Unixでは所有者だけが読み書きできるパーミッションにする
+    File(PathBuf),
+    /// パスフレーズから作った鍵で暗号化してファイルに保存する
+    ///
+    /// 鍵はArgon2id、暗号化はXChaCha20-Poly1305です。
+    EncryptedFile { path: PathBuf, passphrase: String },
+    /// コールバックで読み書きする
+    Custom(Arc<dyn TokenCallback>),
+}
+
+impl std::fmt::Debug for TokenStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenStore::Memory => f.write_str("Memory"),
+            TokenStore::File(path) => f.debug_tuple("File").field(path).finish(),
+            TokenStore::EncryptedFile { path, .. } => f
+                .debug_struct("EncryptedFile")
+                .field("path", path)
+                .finish_non_exhaustive(),
+            TokenStore::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl From<&str> for TokenStore {
+    fn from(path: &str) -> Self {
+        TokenStore::File(path.into())
+    }
+}
+
+impl From<String> for TokenStore {
+    fn from(path: String) -> Self {
+        TokenStore::File(path.into())
+    }
+}
+
+impl From<&Path> for TokenStore {
+    fn from(path: &Path) -> Self {
+        TokenStore::File(path.into())
+    }
+}
+
+impl From<PathBuf> for TokenStore {
+    fn from(path: PathBuf) -> Self {
+        TokenStore::File(path)
+    }
+}
+
+impl From<&PathBuf> for TokenStore {
+    fn from(path: &PathBuf) -> Self {
+        TokenStore::File(path.clone())
+    }
+}
+
+impl TokenStore {
+    /// 保存したデータを読み込む。まだ無ければ`None`
+    async fn load(&self) -> Result<Option<Vec<u8>>, Error> {
+        async fn read(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+            match tokio::fs::read(path).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        match self {
+            TokenStore::Memory => Ok(None),
+            TokenStore::File(path) => Ok(read(path).await?),
+            TokenStore::EncryptedFile { path, passphrase } => match read(path).await? {
+                Some(data) => {
+                    let passphrase = passphrase.clone();
+                    tokio::task::spawn_blocking(move || decrypt(&data, &passphrase))
+                        .await
+                        .map_err(std::io::Error::other)?
+                        .map(Some)
+                }
+                None => Ok(None),
+            },
+            TokenStore::Custom(callback) => callback.load(),
+        }
+    }
+
+    /// データを保存する
+    async fn save(&self, data: Vec<u8>) -> Result<(), Error> {
+        match self {
+            TokenStore::Memory => Ok(()),
+            TokenStore::File(path) => Ok(write_private(path, data).await?),
+            TokenStore::EncryptedFile { path, passphrase } => {
+                let passphrase = passphrase.clone();
+                let data = tokio::task::spawn_blocking(move || encrypt(&data, &passphrase))
+                    .await
+                    .map_err(std::io::Error::other)??;
+                Ok(write_private(path, data).await?)
+            }
+            TokenStore::Custom(callback) => callback.save(&data),
+        }
+    }
+
+    /// 保存したデータを削除する
+    async fn clear(&self) -> Result<(), Error> {
+        match self {
+            TokenStore::Memory => Ok(()),
+            TokenStore::File(path) | TokenStore::EncryptedFile { path, .. } => {
+                match tokio::fs::remove_file(path).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                    _ => Ok(()),
+                }
+            }
+            TokenStore::Custom(callback) => callback.clear(),
+        }
+    }
+}
+
+/// 保存する 1 つのトークン。`yup_oauth2`がファイルに保存する形式と同じ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    scopes: Vec<String>,
+    token: TokenInfo,
+}
+
+/// `TokenStore`を`yup_oauth2`の`TokenStorage`として使うためのもの
+///
+/// 読み込んだトークンはメモリに保持し、変更があった時だけ保存先に書き込む。
+/// `GDrive`からログアウトのために参照するため`Arc`で共有する。
+pub(crate) struct Tokens {
+    store: TokenStore,
+    /// 読み込む前は`None`
+    entries: Mutex<Option<Vec<Entry>>>,
+}
+
+impl Tokens {
+    pub(crate) fn new(store: TokenStore) -> Arc<Self> {
+        Arc::new(Self {
+            store,
+            entries: Mutex::new(None),
+        })
+    }
+
+    /// 保存先から読み込んだトークンに対して`f`を実行する
+    async fn with_entries<R>(&self, f: impl FnOnce(&mut Vec<Entry>) -> R) -> Result<R, Error> {
+        let mut entries = self.entries.lock().await;
+        Ok(f(self.loaded(&mut entries).await?))
+    }
+
+    async fn loaded<'a>(
+        &self,
+        entries: &'a mut Option<Vec<Entry>>,
+    ) -> Result<&'a mut Vec<Entry>, Error> {
+        if entries.is_none() {
+            let loaded = match self.store.load().await? {
+                Some(data) => serde_json::from_slice(&data)?,
+                None => Vec::new(),
+            };
+            *entries = Some(loaded);
+        }
+        Ok(entries.as_mut().unwrap())
+    }
+
+    /// `scopes`のトークンを更新して保存する
+    ///
+    /// 保存が終わるまでロックを保持し、古い内容で上書きしないようにする。
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> Result<(), Error> {
+        let mut guard = self.entries.lock().await;
+        let entries = self.loaded(&mut guard).await?;
+        match entries.iter_mut().find(|e| same_scopes(&e.scopes, scopes)) {
+            Some(e) => e.token = token,
+            None => entries.push(Entry {
+                scopes: scopes.iter().map(|s| s.to_string()).collect(),
+                token,
+            }),
+        }
+        let data = serde_json::to_vec(entries)?;
+        self.store.save(data).await
+    }
+
+    /// ログアウト時に無効化するトークン。リフレッシュトークンがあればそれを優先する
+    pub(crate) async fn revocable_token(&self) -> Result<Option<String>, Error> {
+        self.with_entries(|entries| {
+            let tokens = entries.iter().map(|e| &e.token);
+            tokens
+                .clone()
+                .find_map(|t| t.refresh_token.clone())
+                .or_else(|| tokens.clone().find_map(|t| t.access_token.clone()))
+        })
+        .await
+    }
+
+    /// 全てのトークンを削除する
+    pub(crate) async fn clear(&self) -> Result<(), Error> {
+        let mut entries = self.entries.lock().await;
+        *entries = Some(Vec::new());
+        self.store.clear().await
+    }
+}
+
+/// `Authenticator`に渡すためのラッパー
+pub(crate) struct SharedTokens(pub(crate) Arc<Tokens>);
+
+#[async_trait]
+impl TokenStorage for SharedTokens {
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> anyhow::Result<()> {
+        self.0
+            .set(scopes, token)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    async fn get(&self, scopes: &[&str]) -> Option<TokenInfo> {
+        self.0
+            .with_entries(|entries| {
+                entries
+                    .iter()
+                    .find(|e| scopes.iter().all(|s| e.scopes.iter().any(|t| t == s)))
+                    .map(|e| e.token.clone())
+            })
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+fn same_scopes(a: &[String], b: &[&str]) -> bool {
+    a.len() == b.len() && b.iter().all(|s| a.iter().any(|t| t == s))
+}
+
+/// 所有者だけが読み書きできるファイルに書き込む
+///
+/// 一時ファイルに書いてから置き換えるため、途中で失敗しても元のファイルは壊れない。
+/// `sync_all`で待たされるため、ブロッキング用のスレッドで書き込む。
+async fn write_private(path: &Path, data: Vec<u8>) -> std::io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_private_blocking(&path, &data))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+fn write_private_blocking(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+
+    let mut options = std::fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    // 既にあったファイルはモードが変わらないため設定し直す
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, Error> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::TokenStoreError(e.to_string()))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// `MAGIC`、ソルト、ノンス、暗号文の順に並べる
+fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = cipher(passphrase, &salt)?
+        .encrypt(&nonce, data)
+        .map_err(|_| Error::TokenStoreError("failed to encrypt tokens".into()))?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + encrypted.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&encrypted);
+    Ok(out)
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::TokenStoreError("not an encrypted token file".into());
+    let data = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(invalid());
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, encrypted) = data.split_at(NONCE_LEN);
+    cipher(passphrase, salt)?
+        .decrypt(XNonce::from_slice(nonce), encrypted)
+        .map_err(|_| Error::TokenStoreError("wrong passphrase or corrupted token file".into()))
+}
+
+/// 名前を付けたアカウントごとのトークンの保存先
+///
+/// 1 つのディレクトリの下に、アカウントごとのトークンファイル`<name>.json`を置きます。
+/// 個人用とチーム用のDriveを切り替えて使う場合などに使います。
+///
+/// # 例
+///
+/// ```skip
+/// use google_drive::{GDrive, Profiles, scope};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let profiles = Profiles::new("./profiles");
+///     let name = profiles.default_profile().await?.unwrap_or("personal".into());
+///     let drive = GDrive::oauth(
+///         "client_secret.json",
+///         profiles.store(&name)?,
+///         &[scope::READONLY],
+///         None,
+///     ).await?;
+///     profiles.set_default_profile(&name).await?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    dir: PathBuf,
+    passphrase: Option<String>,
+}
+
+/// 既定のプロファイル名を保存するファイル
+const DEFAULT_PROFILE_FILE: &str = "default";
+
+impl Profiles {
+    /// `dir`の下にトークンを平文で保存する
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            passphrase: None,
+        }
+    }
+
+    /// `dir`の下にトークンを暗号化して保存する
+    pub fn encrypted<P: AsRef<Path>>(dir: P, passphrase: &str) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            passphrase: Some(passphrase.into()),
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        let valid = !name.is_empty()
+            && name != DEFAULT_PROFILE_FILE
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+            && !name.starts_with('.');
+        if !valid {
+            return Err(Error::TokenStoreError(format!(
+                "invalid profile name: {}",
+                name
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// プロファイル`name`のトークンの保存先
+    ///
+    /// 名前に使えるのは英数字と`-_.@`です。
+    pub fn store(&self, name: &str) -> Result<TokenStore, Error> {
+        let path = self.path(name)?;
+        Ok(match &self.passphrase {
+            Some(passphrase) => TokenStore::EncryptedFile {
+                path,
+                passphrase: passphrase.clone(),
+            },
+            None => TokenStore::File(path),
+        })
+    }
+
+    /// トークンが保存されているプロファイルの名前。名前順
+    pub async fn list(&self) -> Result<Vec<String>, Error> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if let Some(name) = name.to_str().and_then(|n| n.strip_suffix(".json"))
+                && self.path(name).is_ok()
+            {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// プロファイル`name`のトークンを削除する
+    ///
+    /// トークンは無効化しないため、先に`GDrive::logout`を呼んでください。
+    pub async fn remove(&self, name: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(name)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        if self.default_profile().await?.as_deref() == Some(name) {
+            tokio::fs::remove_file(self.dir.join(DEFAULT_PROFILE_FILE)).await?;
+        }
+        Ok(())
+    }
+
+    /// 既定のプロファイルの名前
+    pub async fn default_profile(&self) -> Result<Option<String>, Error> {
+        match tokio::fs::read_to_string(self.dir.join(DEFAULT_PROFILE_FILE)).await {
+            Ok(name) => Ok(Some(name.trim().to_string()).filter(|n| !n.is_empty())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 既定のプロファイルを設定する
+    pub async fn set_default_profile(&self, name: &str) -> Result<(), Error> {
+        self.path(name)?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        Ok(tokio::fs::write(self.dir.join(DEFAULT_PROFILE_FILE), format!("{}\n", name)).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(access: &str, refresh: Option<&str>) -> TokenInfo {
+        TokenInfo {
+            access_token: Some(access.into()),
+            refresh_token: refresh.map(Into::into),
+            expires_at: None,
+            id_token: None,
+        }
+    }
+
+    async fn round_trip(store: TokenStore) {
+        let tokens = SharedTokens(Tokens::new(store.clone()));
+        assert!(tokens.get(&["a"]).await.is_none());
+        tokens
+            .set(&["a", "b"], token("t1", Some("r1")))
+            .await
+            .unwrap();
+
+        // 別のインスタンスから読み込めること
+        let tokens = SharedTokens(Tokens::new(store));
+        let t = tokens.get(&["a"]).await.unwrap();
+        assert_eq!(t.access_token.as_deref(), Some("t1"));
+        assert!(tokens.get(&["c"]).await.is_none());
+        assert_eq!(
+            tokens.0.revocable_token().await.unwrap().as_deref(),
+            Some("r1")
+        );
+
+        tokens.0.clear().await.unwrap();
+        assert!(tokens.get(&["a"]).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        round_trip(TokenStore::File(path.clone())).await;
+        assert!(!path.exists());
+
+        let tokens = SharedTokens(Tokens::new(TokenStore::File(path.clone())));
+        tokens.set(&["a"], token("t1", None)).await.unwrap();
+        // yup_oauth2と同じ形式
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json[0]["scopes"][0], "a");
+        assert_eq!(json[0]["token"]["access_token"], "t1");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypted_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.bin");
+        let store = TokenStore::EncryptedFile {
+            path: path.clone(),
+            passphrase: "secret".into(),
+        };
+        round_trip(store.clone()).await;
+
+        let tokens = SharedTokens(Tokens::new(store));
+        tokens
+            .set(&["a"], token("plain-access-token", None))
+            .await
+            .unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert!(!data.windows(18).any(|w| w == b"plain-access-token"));
+
+        let wrong = Tokens::new(TokenStore::EncryptedFile {
+            path,
+            passphrase: "wrong".into(),
+        });
+        assert!(matches!(
+            wrong.revocable_token().await,
+            Err(Error::TokenStoreError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn custom_store() {
+        #[derive(Default)]
+        struct Callback(std::sync::Mutex<Option<Vec<u8>>>);
+        impl TokenCallback for Callback {
+            fn load(&self) -> Result<Option<Vec<u8>>, Error> {
+                Ok(self.0.lock().unwrap().clone())
+            }
+            fn save(&self, data: &[u8]) -> Result<(), Error> {
+                *self.0.lock().unwrap() = Some(data.to_vec());
+                Ok(())
+            }
+            fn clear(&self) -> Result<(), Error> {
+                *self.0.lock().unwrap() = None;
+                Ok(())
+            }
+        }
+        let callback = Arc::new(Callback::default());
+        round_trip(TokenStore::Custom(callback.clone())).await;
+        assert!(callback.0.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = Profiles::new(dir.path());
+        assert!(profiles.list().await.unwrap().is_empty());
+        assert!(profiles.store("../x").is_err());
+        assert!(profiles.store("default").is_err());
+
+        for name in ["team", "personal"] {
+            let TokenStore::File(path) = profiles.store(name).unwrap() else {
+                panic!("not a file store");
+            };
+            write_private(&path, b"[]".to_vec()).await.unwrap();
+        }
+        profiles.set_default_profile("team").await.unwrap();
+        assert_eq!(profiles.list().await.unwrap(), ["personal", "team"]);
+        assert_eq!(
+            profiles.default_profile().await.unwrap().as_deref(),
+            Some("team")
+        );
+
+        profiles.remove("team").await.unwrap();
+        assert_eq!(profiles.list().await.unwrap(), ["personal"]);
+        assert_eq!(profiles.default_profile().await.unwrap(), None);
+    }
+}
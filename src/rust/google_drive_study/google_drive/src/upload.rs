@@ -0,0 +1,798 @@
+//! ファイルのアップロード
+//!
+//! `DriveHub`の再開可能なアップロードは中断したセッションを再開できないため、
+//! アップロードはHTTPリクエストを直接組み立てて送ります。
+
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use google_drive3::{
+    api::File,
+    hyper::{
+        Method, Request, Response, StatusCode,
+        body::Bytes,
+        header::{CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE},
+    },
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{Error, GDrive, GDriveId, GMeta, META_FIELDS};
+
+/// これより大きいファイルは`GDrive::upload_file`で再開可能なアップロードを使う
+pub const RESUMABLE_THRESHOLD: u64 = 5 << 20;
+
+/// 再開可能なアップロードのチャンクサイズの単位
+const CHUNK_ALIGN: usize = 256 << 10;
+
+/// アップロード先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadTarget {
+    /// フォルダ`parent`に`name`という名前で新しいファイルを作る
+    New { parent: GDriveId, name: String },
+    /// 既存のファイルの内容を置き換える
+    Existing(GDriveId),
+}
+
+/// 再開可能なアップロードのオプション
+#[derive(Debug, Clone)]
+pub struct ResumableOptions {
+    /// 1 回のリクエストで送るサイズ。256KiBの倍数に切り上げる
+    pub chunk_size: usize,
+    /// アップロードのセッションを保存するファイル
+    ///
+    /// 指定すると、中断したアップロードを次の呼び出しで途中から再開します。
+    /// アップロードが完了すると削除されます。
+    pub session_file: Option<PathBuf>,
+}
+
+impl Default for ResumableOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 8 << 20,
+            session_file: None,
+        }
+    }
+}
+
+/// 再開可能なアップロードの状態
+enum Status {
+    /// 受信済みのバイト数
+    Incomplete(u64),
+    /// 完了した
+    Done(Box<GMeta>),
+    /// セッションの有効期限が切れた。レスポンスのステータスコード
+    Expired(u16),
+}
+
+impl GDrive {
+    /// メモリ上のデータをアップロードする
+    ///
+    /// 新しいファイルはメタデータと内容を1回で送るマルチパートアップロード、
+    /// 既存のファイルは内容のみを送るシンプルアップロードを使います。
+    /// 大きなデータは[`GDrive::upload_resumable`]を使ってください。
+    ///
+    /// # 引数
+    ///
+    /// * `target` - アップロード先
+    /// * `mime_type` - 内容のMIMEタイプ
+    /// * `data` - アップロードする内容
+    ///
+    /// # 戻り値
+    ///
+    /// アップロードしたファイルのメタデータ、または発生したエラー
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, GDriveId, UploadTarget, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///     let target = UploadTarget::New {
+    ///         parent: "your_folder_id".into(),
+    ///         name: "hello.txt".into(),
+    ///     };
+    ///
+    ///     let meta = drive.upload(&target, "text/plain", b"Hello".to_vec()).await?;
+    ///     println!("ID: {}", meta.id);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn upload(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> Result<GMeta, Error> {
+        let (request, body) = match target {
+            UploadTarget::New { .. } => {
+                let metadata = metadata(target);
+                let boundary = boundary(&[metadata.as_bytes(), &data]);
+                let mut body = format!(
+                    "--{boundary}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{metadata}\r\n\
+                     --{boundary}\r\nContent-Type: {mime_type}\r\n\r\n"
+                )
+                .into_bytes();
+                body.extend_from_slice(&data);
+                body.extend_from_slice(format!("\r\n--{boundary}--").as_bytes());
+                let request = self.upload_request(target, "multipart").header(
+                    CONTENT_TYPE,
+                    format!("multipart/related; boundary={boundary}"),
+                );
+                (request, body)
+            }
+            UploadTarget::Existing(_) => {
+                let request = self
+                    .upload_request(target, "media")
+                    .header(CONTENT_TYPE, mime_type);
+                (request, data)
+            }
+        };
+        let rsp = self.send(request, body.into()).await?;
+        parse_meta(&rsp)
+    }
+
+    /// ローカルのファイルをアップロードする
+    ///
+    /// [`RESUMABLE_THRESHOLD`]以下のファイルは[`GDrive::upload`]、
+    /// それより大きいファイルは[`GDrive::upload_resumable`]を使います。
+    /// 再開可能なアップロードのセッションは`file`と同じディレクトリの`.<ファイル名>.upload`に保存し、
+    /// 中断した場合は同じ引数で呼び直すと続きからアップロードします。
+    ///
+    /// # 引数
+    ///
+    /// * `target` - アップロード先
+    /// * `mime_type` - 内容のMIMEタイプ
+    /// * `file` - アップロードするファイルのパス
+    ///
+    /// # 戻り値
+    ///
+    /// アップロードしたファイルのメタデータ、または発生したエラー
+    pub async fn upload_file<P: AsRef<Path>>(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        file: P,
+    ) -> Result<GMeta, Error> {
+        let file = file.as_ref();
+        if tokio::fs::metadata(file).await?.len() <= RESUMABLE_THRESHOLD {
+            let data = tokio::fs::read(file).await?;
+            return self.upload(target, mime_type, data).await;
+        }
+        let opts = ResumableOptions {
+            session_file: Some(session_file_for(file)),
+            ..ResumableOptions::default()
+        };
+        self.upload_resumable(target, mime_type, file, &opts).await
+    }
+
+    /// ローカルのファイルを再開可能なアップロードでチャンクごとにアップロードする
+    ///
+    /// 一時的なエラーで失敗したチャンクは、リトライの方針に従って
+    /// サーバーが受信済みの位置を問い合わせてから送り直します。
+    /// `opts.session_file`を指定すると、ネットワークエラーなどで中断した場合に
+    /// 同じ引数で呼び直すことで、サーバーが受信済みの位置から再開します。
+    /// セッションの有効期限（1週間）が切れている場合や、ファイルのパス、サイズ、更新日時が
+    /// セッションを開始した時と違う場合は最初からやり直します。
+    ///
+    /// # 引数
+    ///
+    /// * `target` - アップロード先
+    /// * `mime_type` - 内容のMIMEタイプ
+    /// * `file` - アップロードするファイルのパス
+    /// * `opts` - チャンクサイズとセッションの保存先
+    ///
+    /// # 戻り値
+    ///
+    /// アップロードしたファイルのメタデータ、または発生したエラー
+    ///
+    /// # 例
+    ///
+    /// ```skip
+    /// use google_drive::{GDrive, ResumableOptions, UploadTarget, scope};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let drive = GDrive::oauth("client_secret.json", "./token.json", &[scope::FULL], None).await?;
+    ///     let target = UploadTarget::Existing("your_file_id".into());
+    ///     let opts = ResumableOptions {
+    ///         session_file: Some("./build.zip.upload".into()),
+    ///         ..ResumableOptions::default()
+    ///     };
+    ///
+    ///     // 失敗しても、もう一度呼べば続きからアップロードする
+    ///     drive.upload_resumable(&target, "application/zip", "./build.zip", &opts).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn upload_resumable<P: AsRef<Path>>(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        file: P,
+        opts: &ResumableOptions,
+    ) -> Result<GMeta, Error> {
+        let source = Source::new(file.as_ref()).await?;
+        let mut file = tokio::fs::File::open(file).await?;
+        let size = source.size;
+        let chunk_size = opts.chunk_size.max(1).div_ceil(CHUNK_ALIGN) * CHUNK_ALIGN;
+
+        // 保存したセッションがあれば、受信済みの位置を問い合わせる
+        let mut session = None;
+        if let Some(url) = load_session(opts.session_file.as_deref(), &source).await {
+            match self.upload_status(&url, size).await? {
+                Status::Done(meta) => {
+                    remove_session(opts.session_file.as_deref()).await?;
+                    return Ok(*meta);
+                }
+                Status::Incomplete(offset) => session = Some((url, offset)),
+                Status::Expired(_) => {}
+            }
+        }
+        let (url, mut offset) = match session {
+            Some(session) => session,
+            None => {
+                let url = self.start_resumable(target, mime_type, size).await?;
+                if let Some(path) = &opts.session_file {
+                    tokio::fs::write(path, source.session(&url)).await?;
+                }
+                (url, 0)
+            }
+        };
+
+        let mut buf = vec![0; chunk_size];
+        loop {
+            let len = (size - offset).min(chunk_size as u64) as usize;
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut buf[..len]).await?;
+            let chunk = Bytes::copy_from_slice(&buf[..len]);
+            let failed = Cell::new(false);
+            let status = self
+                .retry(|| async {
+                    // 前の試行が失敗した場合は、サーバーが受信済みの位置を問い合わせ直す
+                    if failed.replace(true) {
+                        match self.upload_status(&url, size).await? {
+                            Status::Incomplete(n) if n == offset => {}
+                            status => return Ok(status),
+                        }
+                    }
+                    let range = if len == 0 {
+                        format!("bytes */{}", size)
+                    } else {
+                        format!("bytes {}-{}/{}", offset, offset + len as u64 - 1, size)
+                    };
+                    let request = Request::builder()
+                        .method(Method::PUT)
+                        .uri(&url)
+                        .header(CONTENT_RANGE, range);
+                    let rsp = self.send(request, chunk.clone()).await?;
+                    upload_status(&rsp)
+                })
+                .await?;
+            match status {
+                Status::Incomplete(n) => offset = n,
+                Status::Done(meta) => {
+                    remove_session(opts.session_file.as_deref()).await?;
+                    return Ok(*meta);
+                }
+                Status::Expired(status) => return Err(Error::InvalidResponse(status)),
+            }
+        }
+    }
+
+    /// アップロード用のリクエストを作る
+    fn upload_request(
+        &self,
+        target: &UploadTarget,
+        upload_type: &str,
+    ) -> google_drive3::hyper::http::request::Builder {
+        let (method, path) = match target {
+            UploadTarget::New { .. } => (Method::POST, String::new()),
+            UploadTarget::Existing(id) => (Method::PATCH, format!("/{}", id)),
+        };
+        Request::builder().method(method).uri(format!(
+            "{}upload/drive/v3/files{}?uploadType={}&supportsAllDrives=true&fields={}",
+            self.root_url, path, upload_type, META_FIELDS
+        ))
+    }
+
+    /// 再開可能なアップロードのセッションを開始し、セッションのURLを返す
+    async fn start_resumable(
+        &self,
+        target: &UploadTarget,
+        mime_type: &str,
+        size: u64,
+    ) -> Result<String, Error> {
+        let request = self
+            .upload_request(target, "resumable")
+            .header(CONTENT_TYPE, "application/json; charset=UTF-8")
+            .header("X-Upload-Content-Type", mime_type)
+            .header("X-Upload-Content-Length", size);
+        let rsp = self.send(request, metadata(target).into()).await?;
+        if !rsp.status().is_success() {
+            return Err(Error::InvalidResponse(rsp.status().as_u16()));
+        }
+        rsp.headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+            .ok_or(Error::MetaIsNull("Location"))
+    }
+
+    /// セッションの受信済みのバイト数を問い合わせる
+    async fn upload_status(&self, url: &str, size: u64) -> Result<Status, Error> {
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(url)
+            .header(CONTENT_RANGE, format!("bytes */{}", size));
+        let rsp = self.send(request, Bytes::new()).await?;
+        upload_status(&rsp)
+    }
+}
+
+/// アップロード先に応じたメタデータのJSON
+fn metadata(target: &UploadTarget) -> String {
+    match target {
+        UploadTarget::New { parent, name } => serde_json::json!({
+            "name": name,
+            "parents": [parent.as_ref()],
+        }),
+        UploadTarget::Existing(_) => serde_json::json!({}),
+    }
+    .to_string()
+}
+
+fn parse_meta(rsp: &Response<Bytes>) -> Result<GMeta, Error> {
+    if !rsp.status().is_success() {
+        return Err(Error::InvalidResponse(rsp.status().as_u16()));
+    }
+    let file: File = serde_json::from_slice(rsp.body())?;
+    GMeta::new(file)
+}
+
+/// 再開可能なアップロードのレスポンスを解釈する
+///
+/// 308 の`Range: bytes=0-N`は N バイト目まで受信済み、`Range`が無ければ何も受信していない。
+fn upload_status(rsp: &Response<Bytes>) -> Result<Status, Error> {
+    match rsp.status() {
+        StatusCode::PERMANENT_REDIRECT => {
+            let received = rsp
+                .headers()
+                .get(RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit_once('-'))
+                .and_then(|(_, last)| last.parse::<u64>().ok())
+                .map_or(0, |last| last + 1);
+            Ok(Status::Incomplete(received))
+        }
+        status @ (StatusCode::NOT_FOUND | StatusCode::GONE) => Ok(Status::Expired(status.as_u16())),
+        _ => parse_meta(rsp).map(|meta| Status::Done(Box::new(meta))),
+    }
+}
+
+/// `parts`のどれにも含まれないマルチパートの境界文字列
+fn boundary(parts: &[&[u8]]) -> String {
+    loop {
+        let boundary = format!("google_drive_upload_{:016x}", fastrand::u64(..));
+        let found = parts.iter().any(|part| {
+            part.windows(boundary.len())
+                .any(|w| w == boundary.as_bytes())
+        });
+        if !found {
+            return boundary;
+        }
+    }
+}
+
+/// アップロードするファイルの状態。セッションを保存した時と違えば、そのセッションは使わない
+#[derive(Debug, PartialEq, Eq)]
+struct Source {
+    size: u64,
+    /// 更新日時（UNIXエポックからのナノ秒）
+    modified: u128,
+    path: PathBuf,
+}
+
+impl Source {
+    async fn new(path: &Path) -> Result<Self, Error> {
+        let meta = tokio::fs::metadata(path).await?;
+        let modified = meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let path = tokio::fs::canonicalize(path)
+            .await
+            .unwrap_or_else(|_| path.into());
+        Ok(Self {
+            size: meta.len(),
+            modified,
+            path,
+        })
+    }
+
+    /// セッションファイルの内容。`サイズ`、`更新日時`、`URL`、`パス`の各行
+    fn session(&self, url: &str) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n",
+            self.size,
+            self.modified,
+            url,
+            self.path.display()
+        )
+    }
+}
+
+/// `GDrive::upload_file`がセッションを保存するファイル。`file`と同じディレクトリに置く
+fn session_file_for(file: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(file.file_name().unwrap_or_default());
+    name.push(".upload");
+    file.with_file_name(name)
+}
+
+/// 保存したセッションのURLを読み込む。ファイルが`source`と違えば使わない
+async fn load_session(path: Option<&Path>, source: &Source) -> Option<String> {
+    let text = tokio::fs::read_to_string(path?).await.ok()?;
+    let mut lines = text.splitn(4, '\n');
+    let size = lines.next()?.parse().ok()?;
+    let modified = lines.next()?.parse().ok()?;
+    let url = lines.next()?;
+    let saved = Source {
+        size,
+        modified,
+        path: lines.next()?.strip_suffix('\n')?.into(),
+    };
+    (saved == *source).then(|| url.to_string())
+}
+
+async fn remove_session(path: Option<&Path>) -> Result<(), Error> {
+    if let Some(path) = path {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        RetryPolicy,
+        test_server::{self, TestServer},
+    };
+
+    const FILE_JSON: &str = r#"{
+        "id": "new-id",
+        "name": "hello.txt",
+        "mimeType": "text/plain",
+        "modifiedTime": "2025-01-01T00:00:00Z",
+        "md5Checksum": "8b1a9953c4611296a827abf8c47804d7",
+        "capabilities": {"canDownload": true}
+    }"#;
+
+    #[tokio::test]
+    async fn upload_new_file_as_multipart() {
+        let server = TestServer::start(|_| test_server::Response::json(200, FILE_JSON)).await;
+        let target = UploadTarget::New {
+            parent: "folder-id".into(),
+            name: "hello.txt".into(),
+        };
+        let meta = server
+            .drive()
+            .upload(&target, "text/plain", b"Hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(meta.id, "new-id".into());
+        assert_eq!(
+            meta.md5_checksum.as_deref(),
+            Some("8b1a9953c4611296a827abf8c47804d7")
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let r = &requests[0];
+        assert_eq!(r.method, "POST");
+        assert_eq!(r.path, "/upload/drive/v3/files");
+        assert_eq!(r.query("uploadType"), Some("multipart"));
+        assert_eq!(r.header("Authorization"), Some("Bearer token"));
+        assert!(
+            r.header("Content-Type")
+                .unwrap()
+                .starts_with("multipart/related")
+        );
+        let body = String::from_utf8_lossy(&r.body);
+        assert!(body.contains(r#""name":"hello.txt""#));
+        assert!(body.contains(r#""parents":["folder-id"]"#));
+        assert!(body.contains("Content-Type: text/plain\r\n\r\nHello\r\n"));
+    }
+
+    #[tokio::test]
+    async fn update_existing_file_as_simple_upload() {
+        let server = TestServer::start(|_| test_server::Response::json(200, FILE_JSON)).await;
+        let target = UploadTarget::Existing("file-id".into());
+        server
+            .drive()
+            .upload(&target, "text/plain", b"Hello".to_vec())
+            .await
+            .unwrap();
+
+        let r = &server.requests()[0];
+        assert_eq!(r.method, "PATCH");
+        assert_eq!(r.path, "/upload/drive/v3/files/file-id");
+        assert_eq!(r.query("uploadType"), Some("media"));
+        assert_eq!(r.header("Content-Type"), Some("text/plain"));
+        assert_eq!(r.body, b"Hello");
+    }
+
+    #[tokio::test]
+    async fn upload_error_status() {
+        let server = TestServer::start(|_| test_server::Response::json(403, "{}")).await;
+        let target = UploadTarget::Existing("file-id".into());
+        let result = server.drive().upload(&target, "text/plain", vec![]).await;
+        assert!(matches!(result, Err(Error::InvalidResponse(403))));
+    }
+
+    /// 再開可能なアップロードのセッションを真似るハンドラ
+    ///
+    /// `fail_at`番目のチャンクを一度だけ 503 で失敗させる。
+    fn resumable_handler(
+        url: Arc<Mutex<String>>,
+        received: Arc<Mutex<Vec<u8>>>,
+        fail_at: usize,
+    ) -> impl Fn(&test_server::Request) -> test_server::Response + Send + Sync + 'static {
+        let chunks = Mutex::new(0);
+        move |r| {
+            if r.path.starts_with("/upload/drive/v3/files") {
+                assert_eq!(r.query("uploadType"), Some("resumable"));
+                let location = format!("{}session/1", url.lock().unwrap());
+                return test_server::Response::json(200, "{}").header("Location", &location);
+            }
+            assert_eq!(r.path, "/session/1");
+            let range = r.header("Content-Range").unwrap();
+            let (range, total) = range.trim_start_matches("bytes ").split_once('/').unwrap();
+            let total: usize = total.parse().unwrap();
+            let mut received = received.lock().unwrap();
+            if range != "*" {
+                let mut chunks = chunks.lock().unwrap();
+                *chunks += 1;
+                if *chunks == fail_at {
+                    return test_server::Response::new(503);
+                }
+                let start: usize = range.split_once('-').unwrap().0.parse().unwrap();
+                assert_eq!(start, received.len());
+                received.extend_from_slice(&r.body);
+            }
+            if received.len() == total {
+                return test_server::Response::json(200, FILE_JSON);
+            }
+            let rsp = test_server::Response::new(308);
+            match received.len() {
+                0 => rsp,
+                n => rsp.header("Range", &format!("bytes=0-{}", n - 1)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn resumable_upload_resumes_after_interruption() {
+        let url = Arc::new(Mutex::new(String::new()));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server = TestServer::start(resumable_handler(url.clone(), received.clone(), 2)).await;
+        *url.lock().unwrap() = server.url.clone();
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("data.bin");
+        let data: Vec<u8> = (0..CHUNK_ALIGN * 2 + 100).map(|i| i as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+        let opts = ResumableOptions {
+            chunk_size: CHUNK_ALIGN,
+            session_file: Some(dir.path().join("data.bin.upload")),
+        };
+        let target = UploadTarget::New {
+            parent: "folder-id".into(),
+            name: "data.bin".into(),
+        };
+        let mut drive = server.drive();
+        drive.set_retry_policy(RetryPolicy::none());
+
+        // 2 つ目のチャンクで失敗する
+        let result = drive
+            .upload_resumable(&target, "application/octet-stream", &src, &opts)
+            .await;
+        assert!(matches!(result, Err(Error::InvalidResponse(503))));
+        assert!(opts.session_file.as_ref().unwrap().exists());
+        assert_eq!(received.lock().unwrap().len(), CHUNK_ALIGN);
+
+        // 続きから再開する
+        let meta = drive
+            .upload_resumable(&target, "application/octet-stream", &src, &opts)
+            .await
+            .unwrap();
+        assert_eq!(meta.id, "new-id".into());
+        assert_eq!(*received.lock().unwrap(), data);
+        assert!(!opts.session_file.as_ref().unwrap().exists());
+
+        // セッションの開始は 1 回だけ
+        let starts = server
+            .requests()
+            .iter()
+            .filter(|r| r.path.starts_with("/upload/drive/v3/files"))
+            .count();
+        assert_eq!(starts, 1);
+    }
+
+    #[tokio::test]
+    async fn resumable_upload_retries_failed_chunk() {
+        let url = Arc::new(Mutex::new(String::new()));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server = TestServer::start(resumable_handler(url.clone(), received.clone(), 2)).await;
+        *url.lock().unwrap() = server.url.clone();
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("data.bin");
+        let data: Vec<u8> = (0..CHUNK_ALIGN * 2 + 100).map(|i| i as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+        let opts = ResumableOptions {
+            chunk_size: CHUNK_ALIGN,
+            session_file: None,
+        };
+        let target = UploadTarget::Existing("file-id".into());
+        let mut drive = server.drive();
+        drive.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..RetryPolicy::default()
+        });
+
+        // 2 つ目のチャンクが 503 になっても、1 回の呼び出しで完了する
+        let meta = drive
+            .upload_resumable(&target, "application/octet-stream", &src, &opts)
+            .await
+            .unwrap();
+        assert_eq!(meta.id, "new-id".into());
+        assert_eq!(*received.lock().unwrap(), data);
+
+        // 失敗した後に受信済みの位置を問い合わせている
+        let ranges: Vec<_> = server
+            .requests()
+            .iter()
+            .filter_map(|r| r.header("Content-Range").map(String::from))
+            .collect();
+        let total = data.len();
+        assert_eq!(
+            ranges,
+            [
+                format!("bytes 0-{}/{}", CHUNK_ALIGN - 1, total),
+                format!("bytes {}-{}/{}", CHUNK_ALIGN, CHUNK_ALIGN * 2 - 1, total),
+                format!("bytes */{}", total),
+                format!("bytes {}-{}/{}", CHUNK_ALIGN, CHUNK_ALIGN * 2 - 1, total),
+                format!("bytes {}-{}/{}", CHUNK_ALIGN * 2, total - 1, total),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_file_saves_session_next_to_source() {
+        let url = Arc::new(Mutex::new(String::new()));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server = TestServer::start(resumable_handler(url.clone(), received.clone(), 1)).await;
+        *url.lock().unwrap() = server.url.clone();
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("data.bin");
+        let data: Vec<u8> = (0..RESUMABLE_THRESHOLD + 1).map(|i| i as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+        let session = dir.path().join(".data.bin.upload");
+        assert_eq!(session_file_for(&src), session);
+        let target = UploadTarget::Existing("file-id".into());
+        let mut drive = server.drive();
+        drive.set_retry_policy(RetryPolicy::none());
+
+        let result = drive
+            .upload_file(&target, "application/octet-stream", &src)
+            .await;
+        assert!(matches!(result, Err(Error::InvalidResponse(503))));
+        assert!(session.exists());
+
+        drive
+            .upload_file(&target, "application/octet-stream", &src)
+            .await
+            .unwrap();
+        assert_eq!(*received.lock().unwrap(), data);
+        assert!(!session.exists());
+        let starts = server
+            .requests()
+            .iter()
+            .filter(|r| r.path.starts_with("/upload/drive/v3/files"))
+            .count();
+        assert_eq!(starts, 1);
+    }
+
+    #[tokio::test]
+    async fn multipart_boundary_does_not_appear_in_payload() {
+        let first = boundary(&[b"{}", b"data"]);
+        assert!(first.starts_with("google_drive_upload_"));
+        assert_ne!(first, boundary(&[b"{}", b"data"]));
+
+        let server = TestServer::start(|_| test_server::Response::json(200, FILE_JSON)).await;
+        let target = UploadTarget::New {
+            parent: "folder-id".into(),
+            name: "hello.txt".into(),
+        };
+        let data = b"--google_drive_upload_boundary\r\n".to_vec();
+        server
+            .drive()
+            .upload(&target, "text/plain", data.clone())
+            .await
+            .unwrap();
+        let r = &server.requests()[0];
+        let content_type = r.header("Content-Type").unwrap();
+        let boundary = content_type.split_once("boundary=").unwrap().1;
+        let body = String::from_utf8_lossy(&r.body);
+        assert_eq!(body.matches(boundary).count(), 3);
+        assert!(body.contains(std::str::from_utf8(&data).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn session_is_not_resumed_for_a_different_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        std::fs::write(&a, b"0123").unwrap();
+        std::fs::write(&b, b"0123").unwrap();
+        let session = dir.path().join("upload");
+        let source = Source::new(&a).await.unwrap();
+        std::fs::write(&session, source.session("http://example.com/s")).unwrap();
+
+        let load = |path: PathBuf| {
+            let session = session.clone();
+            async move {
+                let source = Source::new(&path).await.unwrap();
+                load_session(Some(&session), &source).await
+            }
+        };
+        assert_eq!(
+            load(a.clone()).await.as_deref(),
+            Some("http://example.com/s")
+        );
+        // 同じサイズの別のファイル
+        assert_eq!(load(b).await, None);
+        // 同じサイズのまま書き換えた
+        std::fs::File::options()
+            .write(true)
+            .open(&a)
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(load(a).await, None);
+    }
+
+    #[tokio::test]
+    async fn resumable_upload_empty_file() {
+        let url = Arc::new(Mutex::new(String::new()));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server = TestServer::start(resumable_handler(url.clone(), received, 0)).await;
+        *url.lock().unwrap() = server.url.clone();
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("empty");
+        std::fs::write(&src, b"").unwrap();
+        let target = UploadTarget::Existing("file-id".into());
+        let result = server
+            .drive()
+            .upload_resumable(&target, "text/plain", &src, &ResumableOptions::default())
+            .await;
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//! 設定ファイルの読み込み
+//!
+//! 設定はコマンドライン引数、環境変数、設定ファイル、既定値の順に優先します。
+//! 設定ファイルは`$GDRIVE_CONFIG`、無ければ`<設定ディレクトリ>/gdrive/config.json`です。
+//!
+//! ```json
+//! {
+//!   "client_secret": "/path/to/client_secret.json",
+//!   "token_file": "/path/to/token.json",
+//!   "scope": "full"
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use google_drive::scope;
+
+/// 設定ファイルの内容。書かれていない項目は`None`
+#[derive(Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub client_secret: Option<PathBuf>,
+    pub token_file: Option<PathBuf>,
+    pub scope: Option<String>,
+}
+
+/// 最終的な設定
+#[derive(Debug, PartialEq, Eq)]
+pub struct Config {
+    /// OAuthクライアントのシークレット
+    pub client_secret: PathBuf,
+    /// トークンの保存先
+    pub token_file: PathBuf,
+    /// 要求するスコープ
+    pub scope: &'static str,
+}
+
+impl Config {
+    /// 設定ファイルを読み込み、コマンドライン引数（と環境変数）で上書きする
+    ///
+    /// `path`が`None`なら既定の場所の設定ファイルを読み、無ければ既定値を使う。
+    pub fn load(path: Option<&Path>, overrides: ConfigFile) -> Result<Self> {
+        let dir = config_dir();
+        let file = match path {
+            Some(path) => ConfigFile::read(path)?,
+            None => match dir.as_ref().map(|d| d.join("config.json")) {
+                Some(path) if path.exists() => ConfigFile::read(&path)?,
+                _ => ConfigFile::default(),
+            },
+        };
+        let dir = dir.unwrap_or_else(|| ".".into());
+        Self::merge(overrides, file, &dir)
+    }
+
+    /// `overrides`、`file`、既定値の順に採用する。既定のパスは`dir`の下
+    fn merge(overrides: ConfigFile, file: ConfigFile, dir: &Path) -> Result<Self> {
+        let scope = overrides.scope.or(file.scope);
+        Ok(Self {
+            client_secret: overrides
+                .client_secret
+                .or(file.client_secret)
+                .unwrap_or_else(|| dir.join("client_secret.json")),
+            token_file: overrides
+                .token_file
+                .or(file.token_file)
+                .unwrap_or_else(|| dir.join("token.json")),
+            scope: parse_scope(scope.as_deref().unwrap_or("full"))?,
+        })
+    }
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("cannot read config file {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+/// `full`、`readonly`、`file`、またはスコープのURL
+fn parse_scope(s: &str) -> Result<&'static str> {
+    Ok(match s {
+        "full" | scope::FULL => scope::FULL,
+        "readonly" | scope::READONLY => scope::READONLY,
+        "file" | scope::FILE => scope::FILE,
+        _ => bail!("unknown scope: {} (full, readonly, file)", s),
+    })
+}
+
+/// `$XDG_CONFIG_HOME/gdrive`、`~/.config/gdrive`、Windowsでは`%APPDATA%\gdrive`
+fn config_dir() -> Option<PathBuf> {
+    let env = |key| {
+        std::env::var_os(key)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let base = if cfg!(windows) {
+        env("APPDATA")
+    } else {
+        env("XDG_CONFIG_HOME").or_else(|| env("HOME").map(|h| h.join(".config")))
+    };
+    base.map(|b| b.join("gdrive"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_take_precedence_over_file() {
+        let file: ConfigFile = serde_json::from_str(
+            r#"{"client_secret": "/etc/secret.json", "token_file": "/etc/token.json", "scope": "readonly"}"#,
+        )
+        .unwrap();
+        let overrides = ConfigFile {
+            token_file: Some("/tmp/token.json".into()),
+            ..Default::default()
+        };
+        let config = Config::merge(overrides, file, Path::new("/home/me/.config/gdrive")).unwrap();
+        assert_eq!(config.client_secret, Path::new("/etc/secret.json"));
+        assert_eq!(config.token_file, Path::new("/tmp/token.json"));
+        assert_eq!(config.scope, scope::READONLY);
+    }
+
+    #[test]
+    fn defaults_are_in_config_dir() {
+        let dir = Path::new("/home/me/.config/gdrive");
+        let config = Config::merge(ConfigFile::default(), ConfigFile::default(), dir).unwrap();
+        assert_eq!(config.client_secret, dir.join("client_secret.json"));
+        assert_eq!(config.token_file, dir.join("token.json"));
+        assert_eq!(config.scope, scope::FULL);
+
+        let overrides = ConfigFile {
+            scope: Some("admin".into()),
+            ..Default::default()
+        };
+        assert!(Config::merge(overrides, ConfigFile::default(), dir).is_err());
+        assert!(serde_json::from_str::<ConfigFile>(r#"{"secret": "x"}"#).is_err());
+    }
+}
@@ -1,11 +1,37 @@
-use std::path::PathBuf;
+//! `google_drive`クレートを使ったGoogle Driveのコマンドラインツール
+//!
+//! 対象のファイルやフォルダは次のいずれかで指定します。
+//!
+//! - パス: `My Drive/dir/file`、`/dir/file`（マイドライブ）、`共有ドライブ名:/dir/file`
+//! - 共有URL: `https://drive.google.com/file/d/<id>/view`など
+//! - ID
 
-use anyhow::Result;
-use google_drive::{GDrive, GDriveId, InstalledFlowDelegate};
+mod config;
+mod output;
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use google_drive::{
+    Corpus, DownloadOptions, DuplicatePolicy, Error, ExportFormat, GDrive, GDriveId, GMeta,
+    MetaFields, MirrorOptions, Query, Removed, SearchOptions, UploadTarget,
+};
+
+use config::{Config, ConfigFile};
+use output::Node;
 
 /// ログインの為にブラウザを開く
+#[cfg(feature = "browser")]
 struct OpenInstalledFlowDelegate;
-impl InstalledFlowDelegate for OpenInstalledFlowDelegate {
+
+#[cfg(feature = "browser")]
+impl google_drive::InstalledFlowDelegate for OpenInstalledFlowDelegate {
     fn redirect_uri(&self) -> Option<&str> {
         None
     }
@@ -14,8 +40,7 @@ impl InstalledFlowDelegate for OpenInstalledFlowDelegate {
         &'a self,
         url: &'a str,
         _need_code: bool,
-    ) -> std::pin::Pin<Box<dyn Future<Output = std::result::Result<String, String>> + Send + 'a>>
-    {
+    ) -> Pin<Box<dyn Future<Output = std::result::Result<String, String>> + Send + 'a>> {
         println!("URL: {}", url);
         Box::pin(async move {
             open::that(url).map_err(|e| format!("{:?}", e))?;
@@ -24,25 +49,584 @@ impl InstalledFlowDelegate for OpenInstalledFlowDelegate {
     }
 }
 
+#[derive(Parser)]
+#[command(name = "gdrive", version, about = "Google Drive command-line tool")]
+struct Cli {
+    /// 設定ファイル
+    #[arg(long, global = true, env = "GDRIVE_CONFIG")]
+    config: Option<PathBuf>,
+    /// OAuthクライアントのシークレット（JSON）
+    #[arg(long, global = true, env = "GDRIVE_CLIENT_SECRET")]
+    client_secret: Option<PathBuf>,
+    /// トークンの保存先
+    #[arg(long, global = true, env = "GDRIVE_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+    /// スコープ（full、readonly、file）
+    #[arg(long, global = true, env = "GDRIVE_SCOPE")]
+    scope: Option<String>,
+    /// ログイン時にブラウザを開かず、URLだけを表示する
+    #[arg(long, global = true)]
+    no_browser: bool,
+    /// 結果をJSONで出力する
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// ログインしてトークンを保存する
+    Login,
+    /// フォルダの中身を表示する
+    Ls {
+        /// フォルダ（省略するとマイドライブ）
+        target: Option<String>,
+        /// 更新日時、サイズ、IDも表示する
+        #[arg(short, long)]
+        long: bool,
+        /// サブフォルダもたどってツリー表示する
+        #[arg(short, long)]
+        tree: bool,
+    },
+    /// メタデータを表示する
+    Stat { target: String },
+    /// ファイルをダウンロードする
+    Get {
+        target: String,
+        /// 保存先。ディレクトリなら中にファイル名で保存する（省略するとカレントディレクトリ）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Google Apps形式のエクスポート形式（docx、pdf、md、xlsx、csvなど）
+        #[arg(short, long)]
+        format: Option<ExportFormat>,
+    },
+    /// ファイルをアップロードする
+    Put {
+        file: PathBuf,
+        /// アップロード先のフォルダ（省略するとマイドライブ）
+        dest: Option<String>,
+        /// Drive上の名前（省略するとローカルのファイル名）
+        #[arg(long, conflicts_with = "replace")]
+        name: Option<String>,
+        /// `dest`を既存のファイルとして、その内容を置き換える
+        #[arg(long, requires = "dest")]
+        replace: bool,
+        /// MIMEタイプ（省略すると拡張子から推測する）
+        #[arg(long)]
+        mime: Option<String>,
+    },
+    /// フォルダを作成する。途中のフォルダも作成する
+    Mkdir { path: String },
+    /// 移動、または名前を変更する
+    ///
+    /// `dest`が既存のフォルダならその中に移動し、無ければ`dest`のパスに移動して名前を変える。
+    /// `/`を含まない名前なら同じフォルダのまま名前だけを変える。
+    Mv { source: String, dest: String },
+    /// ゴミ箱に移動する
+    Rm {
+        #[arg(required = true)]
+        targets: Vec<String>,
+        /// ゴミ箱に入れず完全に削除する
+        #[arg(long)]
+        permanent: bool,
+    },
+    /// 検索する
+    Search {
+        /// 名前、説明、内容に含む文字列
+        text: Option<String>,
+        /// 名前に含む文字列
+        #[arg(long)]
+        name: Option<String>,
+        /// MIMEタイプ
+        #[arg(long)]
+        mime: Option<String>,
+        /// このフォルダの直下だけを検索する
+        #[arg(long = "in")]
+        parent: Option<String>,
+        /// この日時（RFC3339またはYYYY-MM-DD）より後に更新された
+        #[arg(long, value_parser = parse_time)]
+        modified_after: Option<DateTime<Utc>>,
+        /// 所有者のメールアドレス
+        #[arg(long)]
+        owner: Option<String>,
+        /// 共有ドライブのIDで範囲を絞る
+        #[arg(long, conflicts_with = "all_drives")]
+        drive: Option<String>,
+        /// 全ての共有ドライブも検索する
+        #[arg(long)]
+        all_drives: bool,
+        /// 表示する最大件数
+        #[arg(long)]
+        limit: Option<usize>,
+        /// 更新日時、サイズ、IDも表示する
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// フォルダをローカルディレクトリにミラーリングする
+    Mirror {
+        target: String,
+        dir: PathBuf,
+        /// 同時にダウンロードするファイルの数
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// リモートで削除されたファイルの扱い
+        #[arg(long, value_enum, default_value_t = RemovedArg::Quarantine)]
+        removed: RemovedArg,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RemovedArg {
+    /// `.gdrive-quarantine`に移動する
+    Quarantine,
+    Delete,
+    Keep,
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.into());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("invalid time: {}", s))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let tmp:PathBuf = "./tmp".into();
-    tokio::fs::create_dir_all(&tmp).await?;
-    let drive = GDrive::oauth(
-        "client_secret.json",
-        "./tmp/token.json",
-        Some(Box::new(OpenInstalledFlowDelegate {})),
+    let cli = Cli::parse();
+    let overrides = ConfigFile {
+        client_secret: cli.client_secret.clone(),
+        token_file: cli.token_file.clone(),
+        scope: cli.scope.clone(),
+    };
+    let config = Config::load(cli.config.as_deref(), overrides)?;
+    let drive = connect(&config, cli.no_browser).await?;
+    run(&drive, cli.command, cli.json).await
+}
+
+async fn connect(config: &Config, no_browser: bool) -> Result<GDrive> {
+    if let Some(dir) = config.token_file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    #[cfg(feature = "browser")]
+    let delegate: Option<Box<dyn google_drive::InstalledFlowDelegate>> = match no_browser {
+        true => None,
+        false => Some(Box::new(OpenInstalledFlowDelegate)),
+    };
+    #[cfg(not(feature = "browser"))]
+    let delegate = {
+        let _ = no_browser;
+        None
+    };
+    GDrive::oauth(
+        &config.client_secret,
+        config.token_file.clone(),
+        &[config.scope],
+        delegate,
     )
-    .await?;
-    let folder_id: GDriveId = "1wS6tVoVmkdMZ96DgcKew-pSYBpJa9pXA".into();
+    .await
+    .with_context(|| {
+        format!(
+            "cannot authenticate with {}",
+            config.client_secret.display()
+        )
+    })
+}
 
-    for meta in drive.list(&folder_id).await? {
-        println!("meta: {:?}", meta);
-        if !meta.is_google_app() && meta.can_download{ // google app は export をしないといけない
-            let path = tmp.join(&meta.name);
-            drive.download_and_save(&meta.id, path).await?;
+async fn run(drive: &GDrive, command: Command, json: bool) -> Result<()> {
+    match command {
+        Command::Login => {
+            let root = drive.get_meta(&"root".into()).await?;
+            if json {
+                println!("{}", output::meta_json(&root));
+            } else {
+                println!("logged in ({} id: {})", root.name, root.id);
+            }
+        }
+        Command::Ls { target, long, tree } => {
+            let folder = resolve(drive, target.as_deref().unwrap_or("My Drive")).await?;
+            if !folder.is_directory() {
+                print_items(&[folder], long, json);
+            } else if tree {
+                let node = walk(drive, folder).await?;
+                if json {
+                    println!("{:#}", output::node_json(&node));
+                } else {
+                    output::print_tree(&node, long);
+                }
+            } else {
+                print_items(&drive.list(&folder.id).await?, long, json);
+            }
+        }
+        Command::Stat { target } => {
+            let meta = resolve(drive, &target).await?;
+            let meta = drive
+                .get_meta_with_fields(&meta.id, &MetaFields::Full)
+                .await?;
+            if json {
+                println!("{:#}", output::meta_json(&meta));
+            } else {
+                output::print_stat(&meta);
+            }
+        }
+        Command::Get {
+            target,
+            output,
+            format,
+        } => {
+            let meta = resolve(drive, &target).await?;
+            if meta.is_directory() {
+                bail!("{} is a folder; use `gdrive mirror` instead", target);
+            }
+            let format = match format {
+                Some(f) if !meta.export_formats().contains(&f) => {
+                    bail!("{} cannot be exported as {}", meta.name, f)
+                }
+                Some(f) => Some(f),
+                None => meta.default_export().filter(|_| meta.is_google_app_file()),
+            };
+            let name = match format {
+                Some(f) => format!("{}.{}", meta.name, f.extension()),
+                None => meta.name.clone(),
+            };
+            let name = name.replace(['/', '\\'], "_");
+            let path = match output {
+                Some(p) if p.is_dir() => p.join(name),
+                Some(p) => p,
+                None => name.into(),
+            };
+            match format {
+                Some(f) => drive.export_and_save(&meta.id, f, &path).await?,
+                None => {
+                    drive
+                        .download_to_file(&meta.id, &path, &DownloadOptions::default())
+                        .await?;
+                }
+            }
+            if json {
+                let mut v = output::meta_json(&meta);
+                v["localPath"] = path.display().to_string().into();
+                println!("{}", v);
+            } else {
+                println!("{}", path.display());
+            }
+        }
+        Command::Put {
+            file,
+            dest,
+            name,
+            replace,
+            mime,
+        } => {
+            if !file.is_file() {
+                bail!("{} is not a file", file.display());
+            }
+            let dest = resolve(drive, dest.as_deref().unwrap_or("My Drive")).await?;
+            let target = if replace {
+                if dest.is_directory() {
+                    bail!("{} is a folder", dest.name);
+                }
+                UploadTarget::Existing(dest.id)
+            } else {
+                if !dest.is_directory() {
+                    bail!("{} is not a folder", dest.name);
+                }
+                let name = match name {
+                    Some(name) => name,
+                    None => file_name(&file)?,
+                };
+                UploadTarget::New {
+                    parent: dest.id,
+                    name,
+                }
+            };
+            let mime = mime.unwrap_or_else(|| guess_mime(&file).into());
+            let meta = drive.upload_file(&target, &mime, &file).await?;
+            print_meta(&meta, json);
+        }
+        Command::Mkdir { path } => {
+            let meta = drive.create_folders(&path).await?;
+            print_meta(&meta, json);
+        }
+        Command::Mv { source, dest } => {
+            let source = resolve(drive, &source).await?;
+            let meta = move_item(drive, &source, &dest).await?;
+            print_meta(&meta, json);
+        }
+        Command::Rm { targets, permanent } => {
+            for target in targets {
+                let meta = resolve(drive, &target).await?;
+                let meta = match permanent {
+                    true => drive.delete(&meta.id).await?,
+                    false => drive.trash(&meta.id).await?,
+                };
+                print_meta(&meta, json);
+            }
+        }
+        Command::Search {
+            text,
+            name,
+            mime,
+            parent,
+            modified_after,
+            owner,
+            drive: shared_drive,
+            all_drives,
+            limit,
+            long,
+        } => {
+            let mut queries = Vec::new();
+            queries.extend(text.map(Query::full_text));
+            queries.extend(name.map(Query::name_contains));
+            queries.extend(mime.map(Query::mime_type));
+            if let Some(parent) = parent {
+                queries.push(Query::parent(resolve(drive, &parent).await?.id));
+            }
+            queries.extend(modified_after.map(Query::modified_after));
+            queries.extend(owner.map(Query::owner));
+            if queries.is_empty() {
+                bail!("specify a search text or at least one condition");
+            }
+            queries.push(Query::trashed(false));
+            let query = Query::And(queries);
+            let corpus = match (shared_drive, all_drives) {
+                (Some(id), _) => Corpus::Drive(id.into()),
+                (None, true) => Corpus::AllDrives,
+                (None, false) => Corpus::User,
+            };
+            let opts = SearchOptions {
+                corpus,
+                ..Default::default()
+            };
+            let items = search(drive, &query, &opts, limit).await?;
+            print_items(&items, long, json);
+        }
+        Command::Mirror {
+            target,
+            dir,
+            concurrency,
+            removed,
+        } => {
+            let folder = resolve(drive, &target).await?;
+            if !folder.is_directory() {
+                bail!("{} is not a folder", target);
+            }
+            let opts = MirrorOptions {
+                concurrency,
+                removed: match removed {
+                    RemovedArg::Quarantine => Removed::default(),
+                    RemovedArg::Delete => Removed::Delete,
+                    RemovedArg::Keep => Removed::Keep,
+                },
+            };
+            let report = drive.mirror(&folder.id, &dir, &opts).await?;
+            if json {
+                println!("{:#}", output::report_json(&report));
+            } else {
+                println!("{}", report);
+                for (path, e) in &report.failed {
+                    println!("failed: {}: {}", path.display(), e);
+                }
+            }
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
         }
     }
-
     Ok(())
 }
+
+/// 共有URL、パス、IDのいずれかからアイテムを求める
+///
+/// `/`か`:`を含むもの、または`My Drive`はパス、それ以外はIDとみなす。
+async fn resolve(drive: &GDrive, target: &str) -> Result<GMeta> {
+    let context = || format!("cannot get {}", target);
+    if let Some(id) = GDriveId::from_url(target) {
+        return drive.get_meta(&id).await.with_context(context);
+    }
+    if is_path(target) {
+        return drive
+            .resolve_path(target, DuplicatePolicy::Error)
+            .await
+            .with_context(context);
+    }
+    match drive.get_meta(&target.into()).await {
+        Err(Error::NotFound(_)) => {
+            bail!("{} is not found (use `/{}` for a path)", target, target)
+        }
+        result => result.with_context(context),
+    }
+}
+
+fn is_path(target: &str) -> bool {
+    target.contains('/') || target.contains(':') || target == "My Drive"
+}
+
+/// `mv`の移動先を解釈して、移動と名前の変更を行う
+async fn move_item(drive: &GDrive, source: &GMeta, dest: &str) -> Result<GMeta> {
+    if !is_path(dest) {
+        return Ok(drive.rename(&source.id, dest).await?);
+    }
+    match drive.resolve_path(dest, DuplicatePolicy::Error).await {
+        Ok(folder) if folder.is_directory() => {
+            return Ok(drive.move_to(&source.id, &folder.id).await?);
+        }
+        Ok(_) => bail!("{} already exists", dest),
+        Err(Error::PathNotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let (parent, name) = dest
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .unwrap_or(("", dest));
+    // `共有ドライブ名:`は`共有ドライブ名:/`にする
+    let parent = match parent {
+        "" => "My Drive".to_string(),
+        p if p.ends_with(':') => format!("{}/", p),
+        p => p.to_string(),
+    };
+    let parent = resolve(drive, &parent).await?;
+    if !parent.is_directory() {
+        bail!("{} is not a folder", parent.name);
+    }
+    let mut meta = source.clone();
+    if !source.parents.contains(&parent.id) {
+        meta = drive.move_to(&source.id, &parent.id).await?;
+    }
+    if meta.name != name {
+        meta = drive.rename(&source.id, name).await?;
+    }
+    Ok(meta)
+}
+
+/// フォルダ以下を再帰的に取得する
+fn walk(drive: &GDrive, meta: GMeta) -> Pin<Box<dyn Future<Output = Result<Node>> + '_>> {
+    Box::pin(async move {
+        let mut children = Vec::new();
+        if meta.is_directory() {
+            for child in drive.list(&meta.id).await? {
+                children.push(walk(drive, child).await?);
+            }
+        }
+        Ok(Node { meta, children })
+    })
+}
+
+/// `limit`件まで検索する。必要なページだけを取得する
+async fn search(
+    drive: &GDrive,
+    query: &Query,
+    opts: &SearchOptions,
+    limit: Option<usize>,
+) -> Result<Vec<GMeta>> {
+    use futures::{StreamExt, TryStreamExt};
+
+    let stream = drive.search_stream(query, opts);
+    let items = match limit {
+        Some(n) => stream.take(n).try_collect().await?,
+        None => stream.try_collect().await?,
+    };
+    Ok(items)
+}
+
+fn print_items(items: &[GMeta], long: bool, json: bool) {
+    if json {
+        let v: Vec<_> = items.iter().map(output::meta_json).collect();
+        println!("{:#}", serde_json::Value::Array(v));
+        return;
+    }
+    for meta in items {
+        if long {
+            println!("{}", output::long(meta));
+        } else {
+            println!("{}", output::short(meta));
+        }
+    }
+}
+
+fn print_meta(meta: &GMeta, json: bool) {
+    if json {
+        println!("{}", output::meta_json(meta));
+    } else {
+        println!("{}", output::long(meta));
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(String::from)
+        .with_context(|| format!("invalid file name: {}", path.display()))
+}
+
+/// 拡張子からMIMEタイプを推測する
+fn guess_mime(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        "docx" => ExportFormat::Docx.mime_type(),
+        "xlsx" => ExportFormat::Xlsx.mime_type(),
+        "pptx" => ExportFormat::Pptx.mime_type(),
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        let cli =
+            Cli::try_parse_from(["gdrive", "--json", "get", "My Drive/doc", "-f", "md"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(
+            cli.command,
+            Command::Get {
+                format: Some(ExportFormat::Markdown),
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["gdrive", "search", "--modified-after", "2025-01-02", "x"])
+            .unwrap();
+        let Command::Search { modified_after, .. } = cli.command else {
+            panic!()
+        };
+        assert_eq!(
+            modified_after,
+            Some("2025-01-02T00:00:00Z".parse().unwrap())
+        );
+        assert!(Cli::try_parse_from(["gdrive", "put", "a.txt", "--replace"]).is_err());
+        assert!(Cli::try_parse_from(["gdrive", "rm"]).is_err());
+    }
+
+    #[test]
+    fn distinguishes_paths_from_ids() {
+        assert!(is_path("My Drive"));
+        assert!(is_path("/a.txt"));
+        assert!(is_path("Team:/a"));
+        assert!(!is_path("1wS6tVoVmkdMZ96DgcKew-pSYBpJa9pXA"));
+        assert_eq!(guess_mime(Path::new("a/B.PNG")), "image/png");
+        assert_eq!(guess_mime(Path::new("noext")), "application/octet-stream");
+    }
+}
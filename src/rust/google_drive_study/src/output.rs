@@ -0,0 +1,171 @@
+//! 結果の表示（テキストとJSON）
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use google_drive::{GMeta, MirrorReport};
+use serde_json::{Value, json};
+
+/// フォルダとその中身
+pub struct Node {
+    pub meta: GMeta,
+    pub children: Vec<Node>,
+}
+
+fn time(t: Option<DateTime<Utc>>) -> Option<String> {
+    t.map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// メタデータをJSONにする。取得していないフィールドは含めない
+pub fn meta_json(meta: &GMeta) -> Value {
+    let mut v = json!({
+        "id": meta.id.as_ref(),
+        "name": meta.name,
+        "mimeType": meta.mime_type,
+    });
+    let mut set = |key: &str, value: Value| {
+        if !value.is_null() {
+            v[key] = value;
+        }
+    };
+    set("size", json!(meta.size));
+    set("modifiedTime", json!(time(meta.modified_time)));
+    set("createdTime", json!(time(meta.created_time)));
+    set("md5Checksum", json!(meta.md5_checksum));
+    set("sha256Checksum", json!(meta.sha256_checksum));
+    set("trashed", json!(meta.trashed));
+    set("starred", json!(meta.starred));
+    set("canDownload", json!(meta.can_download));
+    set("driveId", json!(meta.drive_id.as_ref().map(|d| d.as_ref())));
+    set("webViewLink", json!(meta.web_view_link));
+    if !meta.parents.is_empty() {
+        let parents: Vec<&str> = meta.parents.iter().map(|p| p.as_ref()).collect();
+        set("parents", json!(parents));
+    }
+    if !meta.owners.is_empty() {
+        let owners: Vec<_> = meta
+            .owners
+            .iter()
+            .map(|o| json!({"displayName": o.display_name, "emailAddress": o.email_address}))
+            .collect();
+        set("owners", json!(owners));
+    }
+    v
+}
+
+pub fn node_json(node: &Node) -> Value {
+    let mut v = meta_json(&node.meta);
+    if node.meta.is_directory() {
+        v["children"] = node.children.iter().map(node_json).collect();
+    }
+    v
+}
+
+pub fn report_json(report: &MirrorReport) -> Value {
+    let paths = |v: &[std::path::PathBuf]| -> Vec<String> {
+        v.iter().map(|p| p.display().to_string()).collect()
+    };
+    let failed: Vec<_> = report
+        .failed
+        .iter()
+        .map(|(p, e)| json!({"path": p.display().to_string(), "error": e.to_string()}))
+        .collect();
+    json!({
+        "downloaded": paths(&report.downloaded),
+        "exported": paths(&report.exported),
+        "unchanged": paths(&report.unchanged),
+        "unavailable": paths(&report.unavailable),
+        "removed": paths(&report.removed),
+        "failed": failed,
+    })
+}
+
+/// 名前。フォルダには`/`を付ける
+pub fn short(meta: &GMeta) -> String {
+    if meta.is_directory() {
+        format!("{}/", meta.name)
+    } else {
+        meta.name.clone()
+    }
+}
+
+/// `更新日時 サイズ ID 名前`
+pub fn long(meta: &GMeta) -> String {
+    let modified = time(meta.modified_time).unwrap_or_else(|| "-".into());
+    let size = match meta.size {
+        Some(size) if !meta.is_directory() => size.to_string(),
+        _ => "-".into(),
+    };
+    format!(
+        "{:<20} {:>12}  {}  {}",
+        modified,
+        size,
+        meta.id,
+        short(meta)
+    )
+}
+
+/// `tree`コマンドのように表示する
+pub fn print_tree(node: &Node, long_format: bool) {
+    let line = |meta: &GMeta| if long_format { long(meta) } else { short(meta) };
+    println!("{}", line(&node.meta));
+    fn children(node: &Node, prefix: &str, line: &dyn Fn(&GMeta) -> String) {
+        for (i, child) in node.children.iter().enumerate() {
+            let last = i + 1 == node.children.len();
+            println!(
+                "{}{}{}",
+                prefix,
+                if last { "└── " } else { "├── " },
+                line(&child.meta)
+            );
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            children(child, &prefix, line);
+        }
+    }
+    children(node, "", &line);
+}
+
+/// `stat`の表示
+pub fn print_stat(meta: &GMeta) {
+    let json = meta_json(meta);
+    for (key, value) in json.as_object().unwrap() {
+        match value {
+            Value::String(s) => println!("{:<16} {}", key, s),
+            Value::Array(v) => {
+                let v: Vec<String> = v
+                    .iter()
+                    .map(|x| match x {
+                        Value::String(s) => s.clone(),
+                        x => x.to_string(),
+                    })
+                    .collect();
+                println!("{:<16} {}", key, v.join(", "))
+            }
+            value => println!("{:<16} {}", key, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_omits_missing_fields() {
+        let meta = GMeta {
+            id: "id".into(),
+            name: "a.txt".into(),
+            mime_type: "text/plain".into(),
+            size: Some(3),
+            modified_time: Some("2025-01-02T03:04:05Z".parse().unwrap()),
+            parents: vec!["root".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            meta_json(&meta),
+            json!({
+                "id": "id", "name": "a.txt", "mimeType": "text/plain", "size": 3,
+                "modifiedTime": "2025-01-02T03:04:05Z", "parents": ["root"],
+            })
+        );
+        assert_eq!(long(&meta), "2025-01-02T03:04:05Z            3  id  a.txt");
+    }
+}
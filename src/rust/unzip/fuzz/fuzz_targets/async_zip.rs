@@ -0,0 +1,8 @@
+#![no_main]
+
+mod common;
+
+use libfuzzer_sys::fuzz_target;
+use unzip::AsyncZip;
+
+fuzz_target!(|data: &[u8]| common::run::<AsyncZip>(data));
@@ -0,0 +1,80 @@
+//! 任意のバイト列を zip として各バックエンドに渡す共通処理
+
+use std::path::Path;
+
+use unzip::{nested::Nested, ExtractOptions, Unzip};
+
+/// 1 回の展開で書き込む合計サイズの上限
+const MAX_TOTAL_SIZE: u64 = 64 << 20;
+
+/// 展開するアーカイブのエントリ数の上限。これより多ければ検査だけする
+const MAX_ENTRIES: usize = 4096;
+
+/// `data` を検査、展開し、パニックしないことと展開先の外に書き込まないことを確認する
+///
+/// 検査はデータを捨てる `NullSink` に展開する。
+/// 展開はライブラリがパスに直接書き込むため捨てるシンクにはできない。外に書き込んでいないかを
+/// 実際のファイルで調べるため、意図して `/dev/shm` (無ければ通常の一時ディレクトリ) に展開する。
+/// tmpfs のサイズや inode を使い切らないよう、合計サイズを `MAX_TOTAL_SIZE`、
+/// エントリ数を `MAX_ENTRIES` までに抑え、展開先は実行ごとに削除する。
+pub fn run<U: Unzip>(data: &[u8]) {
+    let sandbox = if Path::new("/dev/shm").is_dir() {
+        tempfile::tempdir_in("/dev/shm")
+    } else {
+        tempfile::tempdir()
+    }
+    .unwrap();
+    let src = sandbox.path().join("fuzz.zip");
+    std::fs::write(&src, data).unwrap();
+    let dest = sandbox.path().join("out");
+    std::fs::create_dir(&dest).unwrap();
+
+    let opts = ExtractOptions {
+        nested: Some(Nested {
+            max_total_size: MAX_TOTAL_SIZE,
+            ..Nested::default()
+        }),
+        ..ExtractOptions::default()
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let entries = zip::ZipArchive::new(std::io::Cursor::new(data)).map_or(0, |zip| zip.len());
+    rt.block_on(async {
+        let _ = U::verify(&src, None).await;
+        if entries <= MAX_ENTRIES {
+            let _ = U::unzip(&src, &dest, &opts).await;
+        }
+    });
+
+    for entry in std::fs::read_dir(sandbox.path()).unwrap() {
+        let name = entry.unwrap().file_name();
+        assert!(
+            name == "fuzz.zip" || name == "out",
+            "{:?} was written outside the destination",
+            name
+        );
+    }
+    assert_inside(&dest, &dest.canonicalize().unwrap());
+}
+
+/// `dir` 以下のシンボリックリンクが `root` の外を指していないことを確認する
+fn assert_inside(dir: &Path, root: &Path) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let meta = std::fs::symlink_metadata(&path).unwrap();
+        if meta.is_symlink() {
+            if let Ok(target) = path.canonicalize() {
+                assert!(
+                    target.starts_with(root),
+                    "{:?} points outside the destination: {:?}",
+                    path,
+                    target
+                );
+            }
+        } else if meta.is_dir() {
+            assert_inside(&path, root);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! `is_safe_path` が true を返したパスは、展開先に結合しても展開先の外に出ないことを確認する
+
+#![no_main]
+
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use libfuzzer_sys::fuzz_target;
+use unzip::is_safe_path;
+
+/// ファイルシステムを見ずに `.` と `..` を解決する
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fuzz_target!(|data: &[u8]| {
+    let path = Path::new(OsStr::from_bytes(data));
+    if !is_safe_path(path) {
+        return;
+    }
+    let base = Path::new("/base/dir");
+    let joined = normalize(&base.join(path));
+    assert!(
+        joined.starts_with(base),
+        "{:?} escapes to {:?}",
+        path,
+        joined
+    );
+});
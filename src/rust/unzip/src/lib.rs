@@ -0,0 +1,1165 @@
+//! zip を展開する各種ライブラリの比較用の実装
+
+pub mod nested;
+pub mod overwrite;
+pub mod registry;
+pub mod report;
+pub mod verify;
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Result};
+use nested::{Expander, Nested};
+use overwrite::{from_async_zip_datetime, from_zip_datetime, Action, Overwrite};
+use registry::Capabilities;
+use report::Report;
+use ripunzip::UnzipOptions;
+use verify::{check_local_header, sink_for, Declared, EntryCheck, Manifest, VerifyReport};
+
+/// 展開時のオプション
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// 展開先に既にファイルがある場合の扱い
+    pub overwrite: Overwrite,
+    /// 内側のアーカイブを再帰的に展開する場合に指定する
+    pub nested: Option<Nested>,
+    /// パスワード付きの zip のパスワード
+    pub password: Option<String>,
+}
+
+/// 展開処理
+///
+/// 返す `Future` は `Send` なので、`Registry` から選んだバックエンドも `tokio::spawn` で動かせる。
+/// 実装は `async fn` で書ける。
+pub trait Unzip {
+    /// `Registry` で使う名前
+    const NAME: &'static str;
+    const CAPABILITIES: Capabilities;
+
+    fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> impl Future<Output = Result<Report>> + Send;
+
+    /// 展開せずに全エントリの CRC-32 とサイズ、ローカルヘッダを検査する
+    ///
+    /// `manifest` を指定した場合は SHA-256 も検査する。
+    fn verify<S: AsRef<Path> + Send>(
+        src: S,
+        manifest: Option<&Manifest>,
+    ) -> impl Future<Output = Result<VerifyReport>> + Send {
+        async move { verify::verify_zip(src.as_ref(), manifest) }
+    }
+}
+
+/// 指定されたディレクトリを再帰的に検索し、見つかったファイルパスをソートして返す
+///
+/// # 引数
+/// * `dir` - 検索を開始するディレクトリのパス
+///
+/// # 戻り値
+/// * `Result<Vec<String>, Box<dyn Error>>` - 成功した場合はソートされたファイルパスのリスト、失敗した場合はエラー
+pub fn find_and_sort(root_path: PathBuf) -> Result<Vec<String>> {
+    let mut result = Vec::new();
+    collect_files(&root_path, &root_path, &mut result)?;
+    result.sort();
+    Ok(result)
+}
+
+/// 再帰的にファイルを収集する補助関数
+fn collect_files(
+    root: &Path,
+    current: &Path,
+    result: &mut Vec<String>
+) -> Result<()> {
+    if current.is_dir() {
+        for entry in std::fs::read_dir(current)? {
+            let entry = entry?;
+            let path = entry.path();
+            collect_files(root, &path, result)?;
+        }
+    } else if current.is_file() {
+        if let Ok(relative) = current.strip_prefix(root) {
+            if let Some(path_str) = relative.to_str() {
+                if !path_str.is_empty() {
+                    result.push(path_str.to_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+
+/// アーカイブ内のパスが展開先の外を指さないかを判定する
+///
+/// 絶対パスやドライブ指定、展開先より上に出る `..` を含む場合は `false`。
+pub fn is_safe_path<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path.to_str().is_none() || path.to_string_lossy().contains('\0') {
+        return false;
+    }
+
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                if components.is_empty() {
+                    return false;
+                }
+                components.pop();
+            }
+            std::path::Component::Normal(_) => components.push(component),
+            std::path::Component::CurDir => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// アーカイブ内のファイルのパスと更新日時
+type EntryTimes = HashMap<PathBuf, Option<SystemTime>>;
+
+/// アーカイブ内のディレクトリとファイルの更新日時、セントラルディレクトリ上の展開後の合計サイズを求める
+fn scan_entries(src: &Path) -> Result<(Vec<PathBuf>, EntryTimes, u64)> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(src)?))?;
+    let mut dirs = Vec::new();
+    let mut files = HashMap::new();
+    let mut size = 0u64;
+    for i in 0..zip.len() {
+        let file = zip.by_index_raw(i)?;
+        if file.is_dir() {
+            if let Some(path) = file.enclosed_name() {
+                dirs.push(path);
+            }
+            continue;
+        }
+        size = size.saturating_add(file.size());
+        if let Some(path) = file.enclosed_name() {
+            files.insert(path, file.last_modified().and_then(from_zip_datetime));
+        }
+    }
+    Ok((dirs, files, size))
+}
+
+/// 全エントリを読み捨て、展開後のサイズがセントラルディレクトリ上のサイズを超えないことを確かめる
+///
+/// 出力先を制御できないライブラリは書き込みを数えられないため、宣言されたサイズで上限を確認する。
+/// サイズを偽ったエントリは、宣言より 1 バイト多く読んだ時点でエラーにする。
+fn check_declared_sizes(src: &Path, password: Option<&str>) -> Result<()> {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(src)?))?;
+    for i in 0..zip.len() {
+        let mut file = match password {
+            Some(password) => zip.by_index_decrypt(i, password.as_bytes())?,
+            None => zip.by_index(i)?,
+        };
+        let declared = file.size();
+        let read = std::io::copy(
+            &mut (&mut file).take(declared.saturating_add(1)),
+            &mut std::io::sink(),
+        )?;
+        if read > declared {
+            return Err(anyhow!(
+                "{}: entry is larger than its declared size",
+                file.name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// ファイル単位で展開先を制御できないライブラリ用の展開処理
+///
+/// `extract` で一時ディレクトリに展開した後、内側のアーカイブは `dir` に展開し、
+/// それ以外のファイルは上書きポリシーに従って `dir` に配置する。
+/// 再帰展開せず `Overwrite::Always` の場合は一時ディレクトリを使わず `dir` に直接展開する。
+///
+/// 書き込みを数えられないため、展開前にセントラルディレクトリ上のサイズを `ex` の上限から差し引く。
+/// 上限を指定した場合は、先にサイズを偽ったエントリが無いことを確かめる。
+fn extract_staged<F>(
+    src: &Path,
+    dir: &Path,
+    opts: &ExtractOptions,
+    ex: &Expander,
+    extract: F,
+) -> Result<Report>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    if opts.nested.is_some() {
+        check_declared_sizes(src, opts.password.as_deref())?;
+    }
+    let (dirs, files, declared) = scan_entries(src)?;
+    ex.charge(declared)?;
+    let mut report = Report::default();
+    std::fs::create_dir_all(dir)?;
+    if opts.overwrite.is_always() && !ex.is_enabled() {
+        for path in files.keys() {
+            let path = dir.join(path);
+            report.record(if path.exists() {
+                Action::Overwrite(path)
+            } else {
+                Action::Create(path)
+            });
+        }
+        extract(dir)?;
+        return Ok(report);
+    }
+
+    let staging = tempfile::Builder::new().prefix(".unzip-").tempdir_in(dir)?;
+    extract(staging.path())?;
+    for path in dirs {
+        std::fs::create_dir_all(dir.join(path))?;
+    }
+    for rel in find_and_sort(staging.path().into())? {
+        let path = dir.join(&rel);
+        let staged = staging.path().join(&rel);
+        // 内側のアーカイブは、ストリームで展開するライブラリと同じく配置せずに展開する
+        if ex.target(&path, 1).is_some() {
+            ex.expand_file(&staged, &path, &mut report)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let modified = files.get(Path::new(&rel)).copied().flatten();
+        let mut action = opts.overwrite.resolve(&path, modified)?;
+        action.place(&staged)?;
+        report.record(action);
+    }
+    Ok(report)
+}
+
+///
+/// zip_extra
+///
+pub struct ZipExtra {}
+impl Unzip for ZipExtra {
+    const NAME: &'static str = "zip_extra";
+    const CAPABILITIES: Capabilities = Capabilities {
+        parallel: false,
+        streaming: false,
+        encryption: false,
+        metadata: true,
+    };
+
+    async fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> Result<Report> {
+        use std::fs::File;
+        use std::io::BufReader;
+
+        let ex = Expander::new(opts);
+        extract_staged(src.as_ref(), dir.as_ref(), opts, &ex, |out| {
+            let reader = BufReader::new(File::open(&src)?);
+            zip_extract::extract(reader, out, false)?;
+            Ok(())
+        })
+    }
+}
+
+///
+/// ripunzip
+///
+pub struct Ripunzip {}
+impl Unzip for Ripunzip {
+    const NAME: &'static str = "ripunzip";
+    const CAPABILITIES: Capabilities = Capabilities {
+        parallel: true,
+        streaming: false,
+        encryption: true,
+        metadata: true,
+    };
+
+    async fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> Result<Report> {
+        use std::fs::File;
+
+        let ex = Expander::new(opts);
+        extract_staged(src.as_ref(), dir.as_ref(), opts, &ex, |out| {
+            let file = File::open(&src)?;
+            let zip = ripunzip::UnzipEngine::for_file(file)?;
+            zip.unzip(UnzipOptions {
+                output_directory: Some(out.into()),
+                password: opts.password.clone(),
+                single_threaded: false,
+                filename_filter: None,
+                progress_reporter: Box::new(ripunzip::NullProgressReporter {}),
+            })?;
+            Ok(())
+        })
+    }
+}
+
+pub struct ParallelZip {}
+impl Unzip for ParallelZip {
+    const NAME: &'static str = "parallel_zip";
+    const CAPABILITIES: Capabilities = Capabilities {
+        parallel: true,
+        streaming: true,
+        encryption: false,
+        metadata: false,
+    };
+
+    async fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> Result<Report> {
+        use std::fs::File;
+        use std::io::BufReader;
+
+        let len = {
+            let reader = BufReader::new(File::open(&src)?);
+
+            zip::ZipArchive::new(reader)?.len()
+        };
+        let task = async |from: usize,
+                          end: usize,
+                          src: PathBuf,
+                          base: PathBuf,
+                          ex: Expander|
+                          -> Result<Report> {
+            let reader = BufReader::new(File::open(src)?);
+            let mut zip = zip::ZipArchive::new(reader)?;
+            let mut report = Report::default();
+            for i in from..end {
+                let mut file = zip.by_index(i)?;
+                let path = file.mangled_name();
+                if path.to_string_lossy().is_empty() {
+                    continue;
+                }
+                if !is_safe_path(&path) {
+                    continue;
+                }
+                let path = base.join(path);
+
+                if file.name().ends_with('/') {
+                    std::fs::create_dir_all(path)?;
+                } else if let Some(parent) = path.parent() {
+                    if !parent.is_dir() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    let modified = file.last_modified().and_then(from_zip_datetime);
+                    ex.write(&mut file, &path, modified, 0, &mut report)?;
+                }
+            }
+            Ok(report)
+        };
+
+        let ex = Expander::new(opts);
+        let cores = (num_cpus::get() / 2).max(1);
+        let joins: Vec<_> = (0..cores)
+            .map(|i| {
+                let from = len * i / cores;
+                let end = len * (i + 1) / cores;
+                tokio::task::spawn(task(
+                    from,
+                    end,
+                    src.as_ref().into(),
+                    dir.as_ref().into(),
+                    ex.clone(),
+                ))
+            })
+            .collect();
+        let mut report = Report::default();
+        let mut errmsg = String::new();
+        for j in joins {
+            match j.await {
+                Ok(Ok(r)) => report.merge(r),
+                Ok(Err(e)) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+                Err(e) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+            }
+        }
+        if !errmsg.is_empty() {
+            Err(anyhow!("{}", errmsg))
+        } else {
+            Ok(report)
+        }
+    }
+}
+
+/// async_zip でアーカイブの `from..end` 番目のエントリを検査する
+async fn verify_async_zip(
+    src: PathBuf,
+    from: usize,
+    end: usize,
+    manifest: Option<Manifest>,
+) -> Result<Vec<EntryCheck>> {
+    use async_zip::tokio::read::seek::ZipFileReader;
+    use tokio::fs::File;
+    use tokio::io::BufReader;
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+    let mut zip = ZipFileReader::with_tokio(BufReader::new(File::open(&src).await?)).await?;
+    let mut raw = std::io::BufReader::new(std::fs::File::open(&src)?);
+    let manifest = manifest.as_ref();
+    let mut checks = Vec::new();
+    for i in from..end {
+        let Some(e) = zip.file().entries().get(i) else {
+            break;
+        };
+        if e.dir()? {
+            continue;
+        }
+        let declared = Declared {
+            name: e.filename().as_bytes().into(),
+            method: e.compression().into(),
+            crc32: e.crc32(),
+            compressed_size: e.compressed_size(),
+            uncompressed_size: e.uncompressed_size(),
+            header_offset: e.header_offset(),
+        };
+        let problems = check_local_header(&mut raw, &declared);
+        let mut sink = sink_for(&declared, manifest);
+        let read = match zip.reader_without_entry(i).await {
+            Ok(reader) => tokio::io::copy(&mut reader.compat(), &mut sink).await,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        checks.push(sink.finish(&declared, read, problems, manifest));
+    }
+    Ok(checks)
+}
+
+///
+/// async_zip
+///
+pub struct AsyncZip {}
+impl Unzip for AsyncZip {
+    const NAME: &'static str = "async_zip";
+    const CAPABILITIES: Capabilities = Capabilities {
+        parallel: false,
+        streaming: true,
+        encryption: false,
+        metadata: false,
+    };
+
+    async fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> Result<Report> {
+        use async_zip::tokio::read::seek::ZipFileReader;
+        use tokio::fs::{create_dir_all, File};
+        use tokio::io::BufReader;
+        use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+        let mut zip = ZipFileReader::with_tokio(BufReader::new(File::open(src).await?)).await?;
+        let base = dir.as_ref();
+        let len = zip.file().entries().len();
+        let ex = Expander::new(opts);
+        let mut report = Report::default();
+        for i in 0..len {
+            let e = zip
+                .file()
+                .entries()
+                .get(i)
+                .ok_or_else(|| anyhow!("entry {} not found", i))?;
+            let path = Path::new(e.filename().as_str()?);
+            if !is_safe_path(path) {
+                continue;
+            }
+
+            let path = base.join(path);
+
+            if e.dir()? {
+                create_dir_all(path).await?;
+            } else {
+                let modified = from_async_zip_datetime(e.last_modification_date());
+                let mut reader = zip.reader_without_entry(i).await?.compat();
+                ex.write_async(&mut reader, &path, modified, &mut report)
+                    .await?;
+            }
+        }
+        Ok(report)
+    }
+
+    async fn verify<S: AsRef<Path> + Send>(
+        src: S,
+        manifest: Option<&Manifest>,
+    ) -> Result<VerifyReport> {
+        let entries =
+            verify_async_zip(src.as_ref().into(), 0, usize::MAX, manifest.cloned()).await?;
+        let mut report = VerifyReport { entries };
+        report.finish(manifest);
+        Ok(report)
+    }
+}
+
+///
+/// async_zip (parallel)
+///
+pub struct AsyncZipParallel {}
+impl Unzip for AsyncZipParallel {
+    const NAME: &'static str = "async_zip_parallel";
+    const CAPABILITIES: Capabilities = Capabilities {
+        parallel: true,
+        streaming: true,
+        encryption: false,
+        metadata: false,
+    };
+
+    async fn unzip<S: AsRef<Path> + Send, D: AsRef<Path> + Send>(
+        src: S,
+        dir: D,
+        opts: &ExtractOptions,
+    ) -> Result<Report> {
+        use async_zip::tokio::read::seek::ZipFileReader;
+        use tokio::fs::{create_dir_all, File};
+        use tokio::io::BufReader;
+        use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+        let len = {
+            let zip = ZipFileReader::with_tokio(BufReader::new(File::open(src.as_ref()).await?)).await?;
+            zip.file().entries().len()
+        };
+        let task = async |from: usize,
+                          end: usize,
+                          src: PathBuf,
+                          base: PathBuf,
+                          ex: Expander|
+                          -> Result<Report> {
+            let mut zip = ZipFileReader::with_tokio(BufReader::new(File::open(src).await?)).await?;
+            let mut report = Report::default();
+            for i in from..end {
+                let e = zip
+                    .file()
+                    .entries()
+                    .get(i)
+                    .ok_or_else(|| anyhow!("entry {} not found", i))?;
+                let path = Path::new(e.filename().as_str()?);
+                if !is_safe_path(path) {
+                    continue;
+                }
+
+                let path = base.join(path);
+
+                if e.dir()? {
+                    create_dir_all(path).await?;
+                } else if let Some(parent) = path.parent() {
+                    if !parent.is_dir() {
+                        create_dir_all(parent).await?;
+                    }
+                    let modified = from_async_zip_datetime(e.last_modification_date());
+                    let mut reader = zip.reader_without_entry(i).await?.compat();
+                    ex.write_async(&mut reader, &path, modified, &mut report)
+                        .await?;
+                }
+            }
+            Ok(report)
+        };
+
+        let ex = Expander::new(opts);
+        let cores = num_cpus::get();
+        let joins: Vec<_> = (0..cores)
+            .map(|i| {
+                let from = len * i / cores;
+                let end = len * (i + 1) / cores;
+                tokio::task::spawn(task(
+                    from,
+                    end,
+                    src.as_ref().into(),
+                    dir.as_ref().into(),
+                    ex.clone(),
+                ))
+            })
+            .collect();
+        let mut report = Report::default();
+        let mut errmsg = String::new();
+        for j in joins {
+            match j.await {
+                Ok(Ok(r)) => report.merge(r),
+                Ok(Err(e)) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+                Err(e) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+            }
+        }
+        if !errmsg.is_empty() {
+            Err(anyhow!("{}", errmsg))
+        } else {
+            Ok(report)
+        }
+    }
+
+    async fn verify<S: AsRef<Path> + Send>(
+        src: S,
+        manifest: Option<&Manifest>,
+    ) -> Result<VerifyReport> {
+        use async_zip::tokio::read::seek::ZipFileReader;
+        use tokio::fs::File;
+        use tokio::io::BufReader;
+
+        let len = {
+            let zip = ZipFileReader::with_tokio(BufReader::new(File::open(src.as_ref()).await?)).await?;
+            zip.file().entries().len()
+        };
+        let cores = num_cpus::get();
+        let joins: Vec<_> = (0..cores)
+            .map(|i| {
+                let from = len * i / cores;
+                let end = len * (i + 1) / cores;
+                tokio::task::spawn(verify_async_zip(
+                    src.as_ref().into(),
+                    from,
+                    end,
+                    manifest.cloned(),
+                ))
+            })
+            .collect();
+        let mut report = VerifyReport::default();
+        let mut errmsg = String::new();
+        for j in joins {
+            match j.await {
+                Ok(Ok(entries)) => report.entries.extend(entries),
+                Ok(Err(e)) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+                Err(e) => {
+                    errmsg.push_str(&format!("{}\n", e));
+                }
+            }
+        }
+        if !errmsg.is_empty() {
+            return Err(anyhow!("{}", errmsg));
+        }
+        report.finish(manifest);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nested::NestedTarget;
+    use overwrite::{Conflict, Decision};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::{tempdir, TempDir};
+    use zip::write::SimpleFileOptions;
+
+    /// `$check::<U>()` を全てのバックエンドについて実行するテストを `$name` モジュールに作る
+    macro_rules! backend_tests {
+        ($name:ident, $check:ident) => {
+            mod $name {
+                use super::*;
+
+                #[tokio::test]
+                async fn zip_extra() {
+                    $check::<ZipExtra>().await;
+                }
+
+                #[tokio::test]
+                async fn ripunzip() {
+                    $check::<Ripunzip>().await;
+                }
+
+                #[tokio::test]
+                async fn parallel_zip() {
+                    $check::<ParallelZip>().await;
+                }
+
+                #[tokio::test]
+                async fn async_zip() {
+                    $check::<AsyncZip>().await;
+                }
+
+                #[tokio::test]
+                async fn async_zip_parallel() {
+                    $check::<AsyncZipParallel>().await;
+                }
+            }
+        };
+    }
+
+    /// a.txt (2020-01-01) と dir/b.txt を含む zip を作る
+    fn make_zip(dir: &Path) -> PathBuf {
+        let path = dir.join("test.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let opts = SimpleFileOptions::default()
+            .last_modified_time(zip::DateTime::from_date_and_time(2020, 1, 1, 0, 0, 0).unwrap());
+        zip.start_file("a.txt", opts).unwrap();
+        zip.write_all(b"new").unwrap();
+        zip.add_directory("dir/", opts).unwrap();
+        zip.start_file("dir/b.txt", opts).unwrap();
+        zip.write_all(b"b").unwrap();
+        zip.finish().unwrap();
+        path
+    }
+
+    /// 展開先に a.txt を置いてから展開する
+    async fn run<U: Unzip>(overwrite: Overwrite, existing: SystemTime) -> (TempDir, Report) {
+        let src = tempdir().unwrap();
+        let zip = make_zip(src.path());
+        let out = tempdir().unwrap();
+        let a = out.path().join("a.txt");
+        std::fs::write(&a, "old").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&a)
+            .unwrap()
+            .set_modified(existing)
+            .unwrap();
+        let opts = ExtractOptions {
+            overwrite,
+            ..ExtractOptions::default()
+        };
+        let report = U::unzip(&zip, &out, &opts).await.unwrap();
+        (out, report)
+    }
+
+    fn read(dir: &TempDir, name: &str) -> String {
+        std::fs::read_to_string(dir.path().join(name)).unwrap()
+    }
+
+    async fn check_policies<U: Unzip>() {
+        let now = SystemTime::now();
+        let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000); // 2001
+
+        let (out, report) = run::<U>(Overwrite::Never, now).await;
+        assert_eq!(read(&out, "a.txt"), "old");
+        assert_eq!(read(&out, "dir/b.txt"), "b");
+        assert_eq!(report.skipped, vec![out.path().join("a.txt")]);
+        assert_eq!(report.created, vec![out.path().join("dir/b.txt")]);
+
+        let (out, report) = run::<U>(Overwrite::Always, now).await;
+        assert_eq!(read(&out, "a.txt"), "new");
+        assert_eq!(report.overwritten, vec![out.path().join("a.txt")]);
+        assert_eq!(report.created.len(), 1);
+
+        let (out, report) = run::<U>(Overwrite::IfNewer, now).await;
+        assert_eq!(read(&out, "a.txt"), "old");
+        assert_eq!(report.skipped, vec![out.path().join("a.txt")]);
+
+        let (out, report) = run::<U>(Overwrite::IfNewer, old).await;
+        assert_eq!(read(&out, "a.txt"), "new");
+        assert_eq!(report.overwritten, vec![out.path().join("a.txt")]);
+
+        let (out, report) = run::<U>(Overwrite::Rename, now).await;
+        assert_eq!(read(&out, "a.txt"), "old");
+        assert_eq!(read(&out, "a (1).txt"), "new");
+        assert_eq!(
+            report.renamed,
+            vec![(out.path().join("a.txt"), out.path().join("a (1).txt"))]
+        );
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let c = calls.clone();
+        let prompt = Overwrite::Prompt(Arc::new(move |conflict: &Conflict| {
+            c.fetch_add(1, Ordering::SeqCst);
+            assert!(conflict.path.ends_with("a.txt"));
+            assert_eq!(conflict.existing_modified, Some(old));
+            Decision::Rename
+        }));
+        let (out, report) = run::<U>(prompt, old).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(read(&out, "a.txt"), "old");
+        assert_eq!(read(&out, "a (1).txt"), "new");
+        assert_eq!(report.renamed.len(), 1);
+    }
+
+    backend_tests!(overwrite_policies, check_policies);
+
+    /// エントリを並べた zip をメモリ上に作る
+    fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn tar_gz_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, *data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// 最初のエントリの展開後のサイズを、ローカルヘッダとセントラルディレクトリの両方で `size` と偽る
+    fn forge_size(data: &mut [u8], size: u32) {
+        for (signature, offset) in [(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let i = data.windows(4).position(|w| w == signature).unwrap() + offset;
+            data[i..i + 4].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    /// `existing` のファイルを置いた展開先に `outer` を展開する
+    async fn unzip_into<U: Unzip>(
+        outer: Vec<u8>,
+        existing: &[(&str, &str)],
+        opts: &ExtractOptions,
+    ) -> (TempDir, Result<Report>) {
+        let src = tempdir().unwrap();
+        let zip = src.path().join("outer.zip");
+        std::fs::write(&zip, outer).unwrap();
+        let out = tempdir().unwrap();
+        for (name, data) in existing {
+            std::fs::write(out.path().join(name), data).unwrap();
+        }
+        let report = U::unzip(&zip, &out, opts).await;
+        (out, report)
+    }
+
+    /// a.txt, inner.zip (x.txt, deeper.zip (y.txt)), bundle.tar.gz (t/t.txt) を含む zip を展開する
+    async fn run_nested<U: Unzip>(nested: Nested) -> (TempDir, Result<Report>) {
+        let deeper = zip_bytes(&[("y.txt", b"y")]);
+        let inner = zip_bytes(&[("x.txt", b"x"), ("deeper.zip", &deeper)]);
+        let bundle = tar_gz_bytes(&[("t/t.txt", b"t")]);
+        let outer = zip_bytes(&[
+            ("a.txt", b"a"),
+            ("inner.zip", &inner),
+            ("bundle.tar.gz", &bundle),
+        ]);
+        let opts = ExtractOptions {
+            nested: Some(nested),
+            ..ExtractOptions::default()
+        };
+        unzip_into::<U>(outer, &[], &opts).await
+    }
+
+    async fn check_nested<U: Unzip>() {
+        let (out, report) = run_nested::<U>(Nested::default()).await;
+        let report = report.unwrap();
+        assert_eq!(
+            find_and_sort(out.path().into()).unwrap(),
+            [
+                "a.txt",
+                "bundle/t/t.txt",
+                "inner/deeper/y.txt",
+                "inner/x.txt"
+            ]
+            .map(|p| Path::new(p).to_string_lossy().into_owned())
+        );
+        assert_eq!(read(&out, "inner/deeper/y.txt"), "y");
+        assert_eq!(report.nested.len(), 3);
+        assert_eq!(report.created.len(), 4);
+
+        let (out, report) = run_nested::<U>(Nested {
+            max_depth: 1,
+            ..Nested::default()
+        })
+        .await;
+        assert_eq!(report.unwrap().nested.len(), 2);
+        assert!(out.path().join("inner/deeper.zip").is_file());
+
+        let (out, report) = run_nested::<U>(Nested {
+            target: NestedTarget::InPlace,
+            ..Nested::default()
+        })
+        .await;
+        report.unwrap();
+        assert_eq!(read(&out, "x.txt"), "x");
+        assert_eq!(read(&out, "y.txt"), "y");
+        assert_eq!(read(&out, "t/t.txt"), "t");
+
+        let (_, report) = run_nested::<U>(Nested {
+            max_total_size: 16,
+            ..Nested::default()
+        })
+        .await;
+        assert!(report.is_err());
+
+        // 外側のアーカイブ、内側のアーカイブ、サイズを偽ったアーカイブそれぞれの圧縮爆弾
+        let zeros = vec![0u8; 1 << 20];
+        let limit = 64 << 10;
+        let bomb = zip_bytes(&[("big.bin", &zeros)]);
+        let mut forged = bomb.clone();
+        forge_size(&mut forged, 1024);
+        let opts = ExtractOptions {
+            nested: Some(Nested {
+                max_total_size: limit,
+                ..Nested::default()
+            }),
+            ..ExtractOptions::default()
+        };
+        for outer in [bomb.clone(), zip_bytes(&[("inner.zip", &bomb)]), forged] {
+            let (out, report) = unzip_into::<U>(outer, &[], &opts).await;
+            assert!(report.is_err());
+            let written: u64 = find_and_sort(out.path().into())
+                .unwrap()
+                .iter()
+                .map(|p| std::fs::metadata(out.path().join(p)).unwrap().len())
+                .sum();
+            // 上限を超えたファイルは途中まで書いたものも残さない
+            assert_eq!(written, 0);
+        }
+
+        // 展開先にある同名のアーカイブは、どのポリシーでも上書き、リネームの対象にならない
+        let inner = zip_bytes(&[("x.txt", b"x")]);
+        let outer = zip_bytes(&[("inner.zip", &inner)]);
+        for overwrite in [Overwrite::Never, Overwrite::Rename, Overwrite::Always] {
+            let opts = ExtractOptions {
+                overwrite: overwrite.clone(),
+                nested: Some(Nested::default()),
+                ..ExtractOptions::default()
+            };
+            let existing = [("inner.zip", "mine")];
+            let (out, report) = unzip_into::<U>(outer.clone(), &existing, &opts).await;
+            let report = report.unwrap();
+            assert_eq!(read(&out, "inner.zip"), "mine", "{:?}", overwrite);
+            assert_eq!(read(&out, "inner/x.txt"), "x", "{:?}", overwrite);
+            assert!(!out.path().join("inner (1)").exists(), "{:?}", overwrite);
+            assert_eq!(report.nested, [out.path().join("inner.zip")]);
+        }
+    }
+
+    backend_tests!(nested_archives, check_nested);
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    /// 無圧縮の a.txt ("hello") と b.txt を含む zip を `patch` で書き換えて検査する
+    async fn run_verify<U: Unzip>(
+        patch: impl FnOnce(&mut Vec<u8>),
+        manifest: Option<&Manifest>,
+    ) -> VerifyReport {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let opts = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("a.txt", opts).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.start_file("b.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"b").unwrap();
+        let mut data = zip.finish().unwrap().into_inner();
+        patch(&mut data);
+        let src = tempdir().unwrap();
+        let path = src.path().join("test.zip");
+        std::fs::write(&path, data).unwrap();
+        U::verify(&path, manifest).await.unwrap()
+    }
+
+    /// 最初に見つかった `from` を `to` に置き換える
+    fn replace_first(data: &mut [u8], from: &[u8], to: &[u8]) {
+        let i = data.windows(from.len()).position(|w| w == from).unwrap();
+        data[i..i + to.len()].copy_from_slice(to);
+    }
+
+    async fn check_verify<U: Unzip>() {
+        let report = run_verify::<U>(|_| {}, None).await;
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.entries.len(), 2);
+
+        let report = run_verify::<U>(|d| replace_first(d, b"hello", b"jello"), None).await;
+        let failed: Vec<_> = report.failed().collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].name, "a.txt");
+        assert!(failed[0]
+            .problems
+            .iter()
+            .any(|p| matches!(p, verify::Problem::Crc { .. })));
+
+        // ローカルヘッダのファイル名だけを書き換える
+        let report = run_verify::<U>(|d| replace_first(d, b"a.txt", b"c.txt"), None).await;
+        let failed: Vec<_> = report.failed().collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(
+            failed[0].problems,
+            [verify::Problem::Header("file name differs".into())]
+        );
+
+        let manifest = Manifest::parse(&format!(
+            "{}  a.txt\n{} *b.txt\n{}  missing.txt\n",
+            HELLO_SHA256, HELLO_SHA256, HELLO_SHA256
+        ))
+        .unwrap();
+        let report = run_verify::<U>(|_| {}, Some(&manifest)).await;
+        let failed: Vec<_> = report.failed().map(|e| e.name.as_str()).collect();
+        assert_eq!(failed, ["b.txt", "missing.txt"]);
+    }
+
+    backend_tests!(integrity, check_verify);
+
+    #[test]
+    fn invalid_manifest() {
+        assert!(Manifest::parse("abc  a.txt").is_err());
+        assert!(Manifest::parse(HELLO_SHA256).is_err());
+        assert!(Manifest::parse(&format!("{} a.txt", HELLO_SHA256)).is_err());
+        assert!(Manifest::parse(&format!("{}  ", HELLO_SHA256)).is_err());
+    }
+
+    #[test]
+    fn manifest_keeps_leading_spaces_and_stars() {
+        let manifest = Manifest::parse(&format!(
+            "{}  *star.txt\n{} * space.txt\n{}    two.txt \n",
+            HELLO_SHA256, HELLO_SHA256, HELLO_SHA256
+        ))
+        .unwrap();
+        for name in ["*star.txt", " space.txt", "  two.txt "] {
+            assert_eq!(manifest.get(name), Some(HELLO_SHA256), "{:?}", name);
+        }
+        assert_eq!(manifest.get("star.txt"), None);
+    }
+
+    #[test]
+    fn detect_archive_kind() {
+        use nested::Kind;
+        assert_eq!(Kind::detect("a.tar.gz"), Some((Kind::TarGz, "a")));
+        assert_eq!(Kind::detect("a.TGZ"), Some((Kind::TarGz, "a")));
+        assert_eq!(Kind::detect("a.tar"), Some((Kind::Tar, "a")));
+        assert_eq!(Kind::detect("b.Zip"), Some((Kind::Zip, "b")));
+        assert_eq!(Kind::detect(".zip"), None);
+        assert_eq!(Kind::detect("c.txt"), None);
+    }
+
+    #[test]
+    fn safe_path() {
+        assert!(is_safe_path("a/b.txt"));
+        assert!(is_safe_path("a/../b.txt"));
+        assert!(is_safe_path("./a"));
+        assert!(!is_safe_path("../a"));
+        assert!(!is_safe_path("a/../../b"));
+        assert!(!is_safe_path("/etc/passwd"));
+        assert!(!is_safe_path("a\0b"));
+    }
+
+    #[test]
+    fn rename_picks_free_name() {
+        let out = tempdir().unwrap();
+        let a = out.path().join("a.txt");
+        std::fs::write(&a, "").unwrap();
+        std::fs::write(out.path().join("a (1).txt"), "").unwrap();
+        let action = Overwrite::Rename.resolve(&a, None).unwrap();
+        assert_eq!(
+            action,
+            Action::Rename(a.clone(), out.path().join("a (2).txt"))
+        );
+    }
+
+    #[test]
+    fn parse_policy() {
+        assert!(matches!("never".parse(), Ok(Overwrite::Never)));
+        assert!(matches!("if-newer".parse(), Ok(Overwrite::IfNewer)));
+        assert!("prompt-callback".parse::<Overwrite>().is_err());
+        assert!("foo".parse::<Overwrite>().is_err());
+    }
+
+    #[test]
+    fn zip_time_is_utc() {
+        let t = overwrite::zip_time(2020, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(t, UNIX_EPOCH + Duration::from_secs(1_577_836_800));
+    }
+
+    #[test]
+    fn registry_lookup() {
+        let registry = registry::Registry::with_defaults();
+        assert_eq!(
+            registry.names(),
+            [
+                "zip_extra",
+                "ripunzip",
+                "parallel_zip",
+                "async_zip",
+                "async_zip_parallel"
+            ]
+        );
+        assert_eq!(registry.get("ripunzip").unwrap().name(), "ripunzip");
+        assert!(registry.get("unknown").is_none());
+
+        let required = Capabilities {
+            parallel: true,
+            streaming: true,
+            ..Capabilities::default()
+        };
+        let names: Vec<_> = registry
+            .with_capabilities(required)
+            .map(|b| b.name())
+            .collect();
+        assert_eq!(names, ["parallel_zip", "async_zip_parallel"]);
+    }
+
+    #[test]
+    fn registry_replaces_same_name() {
+        let mut registry = registry::Registry::new();
+        registry.register_unzip::<AsyncZip>();
+        registry.register_unzip::<ZipExtra>();
+        registry.register_unzip::<AsyncZip>();
+        assert_eq!(registry.names(), ["async_zip", "zip_extra"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn registry_futures_can_be_spawned() {
+        let src = tempdir().unwrap();
+        let zip = make_zip(src.path());
+        let registry = Arc::new(registry::Registry::with_defaults());
+        let joins: Vec<_> = registry
+            .names()
+            .into_iter()
+            .map(|name| {
+                let registry = registry.clone();
+                let zip = zip.clone();
+                tokio::spawn(async move {
+                    let backend = registry.get(name).unwrap();
+                    let out = tempdir().unwrap();
+                    let opts = ExtractOptions::default();
+                    let report = backend.unzip(&zip, out.path(), &opts).await.unwrap();
+                    assert!(backend.verify(&zip, None).await.unwrap().is_ok());
+                    (name, report.created.len())
+                })
+            })
+            .collect();
+        for join in joins {
+            let (name, created) = join.await.unwrap();
+            assert_eq!(created, 2, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn registry_unzip() {
+        let src = tempdir().unwrap();
+        let zip = make_zip(src.path());
+        let registry = registry::Registry::with_defaults();
+        for backend in registry.iter() {
+            let out = tempdir().unwrap();
+            let report = backend
+                .unzip(&zip, out.path(), &ExtractOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(report.created.len(), 2, "{}", backend.name());
+            assert!(backend.verify(&zip, None).await.unwrap().is_ok());
+
+            let opts = ExtractOptions {
+                password: Some("secret".into()),
+                ..ExtractOptions::default()
+            };
+            let result = backend.unzip(&zip, out.path(), &opts).await;
+            assert_eq!(
+                result.is_ok(),
+                backend.capabilities().encryption,
+                "{}",
+                backend.name()
+            );
+        }
+    }
+}
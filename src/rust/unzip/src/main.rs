@@ -1,26 +1,167 @@
-use std::{
-    path::{Path, PathBuf},
-    process::exit,
-};
+use std::{path::Path, process::exit, sync::Arc};
 
 use anyhow::{anyhow, Result};
 use reqwest::Client;
-use ripunzip::UnzipOptions;
 use tempfile::tempdir;
-use tokio::{io::AsyncWriteExt, process::Command, time::Instant};
+use tokio::{io::AsyncWriteExt, time::Instant};
+use unzip::{
+    find_and_sort,
+    nested::{Nested, NestedTarget},
+    overwrite::{Conflict, Decision, Overwrite},
+    registry::{Extractor, Registry},
+    verify::Manifest,
+    ExtractOptions,
+};
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = extract(&args).await {
+            eprintln!("[ERR] {}", e);
+            exit(1);
+        }
+        return;
+    }
+
     init().await;
-    let a = test::<ZipExtra>().await;
-    let b = test::<Ripunzip>().await;
-    let c = test::<ParallelZip>().await;
-    let d = test::<AsyncZip>().await;
-    let e = test::<AsyncZipParallel>().await;
+    let registry = Registry::with_defaults();
+    let mut results = Vec::new();
+    for backend in registry.iter() {
+        results.push((backend.name(), test(backend).await));
+    }
+
+    let (first, expected) = &results[0];
+    for (name, files) in &results[1..] {
+        println!("{} == {} ? {}", first, name, expected == files);
+    }
+}
 
-    println!("a == c ? {}", a == c);
-    println!("a == e ? {}", a == e);
+/// 使用するバックエンドの既定値
+const DEFAULT_BACKEND: &str = "async_zip_parallel";
 
+/// `unzip SRC DIR [POLICY] [--nested | --nested-in-place] [--backend=NAME] [--password=PASSWORD]`
+/// で SRC を DIR に展開する
+///
+/// POLICY は never, always, if-newer, rename, prompt-callback のいずれか。
+/// prompt-callback の場合は衝突ごとに標準入力で確認する。
+/// `--nested` は内側のアーカイブを `foo/` に、`--nested-in-place` はその場に展開する。
+///
+/// `unzip --test SRC [MANIFEST] [--backend=NAME]` の場合は展開せずに検査する。
+/// `unzip --list` でバックエンドの一覧を表示する。
+async fn extract(args: &[String]) -> Result<()> {
+    let registry = Registry::with_defaults();
+    let (flags, args): (Vec<_>, Vec<_>) = args.iter().partition(|a| a.starts_with("--"));
+    let mut backend = DEFAULT_BACKEND;
+    let mut password = None;
+    let mut nested = None;
+    let mut test = false;
+    for flag in flags {
+        if let Some(name) = flag.strip_prefix("--backend=") {
+            backend = name;
+            continue;
+        }
+        if let Some(pw) = flag.strip_prefix("--password=") {
+            password = Some(pw.to_string());
+            continue;
+        }
+        let target = match flag.as_str() {
+            "--nested" => NestedTarget::Directory,
+            "--nested-in-place" => NestedTarget::InPlace,
+            "--test" => {
+                test = true;
+                continue;
+            }
+            "--list" => {
+                for b in registry.iter() {
+                    println!("{:<20} {}", b.name(), b.capabilities());
+                }
+                return Ok(());
+            }
+            _ => return Err(anyhow!("unknown option: {}", flag)),
+        };
+        nested = Some(Nested {
+            target,
+            ..Nested::default()
+        });
+    }
+    let Some(backend) = registry.get(backend) else {
+        return Err(anyhow!(
+            "unknown backend: {} (available: {})",
+            backend,
+            registry.names().join(", ")
+        ));
+    };
+    if test {
+        return test_archive(backend, &args).await;
+    }
+
+    let [src, dir, rest @ ..] = args.as_slice() else {
+        return Err(anyhow!(
+            "usage: unzip SRC DIR [POLICY] [--nested | --nested-in-place] [--backend=NAME] [--password=PASSWORD]"
+        ));
+    };
+    let overwrite = match rest.first().map(|s| s.as_str()) {
+        None => Overwrite::default(),
+        Some("prompt-callback") => Overwrite::Prompt(Arc::new(prompt)),
+        Some(s) => s.parse()?,
+    };
+    let opts = ExtractOptions {
+        overwrite,
+        nested,
+        password,
+    };
+    let report = backend.unzip(Path::new(src), Path::new(dir), &opts).await?;
+    for (from, to) in &report.renamed {
+        println!("[LOG] {} -> {}", from.display(), to.display());
+    }
+    for path in &report.skipped {
+        println!("[LOG] skip {}", path.display());
+    }
+    for path in &report.nested {
+        println!("[LOG] nested {}", path.display());
+    }
+    println!("[LOG] {}", report);
+    Ok(())
+}
+
+/// `unzip --test SRC [MANIFEST]` で SRC を検査する
+async fn test_archive(backend: &dyn Extractor, args: &[&String]) -> Result<()> {
+    let [src, rest @ ..] = args else {
+        return Err(anyhow!(
+            "usage: unzip --test SRC [MANIFEST] [--backend=NAME]"
+        ));
+    };
+    let manifest = rest.first().map(Manifest::load).transpose()?;
+    let report = backend.verify(Path::new(src), manifest.as_ref()).await?;
+    println!("{}", report);
+    if !report.is_ok() {
+        return Err(anyhow!("{} failed the integrity check", src));
+    }
+    Ok(())
+}
+
+/// 衝突したファイルの扱いを標準入力で確認する
+fn prompt(c: &Conflict) -> Decision {
+    let mut line = String::new();
+    loop {
+        println!(
+            "{} already exists (archive: {:?}, existing: {:?}). [o]verwrite / [s]kip / [r]ename ?",
+            c.path.display(),
+            c.entry_modified,
+            c.existing_modified
+        );
+        line.clear();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return Decision::Skip;
+        }
+        match line.trim() {
+            "o" => return Decision::Overwrite,
+            "s" => return Decision::Skip,
+            "r" => return Decision::Rename,
+            _ => {}
+        }
+    }
 }
 
 // The wrap time of Windows explorer is 2:23
@@ -51,302 +192,28 @@ async fn init() {
     }
 }
 
-trait Unzip {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()>;
-}
-
-async fn test<U: Unzip>() -> Vec<String> {
-    let name = std::any::type_name::<U>();
+async fn test(backend: &dyn Extractor) -> Vec<String> {
+    let name = backend.name();
     let Ok(odir) = tempdir() else {
         eprintln!("Fail to create temp directory");
         exit(1)
     };
     println!("[LOG] Test {}", name);
     let instant = Instant::now();
-    if let Err(e) = U::unzip(TEST_ZIP_PATH, &odir).await {
-        println!("[ERR] Fail to test {}: {}", name, e);
-        return vec![];
-    }
+    let opts = ExtractOptions::default();
+    let report = match backend
+        .unzip(Path::new(TEST_ZIP_PATH), odir.path(), &opts)
+        .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            println!("[ERR] Fail to test {}: {}", name, e);
+            return vec![];
+        }
+    };
     let time = instant.elapsed();
     println!("[LOG]   Result: {:?}", time);
+    println!("[LOG]   Report: {}", report);
     let path: &Path = odir.as_ref();
-    if let Ok(x) = find_and_sort(path.into()) {
-        x
-    } else {
-        vec![]
-    }
-}
-
-/// 指定されたディレクトリを再帰的に検索し、見つかったファイルパスをソートして返す
-///
-/// # 引数
-/// * `dir` - 検索を開始するディレクトリのパス
-///
-/// # 戻り値
-/// * `Result<Vec<String>, Box<dyn Error>>` - 成功した場合はソートされたファイルパスのリスト、失敗した場合はエラー
-pub fn find_and_sort(root_path: PathBuf) -> Result<Vec<String>> {
-    let mut result = Vec::new();
-    collect_files(&root_path, &root_path, &mut result)?;
-    result.sort();
-    Ok(result)
-}
-
-/// 再帰的にファイルを収集する補助関数
-fn collect_files(
-    root: &Path,
-    current: &Path,
-    result: &mut Vec<String>
-) -> Result<()> {
-    if current.is_dir() {
-        for entry in std::fs::read_dir(current)? {
-            let entry = entry?;
-            let path = entry.path();
-            collect_files(root, &path, result)?;
-        }
-    } else if current.is_file() {
-        if let Ok(relative) = current.strip_prefix(root) {
-            if let Some(path_str) = relative.to_str() {
-                if !path_str.is_empty() {
-                    result.push(path_str.to_string());
-                }
-            }
-        }
-    }
-    Ok(())
-}
-
-
-
-fn is_safe_path<P: AsRef<Path>>(path: P) -> bool {
-    let path = path.as_ref();
-    if path.to_str().is_none() || path.to_string_lossy().contains('\0') {
-        return false;
-    }
-
-    let mut components = Vec::new();
-    for component in path.components() {
-        match component {
-            std::path::Component::ParentDir => {
-                if components.is_empty() {
-                    return false;
-                }
-                components.pop();
-            }
-            std::path::Component::Normal(_) => components.push(component),
-            std::path::Component::CurDir => {}
-            _ => return false,
-        }
-    }
-    true
-}
-
-///
-/// zip_extra
-///
-struct ZipExtra {}
-impl Unzip for ZipExtra {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()> {
-        use std::fs::File;
-        use std::io::BufReader;
-
-        let reader = BufReader::new(File::open(src)?);
-        zip_extract::extract(reader, dir.as_ref(), false)?;
-
-        Ok(())
-    }
-}
-
-///
-/// ripunzip
-///
-struct Ripunzip {}
-impl Unzip for Ripunzip {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()> {
-        use std::fs::File;
-
-        let file = File::open(src)?;
-        let zip = ripunzip::UnzipEngine::for_file(file)?;
-        zip.unzip(UnzipOptions {
-            output_directory: Some(dir.as_ref().into()),
-            password: None,
-            single_threaded: false,
-            filename_filter: None,
-            progress_reporter: Box::new(ripunzip::NullProgressReporter {}),
-        })?;
-
-        Ok(())
-    }
-}
-
-struct ParallelZip {}
-impl Unzip for ParallelZip {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()> {
-        use std::fs::File;
-        use std::io::BufReader;
-
-        let len = {
-            let reader = BufReader::new(File::open(&src)?);
-
-            zip::ZipArchive::new(reader)?.len()
-        };
-        let task = async |from: usize, end: usize, src: PathBuf, base: PathBuf| -> Result<()> {
-            let reader = BufReader::new(File::open(src)?);
-            let mut zip = zip::ZipArchive::new(reader)?;
-            for i in from..end {
-                let mut file = zip.by_index(i)?;
-                let path = file.mangled_name();
-                if path.to_string_lossy().is_empty() {
-                    continue;
-                }
-                if !is_safe_path(&path) {
-                    continue;
-                }
-                let path = base.join(path);
-
-                if file.name().ends_with('/') {
-                    std::fs::create_dir_all(path)?;
-                } else if let Some(parent) = path.parent() {
-                    if !parent.is_dir() {
-                        std::fs::create_dir_all(parent)?;
-                    }
-                    let mut out = std::fs::File::create(&path)?;
-                    std::io::copy(&mut file, &mut out)?;
-                }
-            }
-            Ok(())
-        };
-
-        let cores = num_cpus::get() / 2;
-        println!("[LOG]  cores = {}", cores);
-        let joins: Vec<_> = (0..cores)
-            .into_iter()
-            .map(|i| {
-                let from = len * i / cores;
-                let end = len * (i + 1) / cores;
-                tokio::task::spawn(task(from, end, src.as_ref().into(), dir.as_ref().into()))
-            })
-            .collect();
-        let mut errmsg = String::new();
-        for j in joins {
-            match j.await {
-                Ok(Ok(())) => {}
-                Ok(Err(e)) => {
-                    errmsg.push_str(&format!("{}\n", e));
-                }
-                Err(e) => {
-                    errmsg.push_str(&format!("{}\n", e));
-                }
-            }
-        }
-        if !errmsg.is_empty() {
-            Err(anyhow!("{}", errmsg))
-        } else {
-            Ok(())
-        }
-    }
-}
-
-///
-/// async_zip
-///
-struct AsyncZip {}
-impl Unzip for AsyncZip {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()> {
-        use async_zip::tokio::read::seek::ZipFileReader;
-        use tokio::fs::{create_dir_all, File};
-        use tokio::io::BufReader;
-        use tokio_util::compat::FuturesAsyncReadCompatExt;
-
-        let mut zip = ZipFileReader::with_tokio(BufReader::new(File::open(src).await?)).await?;
-        let base = dir.as_ref();
-        let len = zip.file().entries().len();
-        for i in 0..len {
-            let e = zip.file().entries().get(i).unwrap();
-            let path = Path::new(e.filename().as_str()?);
-            if !is_safe_path(&path) {
-                continue;
-            }
-
-            let path = base.join(path);
-
-            if e.dir()? {
-                create_dir_all(path).await?;
-            } else {
-                let mut reader = zip.reader_without_entry(i).await?.compat();
-                let mut file = File::create(path).await?;
-                tokio::io::copy(&mut reader, &mut file).await?;
-            }
-        }
-        Ok(())
-    }
-}
-
-///
-/// async_zip (parallel)
-///
-struct AsyncZipParallel {}
-impl Unzip for AsyncZipParallel {
-    async fn unzip<S: AsRef<Path>, D: AsRef<Path>>(src: S, dir: D) -> Result<()> {
-        use async_zip::tokio::read::seek::ZipFileReader;
-        use tokio::fs::{create_dir_all, File};
-        use tokio::io::BufReader;
-        use tokio_util::compat::FuturesAsyncReadCompatExt;
-
-        let len = {
-            let zip = ZipFileReader::with_tokio(BufReader::new(File::open(&src).await?)).await?;
-            zip.file().entries().len()
-        };
-        let task = async |from: usize, end: usize, src: PathBuf, base: PathBuf| -> Result<()> {
-            let mut zip = ZipFileReader::with_tokio(BufReader::new(File::open(src).await?)).await?;
-            for i in from..end {
-                let e = zip.file().entries().get(i).unwrap();
-                let path = Path::new(e.filename().as_str()?);
-                if !is_safe_path(&path) {
-                    continue;
-                }
-
-                let path = base.join(path);
-
-                if e.dir()? {
-                    create_dir_all(path).await?;
-                } else if let Some(parent) = path.parent() {
-                    if !parent.is_dir() {
-                        create_dir_all(parent).await?;
-                    }
-                    let mut reader = zip.reader_without_entry(i).await?.compat();
-                    let mut file = File::create(path).await?;
-                    tokio::io::copy(&mut reader, &mut file).await?;
-                }
-            }
-            Ok(())
-        };
-
-        let cores = num_cpus::get();
-        println!("[LOG]  cores = {}", cores);
-        let joins: Vec<_> = (0..cores)
-            .into_iter()
-            .map(|i| {
-                let from = len * i / cores;
-                let end = len * (i + 1) / cores;
-                tokio::task::spawn(task(from, end, src.as_ref().into(), dir.as_ref().into()))
-            })
-            .collect();
-        let mut errmsg = String::new();
-        for j in joins {
-            match j.await {
-                Ok(Ok(())) => {}
-                Ok(Err(e)) => {
-                    errmsg.push_str(&format!("{}\n", e));
-                }
-                Err(e) => {
-                    errmsg.push_str(&format!("{}\n", e));
-                }
-            }
-        }
-        if !errmsg.is_empty() {
-            Err(anyhow!("{}", errmsg))
-        } else {
-            Ok(())
-        }
-    }
+    find_and_sort(path.into()).unwrap_or_default()
 }
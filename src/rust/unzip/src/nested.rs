@@ -0,0 +1,387 @@
+//! アーカイブ内のアーカイブ (zip, tar, tar.gz) の再帰展開
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::SyncIoBridge;
+
+use crate::{
+    is_safe_path,
+    overwrite::{from_zip_datetime, Overwrite},
+    report::Report,
+    ExtractOptions,
+};
+
+/// 内側のアーカイブの展開先
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NestedTarget {
+    /// `foo.zip` を `foo/` に展開する
+    #[default]
+    Directory,
+    /// `foo.zip` があった場所にそのまま展開する
+    InPlace,
+}
+
+/// 再帰展開のオプション
+#[derive(Debug, Clone)]
+pub struct Nested {
+    pub target: NestedTarget,
+    /// 展開する深さ。1 なら外側のアーカイブに含まれるアーカイブまで
+    pub max_depth: usize,
+    /// 全階層で展開するデータの合計サイズの上限
+    ///
+    /// 外側のアーカイブも含めて書き出しながら数える。出力先を制御できないライブラリでは、
+    /// 展開前に全エントリを読んでサイズが宣言どおりであることを確かめ、宣言されたサイズで数える。
+    pub max_total_size: u64,
+}
+
+impl Default for Nested {
+    fn default() -> Self {
+        Self {
+            target: NestedTarget::Directory,
+            max_depth: 4,
+            max_total_size: 16 << 30,
+        }
+    }
+}
+
+/// 内側のアーカイブの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Kind {
+    /// ファイル名から形式と拡張子を除いた名前を求める
+    pub fn detect(name: &str) -> Option<(Kind, &str)> {
+        let lower = name.to_ascii_lowercase();
+        [
+            (".tar.gz", Kind::TarGz),
+            (".tgz", Kind::TarGz),
+            (".tar", Kind::Tar),
+            (".zip", Kind::Zip),
+        ]
+        .into_iter()
+        .find(|(ext, _)| lower.len() > ext.len() && lower.ends_with(ext))
+        .map(|(ext, kind)| (kind, &name[..name.len() - ext.len()]))
+    }
+}
+
+/// 1 回の展開で共有する再帰展開の状態
+///
+/// 再帰展開しない場合でも、展開したサイズの合計は数える。
+#[derive(Debug, Clone)]
+pub struct Expander {
+    nested: Option<Nested>,
+    overwrite: Overwrite,
+    remaining: Arc<AtomicU64>,
+}
+
+impl Expander {
+    pub fn new(opts: &ExtractOptions) -> Self {
+        let limit = opts.nested.as_ref().map_or(u64::MAX, |n| n.max_total_size);
+        Self {
+            nested: opts.nested.clone(),
+            overwrite: opts.overwrite.clone(),
+            remaining: Arc::new(AtomicU64::new(limit)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.nested.as_ref().is_some_and(|n| n.max_depth > 0)
+    }
+
+    /// 展開先 `path` のファイルが深さ `depth` で展開するアーカイブなら、その形式と展開先を返す
+    pub fn target(&self, path: &Path, depth: usize) -> Option<(Kind, PathBuf)> {
+        let nested = self.nested.as_ref()?;
+        if depth > nested.max_depth {
+            return None;
+        }
+        let (kind, stem) = Kind::detect(path.file_name()?.to_str()?)?;
+        let parent = path.parent()?;
+        let dir = match nested.target {
+            NestedTarget::Directory => parent.join(stem),
+            NestedTarget::InPlace => parent.into(),
+        };
+        Some((kind, dir))
+    }
+
+    /// `n` バイトを展開したことを記録する。上限を超えたらエラー
+    pub fn charge(&self, n: u64) -> io::Result<()> {
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(n))
+            .map(|_| ())
+            .map_err(|_| io::Error::other("archive exceeds the total size limit"))
+    }
+
+    /// `charge` で数えた `n` バイトを戻す
+    fn release(&self, n: u64) {
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| {
+                Some(r.saturating_add(n))
+            });
+    }
+
+    /// 読んだ分だけ上限を消費するリーダーを作る
+    pub fn limit<R: Read>(&self, inner: R) -> Limited<'_, R> {
+        Limited { inner, ex: self }
+    }
+
+    /// 上限を確認しながら非同期にコピーする
+    ///
+    /// 読んだ分を書き込む前に数えるので、上限を超える分は書き込まない。
+    async fn copy_async<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = vec![0; 64 << 10];
+        let mut total = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                writer.flush().await?;
+                return Ok(total);
+            }
+            self.charge(n as u64)?;
+            writer.write_all(&buf[..n]).await?;
+            total += n as u64;
+        }
+    }
+
+    /// 内側のアーカイブを非同期に読みながら展開する
+    ///
+    /// tar はシークが要らないため、読んだデータをそのまま別スレッドの展開処理に渡す。
+    /// zip はシークが必要なため一時ファイルに書き出してから展開する。
+    async fn expand_async<R: AsyncRead + Unpin>(
+        &self,
+        kind: Kind,
+        reader: &mut R,
+        dir: &Path,
+        depth: usize,
+        report: &mut Report,
+    ) -> Result<()> {
+        if kind == Kind::Zip {
+            std::fs::create_dir_all(dir)?;
+            let mut tmp = tokio::fs::File::from_std(tempfile::tempfile()?);
+            self.copy_async(reader, &mut tmp).await?;
+            let mut tmp = tmp.into_std().await;
+            tmp.rewind()?;
+            return self.expand_zip(BufReader::new(tmp), dir, depth, report);
+        }
+
+        let (mut tx, rx) = tokio::io::duplex(64 << 10);
+        let mut rx = SyncIoBridge::new(rx);
+        let ex = self.clone();
+        let out = dir.to_path_buf();
+        let job = tokio::task::spawn_blocking(move || {
+            let mut report = Report::default();
+            ex.expand(kind, &mut rx, &out, depth, &mut report)
+                .map(|_| report)
+        });
+        let mut buf = vec![0; 64 << 10];
+        let fed: io::Result<()> = async {
+            loop {
+                let n = reader.read(&mut buf).await?;
+                // 展開側が読み終えた、または失敗した場合は書き込めないので送るのをやめる
+                if n == 0 || tx.write_all(&buf[..n]).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        .await;
+        drop(tx);
+        report.merge(job.await??);
+        Ok(fed?)
+    }
+
+    /// 外側のアーカイブのエントリを非同期に書き出す。内側のアーカイブなら展開する
+    pub async fn write_async<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        path: &Path,
+        modified: Option<SystemTime>,
+        report: &mut Report,
+    ) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if let Some((kind, dir)) = self.target(path, 1) {
+            self.expand_async(kind, reader, &dir, 1, report).await?;
+            report.nested.push(path.into());
+            return Ok(());
+        }
+        let mut action = self.overwrite.resolve(path, modified)?;
+        if let Some(file) = action.open()? {
+            let mut file = tokio::fs::File::from_std(file);
+            if let Err(e) = self.copy_async(reader, &mut file).await {
+                drop(file);
+                action.remove_partial();
+                return Err(e);
+            }
+        }
+        report.record(action);
+        Ok(())
+    }
+
+    /// 一時ディレクトリに展開済みのアーカイブファイル `staged` を、展開先 `path` にあったものとして展開する
+    ///
+    /// 出力先を制御できないライブラリ用。`staged` 自体は展開先に置かない。
+    /// `staged` のサイズは展開時に数え直すので、既に `charge` 済みの分を戻しておく。
+    pub fn expand_file(&self, staged: &Path, path: &Path, report: &mut Report) -> Result<()> {
+        let Some((kind, dir)) = self.target(path, 1) else {
+            return Ok(());
+        };
+        let file = File::open(staged)?;
+        self.release(file.metadata()?.len());
+        self.expand(kind, &mut BufReader::new(file), &dir, 1, report)?;
+        report.nested.push(path.into());
+        Ok(())
+    }
+
+    /// 深さ `depth` のアーカイブを `dir` に展開する
+    ///
+    /// tar はストリームのまま、zip はシークが必要なため一時ファイルに書き出してから展開する。
+    /// `reader` から読んだアーカイブ自体のサイズも上限に数える。
+    pub fn expand(
+        &self,
+        kind: Kind,
+        reader: &mut dyn Read,
+        dir: &Path,
+        depth: usize,
+        report: &mut Report,
+    ) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        match kind {
+            Kind::Zip => {
+                let mut tmp = tempfile::tempfile()?;
+                io::copy(&mut self.limit(reader), &mut tmp)?;
+                tmp.rewind()?;
+                self.expand_zip(BufReader::new(tmp), dir, depth, report)
+            }
+            Kind::Tar => self.expand_tar(&mut self.limit(reader), dir, depth, report),
+            Kind::TarGz => {
+                let mut gz = flate2::read::GzDecoder::new(self.limit(reader));
+                self.expand_tar(&mut gz, dir, depth, report)
+            }
+        }
+    }
+
+    fn expand_zip<R: Read + Seek>(
+        &self,
+        reader: R,
+        dir: &Path,
+        depth: usize,
+        report: &mut Report,
+    ) -> Result<()> {
+        let mut zip = zip::ZipArchive::new(reader)?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            let Some(name) = file.enclosed_name() else {
+                continue;
+            };
+            if !is_safe_path(&name) {
+                continue;
+            }
+            let path = dir.join(name);
+            if file.is_dir() {
+                std::fs::create_dir_all(path)?;
+                continue;
+            }
+            let modified = file.last_modified().and_then(from_zip_datetime);
+            self.write(&mut file, &path, modified, depth, report)?;
+        }
+        Ok(())
+    }
+
+    fn expand_tar(
+        &self,
+        reader: &mut dyn Read,
+        dir: &Path,
+        depth: usize,
+        report: &mut Report,
+    ) -> Result<()> {
+        let mut tar = tar::Archive::new(reader);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.into_owned();
+            if name.as_os_str().is_empty() || !is_safe_path(&name) {
+                continue;
+            }
+            let path = dir.join(name);
+            let kind = entry.header().entry_type();
+            if kind.is_dir() {
+                std::fs::create_dir_all(path)?;
+                continue;
+            }
+            if !kind.is_file() {
+                continue;
+            }
+            let modified = entry
+                .header()
+                .mtime()
+                .ok()
+                .map(|t| UNIX_EPOCH + Duration::from_secs(t));
+            self.write(&mut entry, &path, modified, depth, report)?;
+        }
+        Ok(())
+    }
+
+    /// 深さ `depth` のアーカイブに含まれるファイルを書き出す。アーカイブなら更に展開する
+    ///
+    /// 外側のアーカイブのエントリは `depth` を 0 として呼ぶ。
+    pub fn write(
+        &self,
+        reader: &mut dyn Read,
+        path: &Path,
+        modified: Option<SystemTime>,
+        depth: usize,
+        report: &mut Report,
+    ) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if let Some((kind, dir)) = self.target(path, depth + 1) {
+            self.expand(kind, reader, &dir, depth + 1, report)?;
+            report.nested.push(path.into());
+            return Ok(());
+        }
+        let mut action = self.overwrite.resolve(path, modified)?;
+        if let Some(mut out) = action.open()? {
+            if let Err(e) = io::copy(&mut self.limit(reader), &mut out) {
+                drop(out);
+                action.remove_partial();
+                return Err(e.into());
+            }
+        }
+        report.record(action);
+        Ok(())
+    }
+}
+
+/// 読んだバイト数を `Expander` の上限から差し引くリーダー
+pub struct Limited<'a, R> {
+    inner: R,
+    ex: &'a Expander,
+}
+
+impl<R: Read> Read for Limited<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.ex.charge(n as u64)?;
+        Ok(n)
+    }
+}
@@ -0,0 +1,235 @@
+//! 展開先に同名のファイルが既に存在する場合の上書きポリシー
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+
+/// 衝突時にコールバックへ渡される情報
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    /// 展開しようとしているパス
+    pub path: &'a Path,
+    /// アーカイブ内のエントリの更新日時
+    pub entry_modified: Option<SystemTime>,
+    /// 既存ファイルの更新日時
+    pub existing_modified: Option<SystemTime>,
+}
+
+/// 衝突の解決方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Overwrite,
+    Skip,
+    Rename,
+}
+
+/// `Overwrite::Prompt` で呼ばれるコールバック
+pub type PromptCallback = Arc<dyn Fn(&Conflict) -> Decision + Send + Sync>;
+
+/// 既存ファイルに対する上書きポリシー
+#[derive(Clone, Default)]
+pub enum Overwrite {
+    /// 既存ファイルは残し、エントリを展開しない
+    Never,
+    /// 常に上書きする
+    #[default]
+    Always,
+    /// エントリの更新日時が既存ファイルより新しい場合のみ上書きする
+    IfNewer,
+    /// `name (1).ext` のように名前を変えて展開する
+    Rename,
+    /// コールバックで衝突ごとに決める
+    Prompt(PromptCallback),
+}
+
+impl std::fmt::Debug for Overwrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Overwrite::Never => f.write_str("never"),
+            Overwrite::Always => f.write_str("always"),
+            Overwrite::IfNewer => f.write_str("if-newer"),
+            Overwrite::Rename => f.write_str("rename"),
+            Overwrite::Prompt(_) => f.write_str("prompt-callback"),
+        }
+    }
+}
+
+impl FromStr for Overwrite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "never" => Ok(Overwrite::Never),
+            "always" => Ok(Overwrite::Always),
+            "if-newer" => Ok(Overwrite::IfNewer),
+            "rename" => Ok(Overwrite::Rename),
+            "prompt-callback" => Err(anyhow!("prompt-callback requires a callback")),
+            _ => Err(anyhow!("unknown overwrite policy: {}", s)),
+        }
+    }
+}
+
+/// 1 エントリに対して行う処理
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// 新規作成
+    Create(PathBuf),
+    /// 既存ファイルを上書き
+    Overwrite(PathBuf),
+    /// 別名で作成 (元のパス, 新しいパス)
+    Rename(PathBuf, PathBuf),
+    /// 展開しない
+    Skip(PathBuf),
+}
+
+impl Overwrite {
+    pub fn is_always(&self) -> bool {
+        matches!(self, Overwrite::Always)
+    }
+
+    /// `path` に展開する時の処理を決める
+    pub fn resolve(&self, path: &Path, entry_modified: Option<SystemTime>) -> io::Result<Action> {
+        let meta = match std::fs::symlink_metadata(path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Action::Create(path.into()));
+            }
+            Err(e) => return Err(e),
+        };
+        let existing_modified = meta.modified().ok();
+        let decision = match self {
+            Overwrite::Never => Decision::Skip,
+            Overwrite::Always => Decision::Overwrite,
+            Overwrite::IfNewer => match (entry_modified, existing_modified) {
+                (Some(e), Some(x)) if e > x => Decision::Overwrite,
+                _ => Decision::Skip,
+            },
+            Overwrite::Rename => Decision::Rename,
+            Overwrite::Prompt(f) => f(&Conflict {
+                path,
+                entry_modified,
+                existing_modified,
+            }),
+        };
+        Ok(match decision {
+            Decision::Overwrite => Action::Overwrite(path.into()),
+            Decision::Skip => Action::Skip(path.into()),
+            Decision::Rename => Action::Rename(path.into(), free_name(path)),
+        })
+    }
+}
+
+impl Action {
+    /// 決定に従って書き込み先のファイルを開く。`Skip` の場合は `None`
+    ///
+    /// `Rename` は並列に展開していても同じ名前を取り合わないよう、
+    /// `create_new` で名前を確保し、取れなければ次の名前を試す。
+    pub fn open(&mut self) -> io::Result<Option<File>> {
+        match self {
+            Action::Create(p) | Action::Overwrite(p) => Ok(Some(File::create(p)?)),
+            Action::Rename(from, to) => loop {
+                match OpenOptions::new().write(true).create_new(true).open(&*to) {
+                    Ok(f) => return Ok(Some(f)),
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => *to = free_name(from),
+                    Err(e) => return Err(e),
+                }
+            },
+            Action::Skip(_) => Ok(None),
+        }
+    }
+
+    /// 書き込み先のパス。`Skip` の場合は `None`
+    pub fn written(&self) -> Option<&Path> {
+        match self {
+            Action::Create(p) | Action::Overwrite(p) | Action::Rename(_, p) => Some(p),
+            Action::Skip(_) => None,
+        }
+    }
+
+    /// 一時ディレクトリに展開済みのファイル `staged` を決定に従って配置する
+    pub fn place(&mut self, staged: &Path) -> io::Result<()> {
+        if self.open()?.is_some() {
+            std::fs::rename(staged, self.written().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// 書き込みに失敗した時に、途中まで書いたファイルを消す
+    pub fn remove_partial(&self) {
+        if let Some(path) = self.written() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// `name.ext` に対して、存在しない `name (n).ext` を返す
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|s| format!(".{}", s.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|p| std::fs::symlink_metadata(p).is_err())
+        .unwrap()
+}
+
+/// ZIP の日時 (タイムゾーン無し) を UTC とみなして `SystemTime` に変換する
+pub fn zip_time(
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Option<SystemTime> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year } as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    let secs = u64::try_from(secs).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// zip クレートの `DateTime` を `SystemTime` に変換する
+pub fn from_zip_datetime(t: zip::DateTime) -> Option<SystemTime> {
+    zip_time(
+        t.year() as i32,
+        t.month() as u32,
+        t.day() as u32,
+        t.hour() as u32,
+        t.minute() as u32,
+        t.second() as u32,
+    )
+}
+
+/// async_zip クレートの `ZipDateTime` を `SystemTime` に変換する
+pub fn from_async_zip_datetime(t: &async_zip::ZipDateTime) -> Option<SystemTime> {
+    zip_time(
+        t.year(),
+        t.month(),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+    )
+}
@@ -0,0 +1,181 @@
+//! 実行時に名前で選べるバックエンドの一覧
+
+use std::{future::Future, marker::PhantomData, path::Path, pin::Pin};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    report::Report,
+    verify::{Manifest, VerifyReport},
+    AsyncZip, AsyncZipParallel, ExtractOptions, ParallelZip, Ripunzip, Unzip, ZipExtra,
+};
+
+/// バックエンドが対応している機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// 複数のスレッドで並列に展開する
+    pub parallel: bool,
+    /// 内側のアーカイブをディスクに書き出さずに展開できる
+    pub streaming: bool,
+    /// パスワード付きの zip を展開できる
+    pub encryption: bool,
+    /// パーミッションなどのメタデータを復元する
+    pub metadata: bool,
+}
+
+impl Capabilities {
+    /// `other` で有効な機能を全て持っているか
+    pub fn contains(&self, other: &Capabilities) -> bool {
+        (self.parallel || !other.parallel)
+            && (self.streaming || !other.streaming)
+            && (self.encryption || !other.encryption)
+            && (self.metadata || !other.metadata)
+    }
+}
+
+impl std::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.parallel, "parallel"),
+            (self.streaming, "streaming"),
+            (self.encryption, "encryption"),
+            (self.metadata, "metadata"),
+        ];
+        let names: Vec<_> = flags.iter().filter(|f| f.0).map(|f| f.1).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+/// `tokio::spawn` できるよう `Send` にした `Future`
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// `dyn` で扱える展開処理
+///
+/// `Unzip` はジェネリックな関連関数なので `Box<dyn Unzip>` にできない。
+/// `Backend` で包むと `Unzip` の実装をこのトレイトとして使える。
+pub trait Extractor: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    fn unzip<'a>(
+        &'a self,
+        src: &'a Path,
+        dir: &'a Path,
+        opts: &'a ExtractOptions,
+    ) -> BoxFuture<'a, Result<Report>>;
+
+    fn verify<'a>(
+        &'a self,
+        src: &'a Path,
+        manifest: Option<&'a Manifest>,
+    ) -> BoxFuture<'a, Result<VerifyReport>>;
+}
+
+/// `Unzip` を `Extractor` として扱うためのラッパー
+pub struct Backend<U>(PhantomData<fn() -> U>);
+
+impl<U: Unzip> Backend<U> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<U: Unzip> Default for Backend<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<U: Unzip> Extractor for Backend<U> {
+    fn name(&self) -> &'static str {
+        U::NAME
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        U::CAPABILITIES
+    }
+
+    fn unzip<'a>(
+        &'a self,
+        src: &'a Path,
+        dir: &'a Path,
+        opts: &'a ExtractOptions,
+    ) -> BoxFuture<'a, Result<Report>> {
+        Box::pin(async move {
+            if opts.password.is_some() && !U::CAPABILITIES.encryption {
+                return Err(anyhow!("{} does not support encrypted archives", U::NAME));
+            }
+            U::unzip(src, dir, opts).await
+        })
+    }
+
+    fn verify<'a>(
+        &'a self,
+        src: &'a Path,
+        manifest: Option<&'a Manifest>,
+    ) -> BoxFuture<'a, Result<VerifyReport>> {
+        Box::pin(U::verify(src, manifest))
+    }
+}
+
+/// 名前でバックエンドを引ける一覧。登録順を保つ
+#[derive(Default)]
+pub struct Registry {
+    backends: Vec<Box<dyn Extractor>>,
+}
+
+impl Registry {
+    /// 空の一覧
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// このクレートの全てのバックエンドを登録した一覧
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register_unzip::<ZipExtra>();
+        registry.register_unzip::<Ripunzip>();
+        registry.register_unzip::<ParallelZip>();
+        registry.register_unzip::<AsyncZip>();
+        registry.register_unzip::<AsyncZipParallel>();
+        registry
+    }
+
+    /// バックエンドを登録する。同じ名前があれば置き換える
+    pub fn register(&mut self, backend: Box<dyn Extractor>) {
+        match self
+            .backends
+            .iter_mut()
+            .find(|b| b.name() == backend.name())
+        {
+            Some(b) => *b = backend,
+            None => self.backends.push(backend),
+        }
+    }
+
+    pub fn register_unzip<U: Unzip + 'static>(&mut self) {
+        self.register(Box::new(Backend::<U>::new()));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Extractor> {
+        self.iter().find(|b| b.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Extractor> {
+        self.backends.iter().map(|b| b.as_ref())
+    }
+
+    /// `required` の機能を全て持つバックエンド
+    pub fn with_capabilities(
+        &self,
+        required: Capabilities,
+    ) -> impl Iterator<Item = &dyn Extractor> {
+        self.iter()
+            .filter(move |b| b.capabilities().contains(&required))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.iter().map(|b| b.name()).collect()
+    }
+}
@@ -0,0 +1,54 @@
+//! 展開結果のレポート
+
+use std::path::PathBuf;
+
+use crate::overwrite::Action;
+
+/// 展開したファイルの一覧
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// 新規に作成したファイル
+    pub created: Vec<PathBuf>,
+    /// 上書きしたファイル
+    pub overwritten: Vec<PathBuf>,
+    /// 名前を変えて作成したファイル (元のパス, 作成したパス)
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// 既存ファイルがあるため展開しなかったファイル
+    pub skipped: Vec<PathBuf>,
+    /// 再帰的に展開した内側のアーカイブ
+    pub nested: Vec<PathBuf>,
+}
+
+impl Report {
+    pub fn record(&mut self, action: Action) {
+        match action {
+            Action::Create(p) => self.created.push(p),
+            Action::Overwrite(p) => self.overwritten.push(p),
+            Action::Rename(from, to) => self.renamed.push((from, to)),
+            Action::Skip(p) => self.skipped.push(p),
+        }
+    }
+
+    /// 並列に展開した結果をまとめる
+    pub fn merge(&mut self, other: Report) {
+        self.created.extend(other.created);
+        self.overwritten.extend(other.overwritten);
+        self.renamed.extend(other.renamed);
+        self.skipped.extend(other.skipped);
+        self.nested.extend(other.nested);
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "created: {}, overwritten: {}, renamed: {}, skipped: {}, nested: {}",
+            self.created.len(),
+            self.overwritten.len(),
+            self.renamed.len(),
+            self.skipped.len(),
+            self.nested.len()
+        )
+    }
+}
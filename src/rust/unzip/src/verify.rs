@@ -0,0 +1,351 @@
+//! 展開せずにアーカイブを検査する (`unzip -t` 相当)
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+/// `sha256sum` 形式のマニフェスト (ファイル名 → 小文字の 16 進ハッシュ)
+#[derive(Debug, Clone, Default)]
+pub struct Manifest(Arc<HashMap<String, String>>);
+
+impl Manifest {
+    /// `<hash>  <name>` または `<hash> *<name>` の行を読み込む
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut map = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // ハッシュの後の空白と、テキストなら ` `、バイナリなら `*` の 1 文字だけが区切り。
+            // 名前の先頭や末尾の空白、`*` は名前の一部
+            let Some((hash, name)) = line
+                .split_once(' ')
+                .and_then(|(hash, rest)| Some((hash, rest.strip_prefix([' ', '*'])?)))
+                .filter(|(_, name)| !name.is_empty())
+            else {
+                return Err(anyhow!("invalid manifest line {}: {}", n + 1, line));
+            };
+            if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(anyhow!("invalid SHA-256 at line {}: {}", n + 1, hash));
+            }
+            map.insert(name.to_string(), hash.to_ascii_lowercase());
+        }
+        Ok(Self(Arc::new(map)))
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// 検査で見つかった問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// 展開中のエラー
+    Read(String),
+    /// CRC-32 が一致しない
+    Crc { expected: u32, actual: u32 },
+    /// 展開後のサイズがセントラルディレクトリの値と一致しない
+    Size { expected: u64, actual: u64 },
+    /// ローカルヘッダが読めない、またはセントラルディレクトリと一致しない
+    Header(String),
+    /// マニフェストの SHA-256 と一致しない
+    Sha256 { expected: String, actual: String },
+    /// マニフェストにあるがアーカイブに無い
+    NotInArchive,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Read(e) => write!(f, "read error: {}", e),
+            Problem::Crc { expected, actual } => {
+                write!(f, "CRC-32 {:08x} != {:08x}", actual, expected)
+            }
+            Problem::Size { expected, actual } => write!(f, "size {} != {}", actual, expected),
+            Problem::Header(e) => write!(f, "local header: {}", e),
+            Problem::Sha256 { expected, actual } => write!(f, "SHA-256 {} != {}", actual, expected),
+            Problem::NotInArchive => f.write_str("not in archive"),
+        }
+    }
+}
+
+/// 1 エントリの検査結果。`problems` が空なら合格
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryCheck {
+    pub name: String,
+    pub problems: Vec<Problem>,
+}
+
+impl EntryCheck {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// 全エントリの検査結果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub entries: Vec<EntryCheck>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.entries.iter().all(EntryCheck::is_ok)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &EntryCheck> {
+        self.entries.iter().filter(|e| !e.is_ok())
+    }
+
+    /// マニフェストにあってアーカイブに無いファイルを追加する
+    pub fn finish(&mut self, manifest: Option<&Manifest>) {
+        let Some(manifest) = manifest else {
+            return;
+        };
+        let seen: HashSet<_> = self.entries.iter().map(|e| e.name.as_str()).collect();
+        let mut missing: Vec<_> = manifest
+            .0
+            .keys()
+            .filter(|name| !seen.contains(name.as_str()))
+            .cloned()
+            .collect();
+        missing.sort();
+        self.entries
+            .extend(missing.into_iter().map(|name| EntryCheck {
+                name,
+                problems: vec![Problem::NotInArchive],
+            }));
+    }
+}
+
+impl std::fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for e in &self.entries {
+            if e.is_ok() {
+                writeln!(f, "OK   {}", e.name)?;
+            } else {
+                let problems: Vec<_> = e.problems.iter().map(|p| p.to_string()).collect();
+                writeln!(f, "FAIL {}: {}", e.name, problems.join(", "))?;
+            }
+        }
+        let failed = self.failed().count();
+        write!(f, "{} entries, {} failed", self.entries.len(), failed)
+    }
+}
+
+/// セントラルディレクトリに記録されているエントリの情報
+#[derive(Debug, Clone)]
+pub struct Declared {
+    pub name: Vec<u8>,
+    pub method: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// ローカルヘッダの位置
+    pub header_offset: u64,
+}
+
+/// ローカルヘッダを読んでセントラルディレクトリと比較する
+pub fn check_local_header<R: Read + Seek>(r: &mut R, declared: &Declared) -> Vec<Problem> {
+    let mut buf = [0u8; 30];
+    let read = r
+        .seek(SeekFrom::Start(declared.header_offset))
+        .and_then(|_| r.read_exact(&mut buf));
+    if let Err(e) = read {
+        return vec![Problem::Header(e.to_string())];
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    if u32_at(0) != 0x04034b50 {
+        return vec![Problem::Header("bad signature".into())];
+    }
+    let mut name = vec![0u8; u16_at(26) as usize];
+    if let Err(e) = r.read_exact(&mut name) {
+        return vec![Problem::Header(e.to_string())];
+    }
+
+    let mut problems = Vec::new();
+    let mut mismatch = |what: &str| problems.push(Problem::Header(format!("{} differs", what)));
+    if name != declared.name {
+        mismatch("file name");
+    }
+    if u16_at(8) != declared.method {
+        mismatch("compression method");
+    }
+    // データディスクリプタを使う場合、ローカルヘッダの CRC とサイズは 0
+    if u16_at(6) & 0x08 == 0 {
+        if u32_at(14) != declared.crc32 {
+            mismatch("CRC-32");
+        }
+        let sizes = [
+            (u32_at(18), declared.compressed_size, "compressed size"),
+            (u32_at(22), declared.uncompressed_size, "uncompressed size"),
+        ];
+        for (local, central, what) in sizes {
+            // 0xFFFFFFFF は ZIP64 拡張フィールドを参照する
+            if local != u32::MAX && local as u64 != central {
+                mismatch(what);
+            }
+        }
+    }
+    problems
+}
+
+/// 展開したデータを捨てながら CRC-32, サイズ, SHA-256 を計算する
+pub struct NullSink {
+    crc: crc32fast::Hasher,
+    sha: Option<Sha256>,
+    size: u64,
+}
+
+impl NullSink {
+    pub fn new(sha256: bool) -> Self {
+        Self {
+            crc: crc32fast::Hasher::new(),
+            sha: sha256.then(Sha256::new),
+            size: 0,
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        self.crc.update(buf);
+        if let Some(sha) = &mut self.sha {
+            sha.update(buf);
+        }
+        self.size += buf.len() as u64;
+    }
+
+    /// 読み込み結果とヘッダの検査結果をまとめて 1 エントリの結果にする
+    pub fn finish(
+        self,
+        declared: &Declared,
+        read: io::Result<u64>,
+        mut problems: Vec<Problem>,
+        manifest: Option<&Manifest>,
+    ) -> EntryCheck {
+        let name = String::from_utf8_lossy(&declared.name).into_owned();
+        if let Err(e) = read {
+            problems.push(Problem::Read(e.to_string()));
+        }
+        let actual = self.crc.finalize();
+        if actual != declared.crc32 {
+            problems.push(Problem::Crc {
+                expected: declared.crc32,
+                actual,
+            });
+        }
+        if self.size != declared.uncompressed_size {
+            problems.push(Problem::Size {
+                expected: declared.uncompressed_size,
+                actual: self.size,
+            });
+        }
+        if let (Some(expected), Some(sha)) = (manifest.and_then(|m| m.get(&name)), self.sha) {
+            let actual: String = sha
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if actual != expected {
+                problems.push(Problem::Sha256 {
+                    expected: expected.into(),
+                    actual,
+                });
+            }
+        }
+        EntryCheck { name, problems }
+    }
+}
+
+/// マニフェストに載っているエントリ用の `NullSink` を作る
+pub fn sink_for(declared: &Declared, manifest: Option<&Manifest>) -> NullSink {
+    let name = String::from_utf8_lossy(&declared.name);
+    NullSink::new(manifest.is_some_and(|m| m.get(&name).is_some()))
+}
+
+impl Write for NullSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl tokio::io::AsyncWrite for NullSink {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().update(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// zip クレートでアーカイブを検査する
+pub fn verify_zip(src: &Path, manifest: Option<&Manifest>) -> Result<VerifyReport> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(src)?))?;
+    let mut raw = BufReader::new(File::open(src)?);
+    let mut report = VerifyReport::default();
+    for i in 0..zip.len() {
+        let name = zip.name_for_index(i).unwrap_or_default().to_string();
+        let mut file = match zip.by_index(i) {
+            Ok(file) => file,
+            Err(e) => {
+                report.entries.push(EntryCheck {
+                    name,
+                    problems: vec![Problem::Read(e.to_string())],
+                });
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        #[allow(deprecated)]
+        let method = file.compression().to_u16();
+        let declared = Declared {
+            name: file.name_raw().into(),
+            method,
+            crc32: file.crc32(),
+            compressed_size: file.compressed_size(),
+            uncompressed_size: file.size(),
+            header_offset: file.header_start(),
+        };
+        let problems = check_local_header(&mut raw, &declared);
+        let mut sink = sink_for(&declared, manifest);
+        let read = io::copy(&mut file, &mut sink);
+        report
+            .entries
+            .push(sink.finish(&declared, read, problems, manifest));
+    }
+    report.finish(manifest);
+    Ok(report)
+}